@@ -2,33 +2,70 @@
 //!
 //! This module defines the core data structures and logic for managing
 //! hbackup's persistent configuration, including backup jobs, compression formats,
-//! and config file management. It provides serialization/deserialization for TOML and JSON,
-//! and utilities for reading, writing, and migrating configuration files.
+//! and config file management. It provides TOML serialization/deserialization and
+//! utilities for reading, writing, and migrating configuration files; other formats
+//! are handled by `bk export` and `bk import`.
 
 use crate::error::HbackupError;
+use crate::migration::{self, CURRENT_VERSION, UnsupportedVersion};
 use crate::{Result, constants::CONFIG_NAME, sysexits};
-use hbackup::job::{BackupModel, CompressFormat, Job, Level};
+use anyhow::{anyhow, bail};
+use hbackup::job::{Job, JobRef};
+use hbackup::{lock, template};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::{fs, io, process};
 
+/// Configuration file selected with `--config`, `--profile` or `HBACKUP_CONFIG`.
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
 /// The main application configuration.
 /// Stores the version and all backup jobs.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct Application {
     /// Configuration file version.
     pub version: String,
+    /// Files whose jobs are added to this configuration, relative to its directory.
+    /// The file name may contain `*` and `?` wildcards.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
     /// List of backup jobs.
     pub jobs: Vec<Job>,
+    /// Overrides applied on the machine with the given hostname.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub host: BTreeMap<String, HostConfig>,
+    /// Version of the file this configuration was upgraded from, while the upgrade
+    /// has not been written back yet.
+    #[serde(skip)]
+    pub(crate) upgraded_from: Option<String>,
+}
+
+/// A `[host."<hostname>"]` section of the configuration.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct HostConfig {
+    /// Files included only on this host.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Job fields replaced on this host, keyed by job id or name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub jobs: BTreeMap<String, toml::Table>,
+}
+
+/// The content of an included file: jobs and host overrides, but no further includes.
+#[derive(Deserialize)]
+struct Fragment {
+    #[serde(default)]
+    jobs: Vec<Job>,
+    #[serde(default)]
+    host: BTreeMap<String, HostConfig>,
 }
 
 impl Default for Application {
     fn default() -> Self {
-        Self {
-            version: "1.0".to_string(),
-            jobs: vec![],
-        }
+        Self::new()
     }
 }
 
@@ -36,8 +73,11 @@ impl Application {
     /// Creates a new, empty application configuration.
     pub(crate) fn new() -> Self {
         Self {
-            version: "1.0".to_string(),
+            version: CURRENT_VERSION.to_string(),
+            include: vec![],
             jobs: vec![],
+            host: BTreeMap::new(),
+            upgraded_from: None,
         }
     }
 
@@ -52,37 +92,37 @@ impl Application {
         }
     }
 
-    /// Adds a new backup job with a unique id.
+    /// Adds a new backup job with a unique id and returns that id.
     ///
-    /// The id is automatically assigned to avoid conflicts.
-    pub(crate) fn add_job(
-        &mut self,
-        source: PathBuf,
-        target: PathBuf,
-        compression: Option<CompressFormat>,
-        level: Option<Level>,
-        ignore: Option<Vec<String>>,
-        model: Option<BackupModel>,
-    ) -> Result<()> {
+    /// The id is automatically assigned to avoid conflicts, also with the jobs of
+    /// included files.
+    pub(crate) fn push_job(&mut self, mut job: Job) -> Result<u32> {
+        let resolved = self.resolve().unwrap_or_default();
         let id = self
             .jobs
             .iter()
+            .chain(&resolved)
             .map(|job| job.id)
             .max()
             .unwrap_or(0)
             .checked_add(1)
             .ok_or(HbackupError::TooManyJobs(u32::MAX))?;
-        self.jobs.push(Job {
-            id,
-            source,
-            target,
-            compression,
-            level,
-            ignore,
-            model,
-        });
+        job.id = id;
+        self.jobs.push(job);
 
-        Ok(())
+        Ok(id)
+    }
+
+    /// Adds a job, keeping its id unless another job already uses it. Returns the id
+    /// the job was added with.
+    pub(crate) fn insert_job(&mut self, job: Job) -> Result<u32> {
+        let resolved = self.resolve().unwrap_or_default();
+        if job.id == 0 || self.jobs.iter().chain(&resolved).any(|j| j.id == job.id) {
+            return self.push_job(job);
+        }
+        let id = job.id;
+        self.jobs.push(job);
+        Ok(id)
     }
 
     /// Removes all jobs from the configuration.
@@ -96,19 +136,154 @@ impl Application {
         Ok(())
     }
 
-    /// Returns all jobs from the current configuration.
+    /// Returns all jobs from the current configuration, with included files and the
+    /// overrides for this host applied.
+    ///
+    /// If the configuration cannot be resolved, prints an error and exits.
     pub(crate) fn get_jobs() -> Vec<Job> {
-        Self::load_config().jobs
+        Self::load_config().resolve().unwrap_or_else(|e| {
+            eprintln!(
+                "Error resolving config file {}: {e}",
+                config_file().display()
+            );
+            process::exit(sysexits::EX_CONFIG);
+        })
+    }
+
+    /// Returns the effective jobs like [`Application::get_jobs`], but without asking
+    /// to recover a damaged configuration file or exiting; errors are returned.
+    pub(crate) fn try_get_jobs() -> Result<Vec<Job>> {
+        let path = config_file();
+        let app = if path.exists() {
+            let (app, upgraded_from) = load_config_from(&path)?;
+            upgraded(&path, app, upgraded_from)
+        } else {
+            Self::new()
+        };
+        app.resolve()
+    }
+
+    /// Returns the effective jobs: the jobs of this configuration followed by those
+    /// of the included files, with the overrides for this host applied.
+    pub(crate) fn resolve(&self) -> Result<Vec<Job>> {
+        self.resolve_in(&include_dir(), &template::hostname())
+    }
+
+    /// Resolves the configuration with includes relative to `dir` and the overrides
+    /// for `hostname`.
+    fn resolve_in(&self, dir: &Path, hostname: &str) -> Result<Vec<Job>> {
+        let mut jobs = self.jobs.clone();
+        let mut overrides = vec![];
+        let own = self.host.get(hostname);
+        for (file, fragment) in self.fragments(dir, hostname)? {
+            for job in fragment.jobs {
+                if let Some(other) = jobs.iter().find(|j| j.id == job.id) {
+                    bail!(
+                        "job id {} in {} is already used by another job{}",
+                        job.id,
+                        file.display(),
+                        other
+                            .name
+                            .as_ref()
+                            .map(|name| format!(" ({name})"))
+                            .unwrap_or_default()
+                    );
+                }
+                jobs.push(job);
+            }
+            overrides.extend(fragment.host.get(hostname).map(|host| host.jobs.clone()));
+        }
+        // The overrides of this configuration are applied last, so they win.
+        overrides.extend(own.map(|host| host.jobs.clone()));
+
+        for (job_ref, fields) in overrides.iter().flatten() {
+            let r: JobRef = job_ref.parse()?;
+            let Some(job) = jobs.iter_mut().find(|job| r.matches(job)) else {
+                bail!("host {hostname:?} overrides job {job_ref:?}, which does not exist");
+            };
+            *job = apply_override(job, fields)
+                .map_err(|e| anyhow!("invalid override of job {job_ref:?}: {e}"))?;
+        }
+        Ok(jobs)
+    }
+
+    /// Reads the files included on `hostname`, relative to `dir`.
+    fn fragments(&self, dir: &Path, hostname: &str) -> Result<Vec<(PathBuf, Fragment)>> {
+        let own = self.host.get(hostname);
+        let patterns = self
+            .include
+            .iter()
+            .chain(own.into_iter().flat_map(|host| &host.include));
+        let mut fragments = vec![];
+        for pattern in patterns {
+            for file in include_files(dir, pattern)? {
+                let fragment = read_fragment(&file)?;
+                fragments.push((file, fragment));
+            }
+        }
+        Ok(fragments)
+    }
+
+    /// Returns the included file defining the job referenced by `job_ref`, if it is
+    /// not a job of this configuration.
+    fn included_file(&self, job_ref: &JobRef) -> Option<PathBuf> {
+        self.included_file_in(&include_dir(), &template::hostname(), job_ref)
+    }
+
+    fn included_file_in(&self, dir: &Path, hostname: &str, job_ref: &JobRef) -> Option<PathBuf> {
+        // The reference may be a name given by a host override.
+        let resolved = self.resolve_in(dir, hostname).ok()?;
+        let id = resolved.iter().find(|job| job_ref.matches(job))?.id;
+        if self.jobs.iter().any(|job| job.id == id) {
+            return None;
+        }
+        self.fragments(dir, hostname)
+            .ok()?
+            .into_iter()
+            .find(|(_, fragment)| fragment.jobs.iter().any(|job| job.id == id))
+            .map(|(file, _)| file)
     }
 
-    pub(crate) fn list_by_ids(ids: Vec<u32>) -> Vec<Job> {
+    /// Lists backup jobs by their ids or names.
+    pub(crate) fn list_by_refs(refs: Vec<JobRef>) -> Vec<Job> {
         Self::get_jobs()
             .into_iter()
-            .filter(|job| ids.contains(&job.id))
+            .filter(|job| refs.iter().any(|r| r.matches(job)))
             .collect()
     }
 
-    /// Lists backup jobs by their IDs.
+    /// Returns the job of this configuration referenced by id or name.
+    ///
+    /// Fails if the job is defined in an included file, which bk does not change.
+    pub(crate) fn find_job(&self, job_ref: &JobRef) -> Result<Option<&Job>> {
+        if let Some(job) = self.jobs.iter().find(|job| job_ref.matches(job)) {
+            return Ok(Some(job));
+        }
+        if let Some(file) = self.included_file(job_ref) {
+            bail!(
+                "Job {job_ref} is defined in {} and must be changed there, or with a [host] override.",
+                file.display()
+            );
+        }
+        Ok(None)
+    }
+
+    /// Fails if a job other than the one with `id` already uses `name`, also among
+    /// the jobs of included files.
+    pub(crate) fn check_name(&self, name: &str, id: u32) -> Result<()> {
+        let resolved = self.resolve().unwrap_or_default();
+        if self
+            .jobs
+            .iter()
+            .chain(&resolved)
+            .any(|job| job.id != id && job.name.as_deref() == Some(name))
+        {
+            bail!("a job named {name:?} already exists");
+        }
+        Ok(())
+    }
+
+    /// Lists backup jobs with an id greater than or equal to `id`.
     pub(crate) fn list_by_gte(id: u32) -> Vec<Job> {
         Self::get_jobs()
             .into_iter()
@@ -134,9 +309,132 @@ impl Application {
     }
 }
 
+/// Replaces the fields of `job` with those in `fields`. The id cannot be overridden.
+fn apply_override(job: &Job, fields: &toml::Table) -> Result<Job> {
+    if fields.contains_key("id") {
+        bail!("the id of a job cannot be overridden");
+    }
+    let mut table = toml::Table::try_from(job)?;
+    table.extend(fields.clone());
+    Ok(table.try_into()?)
+}
+
+/// Returns the files matching an `include` entry, sorted by name.
+///
+/// A relative pattern is taken relative to `dir`. Wildcards are only supported in
+/// the file name; a pattern without wildcards must name an existing file.
+fn include_files(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = dir.join(template::expand(Path::new(pattern))?);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        if !path.is_file() {
+            bail!("included file {} does not exist", path.display());
+        }
+        return Ok(vec![path]);
+    }
+    let parent = path.parent().unwrap_or(dir);
+    let mut files = vec![];
+    for entry in fs::read_dir(parent)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file()
+            && matches_wildcard(&name, &entry.file_name().to_string_lossy())
+        {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns `true` if `name` matches `pattern`, where `*` matches any run of
+/// characters and `?` any single character.
+fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bn)) => {
+                    backtrack = Some((bp, bn + 1));
+                    p = bp + 1;
+                    n = bn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Reads an included file, upgrading it to the current schema version like the
+/// configuration file itself.
+fn read_fragment(path: &Path) -> Result<Fragment> {
+    let read = || -> Result<Fragment> {
+        let mut doc: toml::Table = toml::from_str(&fs::read_to_string(path)?)?;
+        if doc.contains_key("include") {
+            bail!("included files cannot include other files");
+        }
+        migration::migrate(&mut doc)?;
+        Ok(doc.try_into()?)
+    };
+    read().map_err(|e| anyhow!("cannot read included file {}: {e}", path.display()))
+}
+
+/// Returns the directory the included files are relative to: that of the
+/// configuration file.
+fn include_dir() -> PathBuf {
+    config_file()
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
 /// Returns the absolute path to the configuration file.
 pub(crate) fn config_file() -> PathBuf {
-    config_dir().join(CONFIG_NAME)
+    custom_config_file()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| config_dir().join(CONFIG_NAME))
+}
+
+/// Returns the configuration file selected instead of the default one, if any.
+pub(crate) fn custom_config_file() -> Option<&'static Path> {
+    CONFIG_FILE.get().map(PathBuf::as_path)
+}
+
+/// Uses `path` as the configuration file for the rest of the process.
+///
+/// Must be called before the configuration is first accessed; later calls are
+/// ignored.
+pub(crate) fn set_config_file(path: &Path) -> Result<()> {
+    let path = std::path::absolute(path)?;
+    let _ = CONFIG_FILE.set(path);
+    Ok(())
+}
+
+/// Returns the configuration file of the profile `name`, next to the default one.
+pub(crate) fn profile_file(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        bail!("invalid profile name: {name:?}");
+    }
+    Ok(config_dir().join(format!("{name}.toml")))
+}
+
+/// Returns the path of the lock file guarding changes to the configuration file.
+pub(crate) fn config_lock_file() -> PathBuf {
+    with_suffix(&config_file(), ".lock")
 }
 
 /// Returns the configuration directory for the application, platform-specific.
@@ -171,53 +469,194 @@ fn config_file_exists() -> bool {
 /// Writes the application configuration to the config file in TOML format.
 ///
 /// Creates the parent directory if it does not exist.
+///
+/// A configuration whose upgrade was not written back yet keeps the file it was
+/// upgraded from, as [`persist_upgrade`] does.
 pub(crate) fn write_config(data: &Application) -> Result<()> {
-    let file_path = config_file();
-    if let Some(parent) = file_path.parent() {
+    match &data.upgraded_from {
+        Some(version) => write_upgraded(&config_file(), data, version),
+        None => write_config_to(&config_file(), data),
+    }
+}
+
+/// Writes the configuration to `path` atomically.
+///
+/// The new content is written to a temporary file that is renamed into place, so a
+/// crash never leaves a truncated configuration behind. The previous configuration
+/// is kept as `<path>.bak`, unless it is unreadable itself.
+fn write_config_to(path: &Path, data: &Application) -> Result<()> {
+    if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)?;
     }
-    let file = fs::File::create(file_path)?;
+    let tmp = with_suffix(path, ".tmp");
+    let file = fs::File::create(&tmp)?;
     let mut writer = io::BufWriter::new(file);
     let toml_str = toml::to_string_pretty(&data)?;
     writer.write_all(toml_str.as_bytes())?;
     writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+
+    if parse_config(path).is_ok() {
+        fs::copy(path, backup_file(path))?;
+    }
+    fs::rename(&tmp, path)?;
     Ok(())
 }
 
+/// Returns the backup kept of the configuration file at `path`.
+pub(crate) fn backup_file(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+/// Reads and parses the configuration file at `path`, upgrading it in memory to the
+/// current schema version.
+fn parse_config(path: &Path) -> Result<Application> {
+    Ok(load_config_from(path)?.0)
+}
+
+/// Reads, migrates and parses the configuration file at `path`.
+///
+/// Also returns the version the file had if it was migrated.
+fn load_config_from(path: &Path) -> Result<(Application, Option<String>)> {
+    let toml_str = fs::read_to_string(path)?;
+    let mut doc: toml::Table = toml::from_str(&toml_str)?;
+    let upgraded_from = migration::migrate(&mut doc)?;
+    Ok((doc.try_into()?, upgraded_from))
+}
+
 /// Reads the default configuration file in TOML format.
+///
+/// If the file cannot be read, offers to restore it from its backup and exits when
+/// that is declined or impossible.
 fn read_config_file() -> Application {
     let file_path = config_file();
-    let toml_str = fs::read_to_string(&file_path).unwrap_or_else(|e| {
-        eprintln!("Error reading config file: {e}");
-        std::process::exit(1);
-    });
-    toml::from_str(&toml_str).unwrap_or_else(|e| {
-        eprintln!("Error parsing config file: {e}");
-        std::process::exit(1);
-    })
+    match load_config_from(&file_path) {
+        Ok((app, upgraded_from)) => upgraded(&file_path, app, upgraded_from),
+        Err(e) if e.downcast_ref::<UnsupportedVersion>().is_some() => {
+            eprintln!("Error reading config file {}: {e}", file_path.display());
+            process::exit(sysexits::EX_CONFIG);
+        }
+        Err(e) => {
+            eprintln!("Error reading config file {}: {e}", file_path.display());
+            recover(&file_path).unwrap_or_else(|| process::exit(sysexits::EX_CONFIG))
+        }
+    }
+}
+
+/// Writes the configuration `app` read from `path` back to disk if it was upgraded
+/// from an older version, or remembers that it still has to be.
+fn upgraded(path: &Path, mut app: Application, upgraded_from: Option<String>) -> Application {
+    if let Some(version) = upgraded_from {
+        match persist_upgrade(path, &config_lock_file()) {
+            Ok(true) => {}
+            Ok(false) => app.upgraded_from = Some(version),
+            Err(e) => eprintln!("Failed to write the upgraded config file: {e}"),
+        }
+    }
+    app
+}
+
+/// Writes the upgraded configuration at `path` back to disk while holding the lock
+/// file `lock_path`.
+///
+/// If another process, or a command of this one, holds the lock, the file is left
+/// alone and `false` is returned: the configuration stays upgraded in memory, and
+/// the holder of the lock writes it in the current format with its own change. The
+/// file is read again under the lock, so a change made since it was first read is
+/// not lost.
+fn persist_upgrade(path: &Path, lock_path: &Path) -> Result<bool> {
+    let _lock = match lock::acquire(lock_path, None) {
+        Err(e) if lock::is_contention(&e) => return Ok(false),
+        res => res?,
+    };
+    if let (app, Some(version)) = load_config_from(path)? {
+        write_upgraded(path, &app, &version)?;
+    }
+    Ok(true)
+}
+
+/// Writes a configuration upgraded from `version`, keeping the old file as
+/// `<path>.v<version>.bak`.
+fn write_upgraded(path: &Path, app: &Application, version: &str) -> Result<()> {
+    let backup = with_suffix(path, &format!(".v{version}.bak"));
+    fs::copy(path, &backup)?;
+    write_config_to(path, app)?;
+    eprintln!(
+        "Upgraded config file from version {version} to {CURRENT_VERSION} (previous version kept as {}).",
+        backup.display()
+    );
+    Ok(())
+}
+
+/// Asks whether the damaged configuration at `path` should be replaced by its backup.
+///
+/// The damaged file is kept as `<path>.corrupt`. Returns the restored configuration,
+/// or `None` if there is no usable backup or the user declined.
+fn recover(path: &Path) -> Option<Application> {
+    let backup = backup_file(path);
+    let app = match parse_config(&backup) {
+        Ok(app) => app,
+        Err(_) => {
+            eprintln!("No usable backup found at {}.", backup.display());
+            return None;
+        }
+    };
+    eprint!(
+        "Restore the configuration from {} ({} job(s))? (y/n): ",
+        backup.display(),
+        app.jobs.len()
+    );
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).ok()?;
+    if input.trim().to_lowercase() != "y" {
+        eprintln!(
+            "Configuration left unchanged. Fix {} or copy the backup over it.",
+            path.display()
+        );
+        return None;
+    }
+
+    let restored = fs::rename(path, with_suffix(path, ".corrupt"))
+        .map_err(anyhow::Error::from)
+        .and_then(|_| write_config_to(path, &app));
+    match restored {
+        Ok(()) => {
+            eprintln!("Configuration restored from {}.", backup.display());
+            Some(app)
+        }
+        Err(e) => {
+            eprintln!("Failed to restore the configuration: {e}");
+            None
+        }
+    }
 }
 
 /// Initializes the configuration file for the application if it does not exist.
 /// This ensures that the application always has a valid configuration file to work with.
 pub(crate) fn init_config() {
     let config_file = config_file();
-    if !config_file.exists() {
-        let app = Application::new();
-
-        let parent = config_file.parent().unwrap_or_else(|| Path::new(""));
-        fs::create_dir_all(parent).unwrap();
-
-        let file = fs::File::create(config_file).unwrap();
-        let mut writer = io::BufWriter::new(file);
-        let toml_str = toml::to_string_pretty(&app).unwrap();
-        writer.write_all(toml_str.as_bytes()).unwrap();
-        writer.flush().unwrap();
+    if !config_file.exists()
+        && let Err(e) = write_config_to(&config_file, &Application::new())
+    {
+        eprintln!(
+            "Failed to create config file {}: {e}",
+            config_file.display()
+        );
+        process::exit(sysexits::EX_CANTCREAT);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use hbackup::job::{CompressFormat, Level};
     use std::env;
 
     #[test]
@@ -226,10 +665,19 @@ mod tests {
         assert_eq!(config_file(), file);
     }
 
+    #[test]
+    fn test_profile_file() -> Result<()> {
+        let file = config_dir().join("hbackup").join("work.toml");
+        assert_eq!(profile_file("work")?, file);
+        assert!(profile_file("../work").is_err());
+        assert!(profile_file("").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_application_new() {
         let app = Application::new();
-        assert_eq!(app.version, "1.0");
+        assert_eq!(app.version, CURRENT_VERSION);
         assert!(app.jobs.is_empty());
     }
 
@@ -239,19 +687,19 @@ mod tests {
         let source = PathBuf::from("/test/source");
         let target = PathBuf::from("/test/target");
 
-        app.add_job(
-            source.clone(),
-            target.clone(),
+        app.push_job(Job::temp_job(
+            vec![source.clone()],
+            vec![target.clone()],
             Some(CompressFormat::Gzip),
             Some(Level::Default),
             None,
             None,
-        )?;
+        ))?;
 
         assert_eq!(app.jobs.len(), 1);
         assert_eq!(app.jobs[0].id, 1);
-        assert_eq!(app.jobs[0].source, source);
-        assert_eq!(app.jobs[0].target, target);
+        assert_eq!(app.jobs[0].source, vec![source]);
+        assert_eq!(app.jobs[0].target, vec![target]);
         assert!(matches!(
             app.jobs[0].compression,
             Some(CompressFormat::Gzip)
@@ -265,24 +713,24 @@ mod tests {
         let mut app = Application::new();
 
         // Add first job
-        app.add_job(
-            PathBuf::from("/test/source1"),
-            PathBuf::from("/test/target1"),
+        app.push_job(Job::temp_job(
+            vec![PathBuf::from("/test/source1")],
+            vec![PathBuf::from("/test/target1")],
             Some(CompressFormat::Zip),
             Some(Level::Fastest),
             None,
             None,
-        )?;
+        ))?;
 
         // Add second job
-        app.add_job(
-            PathBuf::from("/test/source2"),
-            PathBuf::from("/test/target2"),
+        app.push_job(Job::temp_job(
+            vec![PathBuf::from("/test/source2")],
+            vec![PathBuf::from("/test/target2")],
             Some(CompressFormat::Zstd),
             Some(Level::Best),
             Some(vec!["*.log".to_string()]),
             None,
-        )?;
+        ))?;
 
         assert_eq!(app.jobs.len(), 2);
         assert_eq!(app.jobs[0].id, 1);
@@ -297,22 +745,22 @@ mod tests {
         let mut app = Application::new();
 
         // Add jobs
-        app.add_job(
-            PathBuf::from("/test/source1"),
-            PathBuf::from("/test/target1"),
+        app.push_job(Job::temp_job(
+            vec![PathBuf::from("/test/source1")],
+            vec![PathBuf::from("/test/target1")],
             None,
             None,
             None,
             None,
-        )?;
-        app.add_job(
-            PathBuf::from("/test/source2"),
-            PathBuf::from("/test/target2"),
+        ))?;
+        app.push_job(Job::temp_job(
+            vec![PathBuf::from("/test/source2")],
+            vec![PathBuf::from("/test/target2")],
             None,
             None,
             None,
             None,
-        )?;
+        ))?;
 
         assert_eq!(app.jobs.len(), 2);
 
@@ -330,27 +778,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_application_insert_job() -> Result<()> {
+        let mut app = Application::new();
+        let job = |id| Job {
+            id,
+            source: vec![PathBuf::from("/test/source")],
+            target: vec![PathBuf::from("/test/target")],
+            ..Default::default()
+        };
+        assert_eq!(app.insert_job(job(5))?, 5);
+        assert_eq!(app.insert_job(job(5))?, 6);
+        assert_eq!(app.insert_job(job(2))?, 2);
+        assert_eq!(app.insert_job(job(0))?, 7);
+        Ok(())
+    }
+
     #[test]
     fn test_application_reset_jobs() -> Result<()> {
         let mut app = Application::new();
 
         // Add some jobs
-        app.add_job(
-            PathBuf::from("/test/source1"),
-            PathBuf::from("/test/target1"),
+        app.push_job(Job::temp_job(
+            vec![PathBuf::from("/test/source1")],
+            vec![PathBuf::from("/test/target1")],
             None,
             None,
             None,
             None,
-        )?;
-        app.add_job(
-            PathBuf::from("/test/source2"),
-            PathBuf::from("/test/target2"),
+        ))?;
+        app.push_job(Job::temp_job(
+            vec![PathBuf::from("/test/source2")],
+            vec![PathBuf::from("/test/target2")],
             None,
             None,
             None,
             None,
-        )?;
+        ))?;
 
         assert_eq!(app.jobs.len(), 2);
 
@@ -363,18 +827,18 @@ mod tests {
     #[test]
     fn test_application_serialization() -> Result<()> {
         let mut app = Application::new();
-        app.add_job(
-            PathBuf::from("/test/source"),
-            PathBuf::from("/test/target"),
+        app.push_job(Job::temp_job(
+            vec![PathBuf::from("/test/source")],
+            vec![PathBuf::from("/test/target")],
             Some(CompressFormat::Gzip),
             Some(Level::Default),
             Some(vec!["*.log".to_string()]),
             None,
-        )?;
+        ))?;
 
         // Test TOML serialization
         let toml_str = toml::to_string(&app).expect("Failed to serialize to TOML");
-        assert!(toml_str.contains("version = \"1.0\""));
+        assert!(toml_str.contains(&format!("version = \"{CURRENT_VERSION}\"")));
         assert!(toml_str.contains("id = 1"));
         assert!(toml_str.contains("Gzip"));
 
@@ -390,10 +854,142 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_write_config_keeps_backup() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let path = temp_dir.path().join("config.toml");
+
+        let mut app = Application::new();
+        write_config_to(&path, &app)?;
+        assert!(!backup_file(&path).exists());
+
+        app.push_job(Job::temp_job(
+            vec![PathBuf::from("/test/source")],
+            vec![PathBuf::from("/test/target")],
+            None,
+            None,
+            None,
+            None,
+        ))?;
+        write_config_to(&path, &app)?;
+        assert_eq!(parse_config(&path)?.jobs.len(), 1);
+        assert!(parse_config(&backup_file(&path))?.jobs.is_empty());
+        assert!(!with_suffix(&path, ".tmp").exists());
+
+        // A damaged configuration does not replace the last good backup.
+        fs::write(&path, "version = ")?;
+        write_config_to(&path, &app)?;
+        assert!(parse_config(&backup_file(&path))?.jobs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_config_migrates_old_version() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let path = temp_dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "version = \"1.0\"\n\n[[jobs]]\nid = 1\nsource = \"/src\"\ntarget = \"/dst\"\n",
+        )?;
+
+        let (app, upgraded_from) = load_config_from(&path)?;
+        assert_eq!(upgraded_from.as_deref(), Some("1.0"));
+        assert_eq!(app.version, CURRENT_VERSION);
+        assert_eq!(app.jobs[0].model, Some(hbackup::job::BackupModel::Full));
+
+        // The file is not rewritten while another process holds the config lock.
+        let lock_path = with_suffix(&path, ".lock");
+        let held = lock::acquire(&lock_path, None)?;
+        assert!(!persist_upgrade(&path, &lock_path)?);
+        assert_eq!(load_config_from(&path)?.1.as_deref(), Some("1.0"));
+        drop(held);
+
+        assert!(persist_upgrade(&path, &lock_path)?);
+        assert!(with_suffix(&path, ".v1.0.bak").exists());
+        assert_eq!(load_config_from(&path)?.1, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_includes_and_host_overrides() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let dir = temp_dir.path();
+        fs::create_dir(dir.join("jobs.d"))?;
+        fs::write(
+            dir.join("team.toml"),
+            "[[jobs]]\nid = 10\nname = \"photos\"\nsource = [\"/mnt/photos\"]\ntarget = [\"/backup\"]\n",
+        )?;
+        fs::write(
+            dir.join("jobs.d").join("b.toml"),
+            "[[jobs]]\nid = 12\nsource = [\"/b\"]\ntarget = [\"/backup\"]\n",
+        )?;
+        fs::write(
+            dir.join("jobs.d").join("a.toml"),
+            "version = \"1.0\"\n\n[[jobs]]\nid = 11\nsource = \"/a\"\ntarget = \"/backup\"\n",
+        )?;
+        fs::write(dir.join("jobs.d").join("notes.txt"), "")?;
+
+        let app: Application = toml::from_str(
+            r#"
+            version = "1.3"
+            include = ["team.toml", "jobs.d/*.toml"]
+
+            [[jobs]]
+            id = 1
+            source = ["/home"]
+            target = ["/backup"]
+
+            [host."laptop-01".jobs.photos]
+            source = ["/media/photos"]
+
+            [host."laptop-01".jobs.1]
+            target = ["/media/usb"]
+            "#,
+        )?;
+
+        let jobs = app.resolve_in(dir, "desktop")?;
+        let ids: Vec<_> = jobs.iter().map(|job| job.id).collect();
+        assert_eq!(ids, [1, 10, 11, 12]);
+        assert_eq!(jobs[1].source, vec![PathBuf::from("/mnt/photos")]);
+        assert_eq!(jobs[2].source, vec![PathBuf::from("/a")]);
+
+        let jobs = app.resolve_in(dir, "laptop-01")?;
+        assert_eq!(jobs[0].target, vec![PathBuf::from("/media/usb")]);
+        assert_eq!(jobs[1].source, vec![PathBuf::from("/media/photos")]);
+        assert_eq!(jobs[1].name.as_deref(), Some("photos"));
+
+        // Included jobs are traced back to their file.
+        let photos = JobRef::Name("photos".to_string());
+        assert_eq!(
+            app.included_file_in(dir, "desktop", &photos),
+            Some(dir.join("team.toml"))
+        );
+        assert_eq!(app.included_file_in(dir, "desktop", &JobRef::Id(1)), None);
+        assert_eq!(app.included_file_in(dir, "desktop", &JobRef::Id(9)), None);
+
+        // Included jobs must not reuse an id.
+        fs::write(
+            dir.join("jobs.d").join("c.toml"),
+            "[[jobs]]\nid = 1\nsource = [\"/c\"]\ntarget = [\"/backup\"]\n",
+        )?;
+        let err = app.resolve_in(dir, "desktop").unwrap_err();
+        assert!(err.to_string().contains("job id 1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_wildcard() {
+        assert!(matches_wildcard("*.toml", "team.toml"));
+        assert!(matches_wildcard("job-?.toml", "job-1.toml"));
+        assert!(matches_wildcard("a*b*c", "aXbYbZc"));
+        assert!(!matches_wildcard("*.toml", "team.toml.bak"));
+        assert!(!matches_wildcard("job-?.toml", "job-10.toml"));
+    }
+
     #[test]
     fn test_application_default() {
         let app = Application::default();
-        assert_eq!(app.version, "1.0");
+        assert_eq!(app.version, CURRENT_VERSION);
         assert!(app.jobs.is_empty());
     }
 
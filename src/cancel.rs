@@ -0,0 +1,38 @@
+//! Cooperative cancellation of running backup jobs.
+//!
+//! The CLI flips a process-wide flag when it receives SIGINT/SIGTERM. Job runners
+//! poll the flag between items and archive entries, so in-flight copies are allowed
+//! to finish while no new work is scheduled.
+use crate::error::HbackupError;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Requests cancellation of all running jobs.
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// Returns `true` once cancellation has been requested.
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Returns `true` if `err` reports that a job was stopped by a cancellation request.
+pub fn is_cancellation(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<HbackupError>(),
+        Some(HbackupError::Cancelled)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_cancellation() {
+        assert!(is_cancellation(&HbackupError::Cancelled.into()));
+        assert!(!is_cancellation(&anyhow::anyhow!("disk full")));
+    }
+}
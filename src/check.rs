@@ -0,0 +1,240 @@
+//! Validation of the configuration and the environment of its jobs.
+//!
+//! `bk check` reports the problems that would otherwise only surface when a job runs:
+//! missing sources, unwritable targets, invalid settings in a hand-edited config and
+//! ignore entries that match nothing. Targets inside a source are only warned about,
+//! since they are left out of the backup.
+
+use crate::Result;
+use crate::application::Application;
+use crate::sysexits;
+use hbackup::error::HbackupError;
+use hbackup::job::{BackupModel, Job, JobRef, validate_name};
+use hbackup::lock;
+use hbackup::schedule::Schedule;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Problems found in a single job.
+#[derive(Debug, Default)]
+struct Findings {
+    /// Problems that make the job fail.
+    errors: Vec<String>,
+    /// Problems that are likely mistakes but do not stop the job.
+    warnings: Vec<String>,
+}
+
+/// Checks the jobs referenced by `ids`, or all jobs, and prints the findings.
+///
+/// Exits with `EX_CONFIG` if any error was found.
+pub(crate) fn run(ids: Option<Vec<JobRef>>) -> Result<()> {
+    let jobs = match Application::load_config().resolve() {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(sysexits::EX_CONFIG);
+        }
+    };
+    let selected: Vec<_> = match &ids {
+        None => jobs.iter().collect(),
+        Some(ids) => {
+            for id in ids
+                .iter()
+                .filter(|id| !jobs.iter().any(|job| id.matches(job)))
+            {
+                eprintln!("Job {id} not found.");
+            }
+            jobs.iter()
+                .filter(|job| ids.iter().any(|id| id.matches(job)))
+                .collect()
+        }
+    };
+    if selected.is_empty() {
+        println!("No jobs to check.");
+        return Ok(());
+    }
+
+    let (mut errors, mut warnings) = (0, 0);
+    for job in selected {
+        let findings = check_job(job, &jobs);
+        match &job.name {
+            Some(name) => print!("job {} ({name}): ", job.id),
+            None => print!("job {}: ", job.id),
+        }
+        if findings.errors.is_empty() && findings.warnings.is_empty() {
+            println!("ok");
+            continue;
+        }
+        println!();
+        for e in &findings.errors {
+            println!("    error: {e}");
+        }
+        for w in &findings.warnings {
+            println!("    warning: {w}");
+        }
+        errors += findings.errors.len();
+        warnings += findings.warnings.len();
+    }
+    println!("{errors} error(s), {warnings} warning(s)");
+    if errors > 0 {
+        process::exit(sysexits::EX_CONFIG);
+    }
+    Ok(())
+}
+
+/// Checks `job` on its own and against the other jobs in `all`.
+fn check_job(job: &Job, all: &[Job]) -> Findings {
+    let mut f = Findings::default();
+
+    if all.iter().filter(|other| other.id == job.id).count() > 1 {
+        f.errors
+            .push(format!("id {} is used by more than one job", job.id));
+    }
+    if let Some(name) = &job.name {
+        if let Err(e) = validate_name(name) {
+            f.errors.push(e.to_string());
+        }
+        if all
+            .iter()
+            .filter(|other| other.name.as_ref() == Some(name))
+            .count()
+            > 1
+        {
+            f.errors
+                .push(format!("name {name:?} is used by more than one job"));
+        }
+    }
+    if job.compression.is_some() && job.model == Some(BackupModel::Mirror) {
+        f.errors
+            .push(HbackupError::InvalidCompressionForMirror.to_string());
+    }
+    if let Some(schedule) = &job.schedule
+        && let Err(e) = schedule.parse::<Schedule>()
+    {
+        f.errors.push(format!("invalid schedule {schedule:?}: {e}"));
+    }
+    if job.source.is_empty() {
+        f.errors.push("the job has no source".to_string());
+    }
+    if job.target.is_empty() {
+        f.errors.push("the job has no target".to_string());
+    }
+
+    let expanded = match job.expanded() {
+        Ok(expanded) => expanded,
+        Err(e) => {
+            f.errors.push(format!("cannot expand the paths: {e}"));
+            return f;
+        }
+    };
+    for src in &expanded.source {
+        if !src.exists() {
+            f.errors
+                .push(format!("source {} does not exist", src.display()));
+        } else if fs::read_dir(src).is_err() && fs::File::open(src).is_err() {
+            f.errors
+                .push(format!("source {} is not readable", src.display()));
+        }
+    }
+    if let Err(e) = expanded.check_paths() {
+        f.errors.push(e.to_string());
+    }
+    for target in &expanded.target {
+        if let Err(e) = check_writable("target", target) {
+            f.errors.push(e);
+        }
+    }
+    if !expanded.target.is_empty()
+        && let Err(e) = check_writable("lock directory", &lock::lock_dir())
+    {
+        f.errors.push(e);
+    }
+    for (target, src) in expanded.nested_targets() {
+        if target != src {
+            f.warnings.push(format!(
+                "target {} is inside the source {} and is left out of the backup",
+                target.display(),
+                src.display()
+            ));
+        }
+    }
+    for entry in job.ignore.iter().flatten() {
+        let dirs: Vec<_> = expanded.source.iter().filter(|src| src.is_dir()).collect();
+        if !dirs.is_empty() && !dirs.iter().any(|src| src.join(entry).exists()) {
+            f.warnings.push(format!(
+                "ignore entry {entry:?} matches nothing in the sources"
+            ));
+        }
+    }
+    f
+}
+
+/// Checks that `what` can be written to `target`, or to the directory it would be
+/// created in.
+fn check_writable(what: &str, target: &Path) -> std::result::Result<(), String> {
+    let mut dir: PathBuf = target.to_path_buf();
+    while !dir.exists() {
+        match dir.parent() {
+            Some(parent) => dir = parent.to_path_buf(),
+            None => return Err(format!("{what} {} cannot be created", target.display())),
+        }
+    }
+    if !dir.is_dir() {
+        // A single file job overwrites an existing target file.
+        return match fs::OpenOptions::new().append(true).open(&dir) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{what} {} is not writable: {e}", dir.display())),
+        };
+    }
+    let probe = dir.join(format!(".hbackup-check-{}", process::id()));
+    match fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) => Err(format!("{what} {} is not writable: {e}", dir.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbackup::job::CompressFormat;
+
+    #[test]
+    fn test_check_job() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        fs::create_dir_all(src.join("cache"))?;
+
+        let mut job = Job {
+            id: 1,
+            source: vec![src.clone()],
+            target: vec![temp_dir.path().join("backup")],
+            ignore: Some(vec!["cache".to_string()]),
+            ..Default::default()
+        };
+        let findings = check_job(&job, std::slice::from_ref(&job));
+        assert!(findings.errors.is_empty(), "{:?}", findings.errors);
+        assert!(findings.warnings.is_empty());
+
+        job.source.push(temp_dir.path().join("missing"));
+        job.target.push(src.join("backup"));
+        job.compression = Some(CompressFormat::Gzip);
+        job.model = Some(BackupModel::Mirror);
+        job.ignore = Some(vec!["*.log".to_string()]);
+        let other = Job {
+            id: 1,
+            ..Default::default()
+        };
+        let findings = check_job(&job, &[job.clone(), other]);
+        let errors = findings.errors.join("\n");
+        assert!(errors.contains("more than one job"));
+        assert!(errors.contains("mirror"));
+        assert!(errors.contains("does not exist"));
+        assert_eq!(findings.warnings.len(), 2);
+        assert!(findings.warnings[0].contains("inside the source"));
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+//! Comparison of the sources of a job with its backups.
+//!
+//! `bk diff` lists the files that were added, removed, modified or only had their
+//! metadata changed since the backup in each target of a job, so a mirror can be
+//! checked before it is trusted or restored from.
+
+use crate::Result;
+use crate::application::Application;
+use crate::sysexits;
+use hbackup::diff::{self, Diff};
+use hbackup::job::JobRef;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process;
+
+/// The differences in one target, as printed with `--json`.
+#[derive(Serialize)]
+struct TargetDiff {
+    target: PathBuf,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    diff: Option<Diff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The differences of a job, as printed with `--json`.
+#[derive(Serialize)]
+struct JobDiff {
+    job: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    targets: Vec<TargetDiff>,
+}
+
+/// Compares the sources of the job referenced by `id` with the backup in each of its
+/// targets and prints the differences, as JSON with `json`.
+///
+/// Exits with `EX_USAGE` if there is no such job and with `EX_DATAERR` if a backup
+/// cannot be read.
+pub(crate) fn run(id: JobRef, checksum: bool, json: bool) -> Result<()> {
+    let Some(job) = Application::get_jobs()
+        .into_iter()
+        .find(|job| id.matches(job))
+    else {
+        eprintln!("Job {id} not found.");
+        process::exit(sysexits::EX_USAGE);
+    };
+    let job = job.expanded()?;
+
+    let targets: Vec<_> = job
+        .target
+        .iter()
+        .map(|target| match diff::diff_target(&job, target, checksum) {
+            Ok(diff) => TargetDiff {
+                target: target.clone(),
+                diff: Some(diff),
+                error: None,
+            },
+            Err(e) => TargetDiff {
+                target: target.clone(),
+                diff: None,
+                error: Some(format!("{e:#}")),
+            },
+        })
+        .collect();
+    let failed = targets.iter().any(|t| t.error.is_some());
+    let result = JobDiff {
+        job: job.id,
+        name: job.name.clone(),
+        targets,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    } else {
+        print(&result);
+    }
+    if failed {
+        process::exit(sysexits::EX_DATAERR);
+    }
+    Ok(())
+}
+
+/// Prints the differences of a job for people.
+fn print(result: &JobDiff) {
+    match &result.name {
+        Some(name) => println!("job {} ({name}):", result.job),
+        None => println!("job {}:", result.job),
+    }
+    for target in &result.targets {
+        let path = target.target.display();
+        let Some(diff) = &target.diff else {
+            let error = target.error.as_deref().unwrap_or_default();
+            println!("    {path}: error: {error}");
+            continue;
+        };
+        println!("    {path}: {}", diff.summary());
+        let details = [
+            ("added", &diff.added),
+            ("removed", &diff.removed),
+            ("modified", &diff.modified),
+            ("metadata", &diff.metadata),
+        ];
+        for (what, paths) in details {
+            for path in paths {
+                println!("        {what}: {path}");
+            }
+        }
+    }
+}
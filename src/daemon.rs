@@ -0,0 +1,226 @@
+//! Built-in scheduler that runs jobs according to their `schedule`.
+//!
+//! The daemon keeps the last run time of every scheduled job in a state file next to
+//! the configuration file, so runs missed while the machine was asleep or turned off
+//! are caught up as soon as it is running again. A job is never started while a
+//! previous run of the same job is still in progress.
+//!
+//! The daemon never prompts: while the configuration cannot be read, the error is
+//! logged and the jobs that were last read successfully keep being scheduled.
+
+use crate::application::{Application, config_file};
+use anyhow::Result;
+use chrono::{DateTime, Local, TimeZone};
+use hbackup::job::{Job, RunOptions, run_job};
+use hbackup::report::Status;
+use hbackup::schedule::Schedule;
+use hbackup::{cancel, lock};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval at which the configuration is reloaded and schedules are evaluated.
+const TICK: Duration = Duration::from_secs(15);
+
+/// Persistent state of the daemon.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct State {
+    /// Last run time of each scheduled job in Unix seconds, keyed by job id.
+    #[serde(default)]
+    pub last_run: BTreeMap<String, i64>,
+}
+
+impl State {
+    /// Loads the daemon state, starting fresh if it is missing or unreadable.
+    pub(crate) fn load() -> Self {
+        let Ok(s) = fs::read_to_string(state_file()) else {
+            return Self::default();
+        };
+        toml::from_str(&s).unwrap_or_else(|e| {
+            eprintln!("Ignoring unreadable daemon state: {e}");
+            Self::default()
+        })
+    }
+
+    /// Writes the daemon state next to the configuration file.
+    pub(crate) fn save(&self) -> Result<()> {
+        let path = state_file();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn last_run(&self, id: u32) -> Option<DateTime<Local>> {
+        let secs = *self.last_run.get(&id.to_string())?;
+        Local.timestamp_opt(secs, 0).single()
+    }
+
+    fn set_last_run(&mut self, id: u32, time: DateTime<Local>) {
+        self.last_run.insert(id.to_string(), time.timestamp());
+    }
+}
+
+/// Returns the path of the daemon state file.
+pub(crate) fn state_file() -> PathBuf {
+    config_file().with_extension("state.toml")
+}
+
+/// A finished run, sent from a job thread back to the scheduler.
+struct Finished {
+    id: u32,
+    started: DateTime<Local>,
+    status: Status,
+}
+
+/// Runs scheduled jobs until cancellation is requested, then waits for running jobs.
+pub(crate) fn run() -> Result<()> {
+    let mut state = State::load();
+    let mut running = HashSet::new();
+    let mut invalid = HashSet::new();
+    let (tx, rx) = mpsc::channel();
+    let mut jobs = vec![];
+    let mut config_error = None;
+    log("daemon started");
+
+    while !cancel::is_cancelled() {
+        match Application::try_get_jobs() {
+            Ok(loaded) => {
+                if config_error.take().is_some() {
+                    log("configuration can be read again");
+                }
+                jobs = loaded;
+            }
+            Err(e) => {
+                let e = format!("{e:#}");
+                if config_error.as_ref() != Some(&e) {
+                    log(&format!(
+                        "cannot read {}, keeping the last jobs read: {e}",
+                        config_file().display()
+                    ));
+                    config_error = Some(e);
+                }
+            }
+        }
+        let now = Local::now();
+        let mut dirty = false;
+        for job in jobs.iter().cloned() {
+            let Some(expr) = job.schedule.as_ref().filter(|_| job.is_enabled()) else {
+                continue;
+            };
+            let schedule = match expr.parse::<Schedule>() {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    if invalid.insert((job.id, expr.clone())) {
+                        eprintln!("Ignoring invalid schedule of job {}: {e}", job.id);
+                    }
+                    continue;
+                }
+            };
+            let Some(last_run) = state.last_run(job.id) else {
+                // A job seen for the first time is anchored at the current time.
+                state.set_last_run(job.id, now);
+                dirty = true;
+                continue;
+            };
+            let due = schedule.next_after(last_run).is_some_and(|due| due <= now);
+            if due && running.insert(job.id) {
+                spawn(job, tx.clone());
+            }
+        }
+        if dirty {
+            state.save()?;
+        }
+
+        let deadline = Instant::now() + TICK;
+        while Instant::now() < deadline && !cancel::is_cancelled() {
+            if let Ok(finished) = rx.recv_timeout(Duration::from_secs(1)) {
+                record(&mut state, &mut running, finished)?;
+            }
+        }
+    }
+
+    if !running.is_empty() {
+        log("waiting for running jobs to finish");
+    }
+    while !running.is_empty() {
+        let finished = rx.recv()?;
+        record(&mut state, &mut running, finished)?;
+    }
+    log("daemon stopped");
+    Ok(())
+}
+
+fn spawn(job: Job, tx: Sender<Finished>) {
+    log(&format!("job {}: started", job.id));
+    thread::spawn(move || {
+        let started = Local::now();
+        let status = match run_job(&job, &RunOptions::default()) {
+            Ok(()) => Status::Success,
+            Err(e) if cancel::is_cancellation(&e) => Status::Cancelled,
+            Err(e) if lock::is_contention(&e) => Status::Locked,
+            Err(e) => Status::Failed(e.to_string()),
+        };
+        let _ = tx.send(Finished {
+            id: job.id,
+            started,
+            status,
+        });
+    });
+}
+
+fn record(state: &mut State, running: &mut HashSet<u32>, finished: Finished) -> Result<()> {
+    let Finished {
+        id,
+        started,
+        status,
+    } = finished;
+    running.remove(&id);
+    match status {
+        Status::Success => log(&format!("job {id}: success")),
+        Status::Failed(e) => log(&format!("job {id}: failed ({e})")),
+        // A cancelled run is not recorded, so it is caught up on the next start.
+        Status::Cancelled => {
+            log(&format!("job {id}: cancelled"));
+            return Ok(());
+        }
+        // Another process is running the job; try again on the next tick.
+        Status::Locked => {
+            log(&format!("job {id}: skipped, another run is in progress"));
+            return Ok(());
+        }
+        // Disabled jobs are never started by the daemon.
+        Status::Disabled => return Ok(()),
+    }
+    state.set_last_run(id, started);
+    state.save()
+}
+
+/// Prints a timestamped log line.
+pub(crate) fn log(msg: &str) {
+    println!("[{}] {msg}", Local::now().format("%Y-%m-%d %H:%M:%S"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_roundtrip() -> Result<()> {
+        let mut state = State::default();
+        let time = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        state.set_last_run(3, time);
+        assert_eq!(state.last_run(3), Some(time));
+        assert_eq!(state.last_run(4), None);
+
+        let toml_str = toml::to_string_pretty(&state)?;
+        let state: State = toml::from_str(&toml_str)?;
+        assert_eq!(state.last_run(3), Some(time));
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+//! Comparison of the sources of a job with its backup.
+//!
+//! The current sources are compared with the copied tree in a target, or with the
+//! files listed for its archive: the [manifest](crate::manifest) written next to it,
+//! or the entries of the archive itself if there is none.
+//!
+//! By default a file counts as modified when a run would copy it again, that is when
+//! its size differs or the source is newer than the backup. With checksums, the
+//! content is compared instead, and a source that was only touched or had its mode
+//! changed is reported as a metadata change.
+
+use crate::file_util;
+use crate::integrity;
+use crate::item::is_newer;
+use crate::job::Job;
+use crate::manifest::{self, Manifest};
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+/// The differences between the sources of a job and the backup in one target.
+///
+/// Paths are named as in the backup, with `/` separators.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Diff {
+    /// Files in the sources that are not in the backup.
+    pub added: Vec<String>,
+    /// Files in the backup that are no longer in the sources.
+    pub removed: Vec<String>,
+    /// Files whose content differs.
+    pub modified: Vec<String>,
+    /// Files with the same content whose modification time or mode changed.
+    pub metadata: Vec<String>,
+}
+
+impl Diff {
+    /// Returns `true` if the backup matches the sources.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+            && self.metadata.is_empty()
+    }
+
+    /// Returns a one-line summary of the differences, e.g. "2 added, 1 modified".
+    pub fn summary(&self) -> String {
+        let parts: Vec<_> = [
+            (self.added.len(), "added"),
+            (self.removed.len(), "removed"),
+            (self.modified.len(), "modified"),
+            (self.metadata.len(), "metadata only"),
+        ]
+        .into_iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, what)| format!("{n} {what}"))
+        .collect();
+        if parts.is_empty() {
+            "no differences".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+/// What is known about a file on one side of the comparison.
+#[derive(Debug, Default)]
+struct FileInfo {
+    size: u64,
+    mtime: Option<SystemTime>,
+    mode: Option<u32>,
+    sha256: Option<String>,
+    /// Where to read the content if its hash is needed.
+    path: Option<PathBuf>,
+}
+
+impl FileInfo {
+    fn from_path(path: &Path) -> Result<FileInfo> {
+        let md = fs::metadata(path).with_context(|| format!("cannot read {}", path.display()))?;
+        Ok(FileInfo {
+            size: md.len(),
+            mtime: md.modified().ok(),
+            mode: Some(manifest::mode(&md)),
+            sha256: None,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    fn hash(&self) -> Result<Option<String>> {
+        match (&self.sha256, &self.path) {
+            (Some(sha256), _) => Ok(Some(sha256.clone())),
+            (None, Some(path)) => Ok(integrity::digest(File::open(path)?, true)?.1),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Compares the current sources of `job` with its backup in `target`. Expects
+/// expanded paths.
+///
+/// With `checksum`, files of the same size are told apart by their SHA-256 hash
+/// instead of their modification time.
+///
+/// # Errors
+/// Returns an error if the sources or the backup cannot be read, e.g. because the
+/// archive is missing.
+pub fn diff_target(job: &Job, target: &Path, checksum: bool) -> Result<Diff> {
+    let scanned = job.scan_sources()?;
+    let (sources, backup) = match (&job.compression, job.archive_path(target)) {
+        (Some(format), Some(archive)) => {
+            let files = file_util::archived_files(&job.source, &scanned, format)?;
+            (
+                source_files(files)?,
+                archive_files(job, &archive, checksum)?,
+            )
+        }
+        _ => {
+            let files = file_util::stored_files(&job.source, &scanned)?;
+            (source_files(files)?, tree_files(job, target)?)
+        }
+    };
+
+    let mut diff = Diff::default();
+    for (name, src) in &sources {
+        match backup.get(name) {
+            None => diff.added.push(name.clone()),
+            Some(dest) => match classify(src, dest, checksum)? {
+                Some(Change::Modified) => diff.modified.push(name.clone()),
+                Some(Change::Metadata) => diff.metadata.push(name.clone()),
+                None => {}
+            },
+        }
+    }
+    diff.removed = backup
+        .into_keys()
+        .filter(|name| !sources.contains_key(name))
+        .collect();
+    Ok(diff)
+}
+
+enum Change {
+    Modified,
+    Metadata,
+}
+
+/// Classifies the difference between a source file and its backup, if any.
+fn classify(src: &FileInfo, dest: &FileInfo, checksum: bool) -> Result<Option<Change>> {
+    if src.size != dest.size {
+        return Ok(Some(Change::Modified));
+    }
+    // The same rule as a mirror run, which copies sources newer than their copy.
+    let newer = matches!((src.mtime, dest.mtime), (Some(s), Some(d)) if is_newer(s, d));
+    let mode_changed = matches!((src.mode, dest.mode), (Some(s), Some(d)) if s != d);
+    let content_changed = if checksum {
+        src.hash()? != dest.hash()?
+    } else {
+        newer
+    };
+    Ok(if content_changed {
+        Some(Change::Modified)
+    } else if mode_changed || (checksum && newer) {
+        Some(Change::Metadata)
+    } else {
+        None
+    })
+}
+
+/// Returns the source files of a job by the name they are stored under.
+fn source_files(files: Vec<(PathBuf, PathBuf)>) -> Result<BTreeMap<String, FileInfo>> {
+    files
+        .into_iter()
+        .map(|(path, name)| Ok((manifest::slash_path(&name), FileInfo::from_path(&path)?)))
+        .collect()
+}
+
+/// Returns the files of the copied tree of an uncompressed job in `target`.
+fn tree_files(job: &Job, target: &Path) -> Result<BTreeMap<String, FileInfo>> {
+    let mut files = BTreeMap::new();
+    if let Some(src) = job.single_file()
+        && !target.is_dir()
+    {
+        // A single file copied to a target that is not a directory.
+        if target.exists() {
+            let name = src.file_name().context("invalid file name")?;
+            files.insert(
+                name.to_string_lossy().into_owned(),
+                FileInfo::from_path(target)?,
+            );
+        }
+        return Ok(files);
+    }
+    for name in file_util::top_level_names(&job.source)? {
+        let root = target.join(name);
+        if fs::symlink_metadata(&root).is_err() {
+            continue;
+        }
+        for entry in WalkDir::new(&root) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let rel = entry.path().strip_prefix(target)?;
+            files.insert(
+                manifest::slash_path(rel),
+                FileInfo::from_path(entry.path())?,
+            );
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the files stored in `archive`, from its manifest if it has one, or by
+/// decoding it otherwise.
+fn archive_files(job: &Job, archive: &Path, checksum: bool) -> Result<BTreeMap<String, FileInfo>> {
+    if !archive.exists() {
+        bail!("{} is missing", archive.display());
+    }
+    let manifest = match archive.parent() {
+        Some(dir) => Manifest::read(dir, job.id)?,
+        None => None,
+    }
+    .filter(|m| m.archive.as_deref().map(Path::new) == archive.file_name().map(Path::new));
+    if let Some(manifest) = manifest {
+        // The manifest records the modification time and mode of the sources.
+        return Ok(manifest
+            .files
+            .into_iter()
+            .map(|f| {
+                let info = FileInfo {
+                    size: f.size,
+                    mtime: Some(UNIX_EPOCH + Duration::from_secs(f.mtime.max(0) as u64)),
+                    mode: Some(f.mode),
+                    sha256: Some(f.sha256),
+                    path: None,
+                };
+                (f.path, info)
+            })
+            .collect());
+    }
+
+    let Some(format) = &job.compression else {
+        bail!("job {} has no compression format", job.id);
+    };
+    let tree = job.single_file().is_none();
+    let entries = integrity::read_archive(archive, format, tree, checksum)
+        .with_context(|| format!("cannot decode {}", archive.display()))?;
+    Ok(entries
+        .into_iter()
+        .map(|e| {
+            let info = FileInfo {
+                size: e.size,
+                sha256: e.sha256,
+                ..Default::default()
+            };
+            (manifest::slash_path(&e.name), info)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::{BackupModel, CompressFormat, RunOptions, run_job};
+
+    #[test]
+    fn test_diff_mirror_tree() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("a.txt"), "a")?;
+        fs::write(src.join("b.txt"), "b")?;
+        fs::write(src.join("c.txt"), "c")?;
+        let target = temp_dir.path().join("backup");
+        fs::create_dir_all(&target)?;
+        let job = Job::temp_job(
+            vec![src.clone()],
+            vec![target.clone()],
+            None,
+            None,
+            None,
+            Some(BackupModel::Mirror),
+        );
+        run_job(&job, &RunOptions::default())?;
+        assert!(diff_target(&job, &target, false)?.is_empty());
+
+        fs::write(src.join("a.txt"), "changed")?;
+        fs::remove_file(src.join("b.txt"))?;
+        fs::write(src.join("d.txt"), "d")?;
+        let diff = diff_target(&job, &target, true)?;
+        assert_eq!(diff.added, ["docs/d.txt"]);
+        assert_eq!(diff.removed, ["docs/b.txt"]);
+        assert_eq!(diff.modified, ["docs/a.txt"]);
+        assert!(diff.metadata.is_empty());
+        assert_eq!(diff.summary(), "1 added, 1 removed, 1 modified");
+
+        // A source touched after the backup keeps its content.
+        let file = File::options().write(true).open(src.join("c.txt"))?;
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))?;
+        assert_eq!(
+            diff_target(&job, &target, false)?.modified,
+            ["docs/a.txt", "docs/c.txt"]
+        );
+        assert_eq!(diff_target(&job, &target, true)?.metadata, ["docs/c.txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_archive() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("a.txt"), "a")?;
+        fs::write(src.join("b.txt"), "b")?;
+        let target = temp_dir.path().join("backup");
+        fs::create_dir_all(&target)?;
+        let job = Job::temp_job(
+            vec![src.clone()],
+            vec![target.clone()],
+            Some(CompressFormat::Tar),
+            None,
+            None,
+            None,
+        );
+        run_job(&job, &RunOptions::default())?;
+        assert!(diff_target(&job, &target, true)?.is_empty());
+
+        fs::write(src.join("a.txt"), "x")?;
+        fs::write(src.join("c.txt"), "c")?;
+        let diff = diff_target(&job, &target, true)?;
+        assert_eq!(diff.added, ["docs/c.txt"]);
+        assert_eq!(diff.modified, ["docs/a.txt"]);
+
+        // Without a manifest, the entries of the archive are listed.
+        fs::remove_file(manifest::manifest_path(&target, job.id))?;
+        let diff = diff_target(&job, &target, true)?;
+        assert_eq!(diff.added, ["docs/c.txt"]);
+        assert_eq!(diff.modified, ["docs/a.txt"]);
+        Ok(())
+    }
+}
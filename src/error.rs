@@ -17,4 +17,16 @@ pub enum HbackupError {
 
     #[error("maximum number of jobs reached ({0})")]
     TooManyJobs(u32),
+
+    #[error("backup cancelled")]
+    Cancelled,
+
+    #[error("{0} hook failed: {1}")]
+    HookFailed(&'static str, String),
+
+    #[error("{0} is locked by another hbackup process")]
+    Locked(PathBuf),
+
+    #[error("{} of {} targets failed", .0.len(), .1)]
+    TargetsFailed(Vec<(PathBuf, String)>, usize),
 }
@@ -0,0 +1,272 @@
+//! Export and import of jobs.
+//!
+//! `bk export` writes the jobs of the configuration file as JSON, TOML or YAML, and
+//! `bk import` reads such a document back, so job sets can be moved between machines
+//! or generated by other tools. Imported documents go through the same schema
+//! migrations as the configuration file.
+
+use crate::Result;
+use crate::application::Application;
+use crate::migration::{self, CURRENT_VERSION};
+use anyhow::{Context, bail};
+use clap::ValueEnum;
+use hbackup::error::HbackupError;
+use hbackup::job::{BackupModel, Job, JobRef, validate_name};
+use hbackup::schedule::Schedule;
+use hbackup::template;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Formats jobs can be exported to and imported from.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Guesses the format of `path` from its extension.
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// An exported set of jobs.
+#[derive(Serialize, Deserialize, Debug)]
+struct Document {
+    version: String,
+    jobs: Vec<Job>,
+}
+
+/// Prints the jobs referenced by `ids`, or all jobs of the configuration file.
+pub(crate) fn export(ids: Option<Vec<JobRef>>, format: Format) -> Result<()> {
+    let jobs = Application::load_config().jobs;
+    let jobs = match ids {
+        None => jobs,
+        Some(ids) => {
+            for id in ids
+                .iter()
+                .filter(|id| !jobs.iter().any(|job| id.matches(job)))
+            {
+                eprintln!("Job {id} not found.");
+            }
+            jobs.into_iter()
+                .filter(|job| ids.iter().any(|id| id.matches(job)))
+                .collect()
+        }
+    };
+    let doc = Document {
+        version: CURRENT_VERSION.to_string(),
+        jobs,
+    };
+    print!("{}", serialize(&doc, format)?);
+    Ok(())
+}
+
+fn serialize(doc: &Document, format: Format) -> Result<String> {
+    Ok(match format {
+        Format::Json => serde_json::to_string_pretty(doc)? + "\n",
+        Format::Toml => toml::to_string_pretty(doc)?,
+        Format::Yaml => serde_yaml::to_string(doc)?,
+    })
+}
+
+/// Imports the jobs of `file` (`-` for stdin) into the configuration.
+///
+/// With `replace`, the existing jobs are removed first. Otherwise an imported job
+/// keeps its id unless that id is taken, in which case it gets a new one.
+pub(crate) fn import(file: &Path, format: Option<Format>, replace: bool) -> Result<()> {
+    let format = format
+        .or_else(|| Format::from_path(file))
+        .unwrap_or(Format::Toml);
+    let content = if file == Path::new("-") {
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content)?;
+        content
+    } else {
+        fs::read_to_string(file).with_context(|| format!("cannot read {}", file.display()))?
+    };
+    let doc = parse(&content, format)?;
+
+    let mut app = Application::load_config();
+    if replace {
+        app.reset_jobs();
+    }
+    for job in &doc.jobs {
+        validate(job).with_context(|| format!("invalid job {}", job.id))?;
+        if let Some(name) = &job.name {
+            app.check_name(name, 0)?;
+        }
+        let old_id = job.id;
+        let id = app.insert_job(job.clone())?;
+        if id == old_id {
+            println!("Imported job {id}.");
+        } else {
+            println!("Imported job {old_id} as job {id}.");
+        }
+    }
+    app.write()
+}
+
+/// Parses a document in `format`, upgrading it to the current schema version.
+fn parse(content: &str, format: Format) -> Result<Document> {
+    let mut doc: toml::Table = match format {
+        Format::Toml => toml::from_str(content)?,
+        Format::Json => from_json(serde_json::from_str(content)?)?,
+        Format::Yaml => from_json(serde_yaml::from_str(content)?)?,
+    };
+    migration::migrate(&mut doc)?;
+    Ok(doc.try_into()?)
+}
+
+/// Converts a JSON value to a TOML table, dropping the `null`s TOML cannot hold.
+fn from_json(mut value: serde_json::Value) -> Result<toml::Table> {
+    fn strip_nulls(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.retain(|_, v| !v.is_null());
+                map.values_mut().for_each(strip_nulls);
+            }
+            serde_json::Value::Array(list) => list.iter_mut().for_each(strip_nulls),
+            _ => {}
+        }
+    }
+    strip_nulls(&mut value);
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Checks that an imported job can be run on this machine.
+///
+/// The paths are checked like those of an added job. Templates that cannot be
+/// expanded here, e.g. because a variable is only set on another machine, are
+/// reported as a warning and their paths are not checked.
+fn validate(job: &Job) -> Result<()> {
+    if job.source.is_empty() || job.target.is_empty() {
+        bail!("a job needs at least one source and one target");
+    }
+    if job.compression.is_some() && job.model == Some(BackupModel::Mirror) {
+        bail!(HbackupError::InvalidCompressionForMirror);
+    }
+    if let Some(name) = &job.name {
+        validate_name(name)?;
+    }
+    if let Some(schedule) = &job.schedule {
+        schedule.parse::<Schedule>()?;
+    }
+
+    for path in job.source.iter().chain(&job.target) {
+        match template::expand(path) {
+            Ok(expanded) if expanded.is_absolute() => {}
+            Ok(_) => bail!("path {} is not absolute", path.display()),
+            Err(e) => {
+                eprintln!(
+                    "Warning: the paths of job {} cannot be checked on this machine: {e:#}",
+                    job.id
+                );
+                return Ok(());
+            }
+        }
+    }
+    let expanded = job.expanded()?;
+    expanded.check_paths()?;
+    for (path, expanded) in job.source.iter().zip(&expanded.source) {
+        if !expanded.exists() {
+            eprintln!(
+                "Warning: source {} of job {} does not exist on this machine.",
+                path.display(),
+                job.id
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() -> Result<()> {
+        let toml = "version = \"1.3\"\n\n[[jobs]]\nid = 2\nsource = [\"/a\"]\ntarget = [\"/b\"]\n";
+        let json = r#"{"version": "1.3", "jobs": [{"id": 2, "name": null, "source": ["/a"], "target": ["/b"]}]}"#;
+        let yaml = "version: '1.3'\njobs:\n- id: 2\n  source: [/a]\n  target: [/b]\n";
+        for (content, format) in [
+            (toml, Format::Toml),
+            (json, Format::Json),
+            (yaml, Format::Yaml),
+        ] {
+            let doc = parse(content, format)?;
+            assert_eq!(doc.jobs.len(), 1);
+            assert_eq!(doc.jobs[0].id, 2);
+            assert_eq!(doc.jobs[0].target, vec![Path::new("/b").to_path_buf()]);
+        }
+
+        // Documents of older versions are migrated.
+        let doc = parse(
+            "[[jobs]]\nid = 1\nsource = \"/a\"\ntarget = \"/b\"\n",
+            Format::Toml,
+        )?;
+        assert_eq!(doc.jobs[0].source, vec![Path::new("/a").to_path_buf()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let doc = Document {
+            version: CURRENT_VERSION.to_string(),
+            jobs: vec![Job {
+                id: 3,
+                name: Some("docs".to_string()),
+                source: vec!["/a".into()],
+                target: vec!["/b".into()],
+                ..Default::default()
+            }],
+        };
+        for format in [Format::Json, Format::Toml, Format::Yaml] {
+            let parsed = parse(&serialize(&doc, format)?, format)?;
+            assert_eq!(parsed.jobs[0].name.as_deref(), Some("docs"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() {
+        let job = Job {
+            source: vec!["relative/dir".into()],
+            target: vec!["/b".into()],
+            ..Default::default()
+        };
+        assert!(validate(&job).is_err());
+        assert!(validate(&Job::default()).is_err());
+
+        // Paths are checked like those of an added job.
+        let job = Job {
+            source: vec!["/data".into(), "/data/docs".into()],
+            target: vec!["/b".into()],
+            ..Default::default()
+        };
+        assert!(validate(&job).is_err());
+        let job = Job {
+            source: vec!["/data/docs".into()],
+            target: vec!["/data".into()],
+            ..Default::default()
+        };
+        assert!(validate(&job).is_err());
+
+        // A template that cannot be expanded here is only warned about.
+        let job = Job {
+            source: vec!["$HBACKUP_TEST_UNSET_VAR/docs".into()],
+            target: vec!["/b".into()],
+            ..Default::default()
+        };
+        assert!(validate(&job).is_ok());
+    }
+}
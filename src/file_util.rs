@@ -5,6 +5,7 @@
 //! - Compress files and directories using various formats (gzip, zip, 7z, zstd, bzip2, xz, lz4, tar)
 //! - Handle different compression levels for each format
 //! - Support ignore lists to exclude specific files/directories from compression
+use crate::cancel;
 use crate::error::HbackupError;
 use crate::job::CompressFormat;
 use crate::job::Level;
@@ -14,8 +15,9 @@ use bzip2::Compression as BzCompression;
 use bzip2::write::BzEncoder;
 use flate2::{Compression, write::GzEncoder};
 use lz4::EncoderBuilder as Lz4EncoderBuilder;
-use sevenz_rust2::ArchiveWriter;
 use sevenz_rust2::encoder_options::Lzma2Options;
+use sevenz_rust2::{ArchiveEntry, ArchiveWriter};
+use std::collections::HashSet;
 use std::io::{BufReader, Read, Write};
 use std::path::PathBuf;
 use std::{fs, io};
@@ -99,6 +101,8 @@ fn create_dir(src: &Path, dest: &Path) -> Result<bool> {
 
 /// Compresses a file or directory at `src` into the `dest` directory using the specified `format` and `level`.
 ///
+/// This is a [`scan`] of `src` followed by [`compress_entries`].
+///
 /// # Arguments
 /// * `src` - The source file or directory to compress.
 /// * `dest` - The destination directory where the compressed file will be placed.
@@ -115,6 +119,248 @@ pub fn compression(
     level: &Level,
     ignore: Option<&[String]>,
 ) -> Result<()> {
+    check_compression_paths(src, dest)?;
+    let entries = scan(src, ignore)?;
+    compress_entries(src, &entries, dest, format, level)
+}
+
+/// Walks `src` and returns every regular file and directory that will be archived.
+///
+/// Symlinks, special files and paths on the ignore list are skipped. For a single
+/// file, the file itself is the only entry.
+pub fn scan(src: &Path, ignore: Option<&[String]>) -> Result<Vec<PathBuf>> {
+    let ignore_paths: Vec<PathBuf> = ignore
+        .map(|dirs| dirs.iter().map(|s| src.join(s)).collect())
+        .unwrap_or_default();
+    scan_excluding(src, &ignore_paths)
+}
+
+/// Walks `src` like [`scan`], skipping everything below the absolute `ignore_paths`.
+pub fn scan_excluding(src: &Path, ignore_paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    if !src.is_dir() {
+        return Ok(vec![src.to_path_buf()]);
+    }
+
+    let mut entries = vec![];
+    for entry in WalkDir::new(src) {
+        if cancel::is_cancelled() {
+            bail!(HbackupError::Cancelled);
+        }
+        let entry = entry?;
+        let path = entry.path();
+        if ignore_paths.iter().any(|p| path.starts_with(p)) {
+            continue;
+        }
+        let file_type = entry.file_type();
+        if file_type.is_dir() || file_type.is_file() {
+            entries.push(path.to_path_buf());
+        }
+    }
+    Ok(entries)
+}
+
+/// Compresses the pre-scanned `entries` of `src` into the `dest` directory.
+///
+/// Entries that disappeared since the scan are skipped. If the compression fails or
+/// is cancelled, the partially written archive is removed and a previous archive is
+/// left as it was.
+///
+/// # Errors
+/// Returns an error if the source does not exist, if the destination is not a
+/// directory, or if any IO error occurs during compression.
+pub fn compress_entries(
+    src: &Path,
+    entries: &[PathBuf],
+    dest: &Path,
+    format: &CompressFormat,
+    level: &Level,
+) -> Result<()> {
+    check_compression_paths(src, dest)?;
+    fs::create_dir_all(dest)?;
+
+    let roots = [Root {
+        path: src,
+        name: get_file_name(src),
+    }];
+    write_archive(
+        &roots,
+        entries,
+        &archive_path(src, dest, format),
+        format,
+        level,
+    )
+}
+
+/// Compresses the pre-scanned `entries` of several `sources` into a single archive
+/// named `name` in the `dest` directory.
+///
+/// Each source is stored under its own top-level name (see [`top_level_names`]).
+/// A single source is compressed exactly like [`compress_entries`] does.
+///
+/// # Errors
+/// Returns an error if a source does not exist, if the destination is not a
+/// directory, or if any IO error occurs during compression.
+pub fn compress_sources(
+    sources: &[PathBuf],
+    entries: &[PathBuf],
+    dest: &Path,
+    name: &str,
+    format: &CompressFormat,
+    level: &Level,
+) -> Result<()> {
+    if let [src] = sources {
+        return compress_entries(src, entries, dest, format, level);
+    }
+    for src in sources {
+        check_compression_paths(src, dest)?;
+    }
+    fs::create_dir_all(dest)?;
+
+    let roots: Vec<Root> = sources
+        .iter()
+        .zip(top_level_names(sources)?)
+        .map(|(path, name)| Root { path, name })
+        .collect();
+    let archive = sources_archive_path(sources, name, dest, format);
+    write_archive(&roots, entries, &archive, format, level)
+}
+
+/// Returns the names the `sources` of a job are stored under in its target.
+///
+/// Each source keeps its file name; when several sources share a name, the later
+/// ones get a numeric suffix before the extension (`notes-2.txt`).
+///
+/// # Errors
+/// Returns an error if a source has no file name, e.g. `/`.
+pub fn top_level_names(sources: &[PathBuf]) -> Result<Vec<String>> {
+    let mut used = HashSet::new();
+    let mut names = Vec::with_capacity(sources.len());
+    for src in sources {
+        let name = src
+            .file_name()
+            .with_context(|| format!("Invalid file name: {src:?}"))?
+            .to_string_lossy()
+            .into_owned();
+        let mut unique = name.clone();
+        let mut n = 2;
+        while !used.insert(unique.clone()) {
+            unique = match name.split_once('.') {
+                Some((stem, ext)) if !stem.is_empty() => format!("{stem}-{n}.{ext}"),
+                _ => format!("{name}-{n}"),
+            };
+            n += 1;
+        }
+        names.push(unique);
+    }
+    Ok(names)
+}
+
+/// Returns the path of the archive that [`compress_sources`] writes into the `dest` directory.
+pub fn sources_archive_path(
+    sources: &[PathBuf],
+    name: &str,
+    dest: &Path,
+    format: &CompressFormat,
+) -> PathBuf {
+    match sources {
+        [src] => archive_path(src, dest, format),
+        // Several sources are always archived as a tree.
+        _ => dest.join(format!("{name}.{}", archive_extension(format, true))),
+    }
+}
+
+/// Returns the regular files among the pre-scanned `entries` of `sources`, each with
+/// the name it is stored under in an archive written by [`compress_sources`].
+///
+/// Directories are left out, since 7z archives of a single source do not store them.
+pub fn archived_files(
+    sources: &[PathBuf],
+    entries: &[PathBuf],
+    format: &CompressFormat,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let files = stored_files(sources, entries)?;
+    match sources {
+        // `push_source_path` stores the files of a single directory relative to it.
+        [src] if *format == CompressFormat::Sevenz && src.is_dir() => files
+            .into_iter()
+            .map(|(path, _)| {
+                let name = path.strip_prefix(src)?.to_path_buf();
+                Ok((path, name))
+            })
+            .collect(),
+        _ => Ok(files),
+    }
+}
+
+/// Returns the regular files among the pre-scanned `entries` of `sources`, each with
+/// its path below the target directory, where every source has its top-level name.
+pub fn stored_files(sources: &[PathBuf], entries: &[PathBuf]) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let roots: Vec<Root> = match sources {
+        [src] => vec![Root {
+            path: src,
+            name: get_file_name(src),
+        }],
+        _ => sources
+            .iter()
+            .zip(top_level_names(sources)?)
+            .map(|(path, name)| Root { path, name })
+            .collect(),
+    };
+    let mut files = vec![];
+    for path in entries {
+        if fs::symlink_metadata(path).is_ok_and(|md| md.is_file()) {
+            files.push((path.clone(), entry_name(&roots, path)?));
+        }
+    }
+    Ok(files)
+}
+
+/// Writes the `entries` of `roots` into `archive`.
+///
+/// The archive is written to a `.partial` file next to it first and only renamed
+/// into place once it is complete, so a failed or cancelled run keeps the previous
+/// archive and removes only the partial one.
+fn write_archive(
+    roots: &[Root],
+    entries: &[PathBuf],
+    archive: &Path,
+    format: &CompressFormat,
+    level: &Level,
+) -> Result<()> {
+    let partial = partial_path(archive);
+    let result = match format {
+        CompressFormat::Gzip => compress_gzip(roots, entries, &partial, level),
+        CompressFormat::Zip => compress_zip(roots, entries, &partial, level),
+        CompressFormat::Sevenz => compress_sevenz(roots, entries, &partial, level),
+        CompressFormat::Zstd => compress_zstd(roots, entries, &partial, level),
+        CompressFormat::Bzip2 => compress_bzip2(roots, entries, &partial, level),
+        CompressFormat::Xz => compress_xz(roots, entries, &partial, level),
+        CompressFormat::Lz4 => compress_lz4(roots, entries, &partial, level),
+        CompressFormat::Tar => compress_tar(roots, entries, &partial),
+    };
+    let result = match result {
+        Ok(()) if cancel::is_cancelled() => Err(HbackupError::Cancelled.into()),
+        Err(_) if cancel::is_cancelled() => Err(HbackupError::Cancelled.into()),
+        Ok(()) => fs::rename(&partial, archive).map_err(Into::into),
+        other => other,
+    };
+    if result.is_err()
+        && partial.exists()
+        && let Err(e) = fs::remove_file(&partial)
+    {
+        eprintln!("Failed to remove partial archive {partial:?}: {e}");
+    }
+    result
+}
+
+/// Returns the path an archive is written to before it is complete.
+pub fn partial_path(archive: &Path) -> PathBuf {
+    let mut name = archive.as_os_str().to_os_string();
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+fn check_compression_paths(src: &Path, dest: &Path) -> Result<()> {
     if !src.exists() {
         bail!(HbackupError::PathNotFound(src.to_path_buf()));
     }
@@ -124,31 +370,78 @@ pub fn compression(
     if dest.exists() && !dest.is_dir() {
         bail!("destination must be a directory");
     }
-    fs::create_dir_all(dest)?;
+    Ok(())
+}
 
-    match format {
-        CompressFormat::Gzip => compress_gzip(src, dest, level, ignore),
-        CompressFormat::Zip => compress_zip(src, dest, level, ignore),
-        CompressFormat::Sevenz => compress_sevenz(src, dest, level, ignore),
-        CompressFormat::Zstd => compress_zstd(src, dest, level, ignore),
-        CompressFormat::Bzip2 => compress_bzip2(src, dest, level, ignore),
-        CompressFormat::Xz => compress_xz(src, dest, level, ignore),
-        CompressFormat::Lz4 => compress_lz4(src, dest, level, ignore),
-        CompressFormat::Tar => compress_tar(src, dest, ignore),
+/// Returns the path of the archive that [`compression`] writes for `src` into the `dest` directory.
+pub fn archive_path(src: &Path, dest: &Path, format: &CompressFormat) -> PathBuf {
+    let file_name = get_file_name(src);
+    let ext = archive_extension(format, src.is_dir());
+    dest.join(format!("{file_name}.{ext}"))
+}
+
+/// Returns the archive extension of `format` for a directory tree or a single file.
+fn archive_extension(format: &CompressFormat, tree: bool) -> &'static str {
+    match (format, tree) {
+        (CompressFormat::Gzip, true) => "tar.gz",
+        (CompressFormat::Gzip, false) => "gz",
+        (CompressFormat::Zip, _) => "zip",
+        (CompressFormat::Sevenz, _) => "7z",
+        (CompressFormat::Zstd, true) => "tar.zst",
+        (CompressFormat::Zstd, false) => "zst",
+        (CompressFormat::Bzip2, true) => "tar.bz2",
+        (CompressFormat::Bzip2, false) => "bz2",
+        (CompressFormat::Xz, true) => "tar.xz",
+        (CompressFormat::Xz, false) => "xz",
+        (CompressFormat::Lz4, true) => "tar.lz4",
+        (CompressFormat::Lz4, false) => "lz4",
+        (CompressFormat::Tar, _) => "tar",
     }
 }
 
-/// Compresses a file or directory at `src` into a gz/tar.gz archive in the `dest` directory.
+/// A source of an archive and the top-level name its entries are stored under.
+struct Root<'a> {
+    path: &'a Path,
+    name: String,
+}
+
+/// Returns the file of an archive with a single file source, which is compressed as
+/// a plain stream instead of a tree.
+fn single_file<'a>(roots: &[Root<'a>]) -> Option<&'a Path> {
+    match roots {
+        [root] if !root.path.is_dir() => Some(root.path),
+        _ => None,
+    }
+}
+
+/// Returns the name `path` is stored under in an archive of `roots`.
+///
+/// A path belongs to the innermost root containing it.
+fn entry_name(roots: &[Root], path: &Path) -> Result<PathBuf> {
+    let root = roots
+        .iter()
+        .filter(|root| path.starts_with(root.path))
+        .max_by_key(|root| root.path.components().count())
+        .with_context(|| format!("{path:?} is not inside any source"))?;
+    let rel = path.strip_prefix(root.path)?;
+    Ok(if rel.as_os_str().is_empty() {
+        PathBuf::from(&root.name)
+    } else {
+        Path::new(&root.name).join(rel)
+    })
+}
+
+/// Compresses the `roots` into a gz/tar.gz archive at `archive`.
 ///
 /// # Arguments
-/// * `src` - The source directory to compress.
-/// * `dest` - The destination directory.
+/// * `roots` - The sources to compress.
+/// * `entries` - The pre-scanned entries of `roots`.
+/// * `archive` - The archive file to create.
 /// * `level` - Compression level.
 ///
 /// # Errors
 /// Returns an error if any IO error occurs.
-fn compress_gzip(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]>) -> Result<()> {
-    let file_name = get_file_name(src);
+fn compress_gzip(roots: &[Root], entries: &[PathBuf], archive: &Path, level: &Level) -> Result<()> {
     let level = match level {
         Level::Fastest => Compression::fast(),
         Level::Faster => Compression::new(3),
@@ -157,40 +450,37 @@ fn compress_gzip(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String
         Level::Best => Compression::best(),
     };
 
-    if src.is_dir() {
-        let dest = dest.join(format!("{file_name}.tar.gz"));
-        let tar_gz = File::create(dest)?;
-
-        let encoder = GzEncoder::new(tar_gz, level);
-        let mut tar_builder = tar::Builder::new(encoder);
-        append_regular_only(&mut tar_builder, src, ignore)?;
-        tar_builder.into_inner()?.finish()?;
-    } else {
-        let dest = dest.join(format!("{file_name}.gz"));
-        let dest_file = File::create(&dest)?;
+    if let Some(src) = single_file(roots) {
+        let dest_file = File::create(archive)?;
 
         let mut reader = BufReader::new(File::open(src)?);
         let mut encoder = GzEncoder::new(dest_file, level);
         io::copy(&mut reader, &mut encoder)?;
         encoder.finish()?;
+    } else {
+        let tar_gz = File::create(archive)?;
+
+        let encoder = GzEncoder::new(tar_gz, level);
+        let mut tar_builder = tar::Builder::new(encoder);
+        append_regular_only(&mut tar_builder, roots, entries)?;
+        tar_builder.into_inner()?.finish()?;
     }
 
     Ok(())
 }
 
-/// Compresses a file or directory at `src` into a zip archive in the `dest` directory.
+/// Compresses the `roots` into a zip archive at `archive`.
 ///
 /// # Arguments
-/// * `src` - The source directory to compress.
-/// * `dest` - The destination directory.
+/// * `roots` - The sources to compress.
+/// * `entries` - The pre-scanned entries of `roots`.
+/// * `archive` - The archive file to create.
 /// * `level` - Compression level (1-9).
 ///
 /// # Errors
 /// Returns an error if any IO error occurs.
-fn compress_zip(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]>) -> Result<()> {
-    let file_name = get_file_name(src);
-    let dest = dest.join(format!("{file_name}.zip"));
-    let dest_file = File::create(dest)?;
+fn compress_zip(roots: &[Root], entries: &[PathBuf], archive: &Path, level: &Level) -> Result<()> {
+    let dest_file = File::create(archive)?;
 
     let mut zip = ZipWriter::new(dest_file);
     let level = match level {
@@ -201,26 +491,25 @@ fn compress_zip(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]
         Level::Best => 9,
     };
     let options = FileOptions::<()>::default().compression_level(Some(level));
-    if src.is_dir() {
-        let prefix = src.parent().unwrap_or_else(|| Path::new(""));
-        let ignore_path = match ignore {
-            Some(ignore) => ignore.iter().map(|s| src.join(s)).collect::<Vec<PathBuf>>(),
-            None => vec![],
-        };
+    if let Some(src) = single_file(roots) {
+        zip.start_file(&roots[0].name, options)?;
 
-        for entry in WalkDir::new(src) {
-            let entry = entry?;
-            let path = entry.path();
-            if ignore_path.iter().any(|p| path.starts_with(p)) {
-                continue;
+        let mut src_file = File::open(src)?;
+        let mut buffer = Vec::new();
+        src_file.read_to_end(&mut buffer)?;
+
+        zip.write_all(&buffer)?;
+        zip.finish()?;
+    } else {
+        for path in entries {
+            if cancel::is_cancelled() {
+                bail!(HbackupError::Cancelled);
             }
+            let Some(md) = entry_metadata(path)? else {
+                continue;
+            };
 
-            let name = path
-                .strip_prefix(prefix)
-                .unwrap()
-                .to_string_lossy()
-                .into_owned();
-            let md = fs::symlink_metadata(path)?;
+            let name = entry_name(roots, path)?.to_string_lossy().into_owned();
             if md.is_dir() {
                 zip.add_directory(name, options)?;
             } else if md.is_file() {
@@ -229,39 +518,31 @@ fn compress_zip(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]
                 io::copy(&mut f, &mut zip)?;
             }
         }
-    } else {
-        zip.start_file(file_name, options)?;
-
-        let mut src_file = File::open(src)?;
-        let mut buffer = Vec::new();
-        src_file.read_to_end(&mut buffer)?;
-
-        zip.write_all(&buffer)?;
-        zip.finish()?;
     }
 
     Ok(())
 }
 
-/// Compresses a file or directory at `src` into a 7z archive in the `dest` directory.
+/// Compresses the `roots` into a 7z archive at `archive`.
+///
+/// A single source is stored relative to itself, as before multiple sources were
+/// supported; several sources are each stored under their top-level name.
 ///
 /// # Arguments
-/// * `src` - The source file or directory to compress.
-/// * `dest` - The destination directory.
+/// * `roots` - The sources to compress.
+/// * `entries` - The pre-scanned entries of `roots`.
+/// * `archive` - The archive file to create.
 /// * `level` - Compression level (1-9).
 ///
 /// # Errors
 /// Returns an error if any IO error occurs or if 7z compression fails.
 fn compress_sevenz(
-    src: &Path,
-    dest: &Path,
+    roots: &[Root],
+    entries: &[PathBuf],
+    archive: &Path,
     level: &Level,
-    ignore: Option<&[String]>,
 ) -> Result<()> {
-    let file_name = get_file_name(src);
-    let dest = dest.join(format!("{file_name}.7z"));
-
-    let mut writer = ArchiveWriter::create(dest)?;
+    let mut writer = ArchiveWriter::create(archive)?;
     let level = match level {
         Level::Fastest => 1,
         Level::Faster => 3,
@@ -271,23 +552,42 @@ fn compress_sevenz(
     };
     let lzma2 = Lzma2Options::from_level(level).into();
     writer.set_content_methods(vec![lzma2]);
-    writer.push_source_path(src, make_filter(src, ignore))?;
+    if let [root] = roots {
+        writer.push_source_path(root.path, make_filter(entries))?;
+    } else {
+        for path in entries {
+            if cancel::is_cancelled() {
+                bail!(HbackupError::Cancelled);
+            }
+            let Some(md) = entry_metadata(path)? else {
+                continue;
+            };
+
+            let name = entry_name(roots, path)?.to_string_lossy().into_owned();
+            let entry = ArchiveEntry::from_path(path, name);
+            if md.is_dir() {
+                writer.push_archive_entry::<File>(entry, None)?;
+            } else if md.is_file() {
+                writer.push_archive_entry(entry, Some(File::open(path)?))?;
+            }
+        }
+    }
     writer.finish()?;
 
     Ok(())
 }
 
-/// Compresses a file or directory at `src` into a zst/tar.zst archive in the `dest` directory.
+/// Compresses the `roots` into a zst/tar.zst archive at `archive`.
 ///
 /// # Arguments
-/// * `src` - The source directory to compress.
-/// * `dest` - The destination directory.
+/// * `roots` - The sources to compress.
+/// * `entries` - The pre-scanned entries of `roots`.
+/// * `archive` - The archive file to create.
 /// * `level` - Compression level (1-22).
 ///
 /// # Errors
 /// Returns an error if any IO error occurs.
-fn compress_zstd(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]>) -> Result<()> {
-    let file_name = get_file_name(src);
+fn compress_zstd(roots: &[Root], entries: &[PathBuf], archive: &Path, level: &Level) -> Result<()> {
     let level = match level {
         Level::Fastest => 1,
         Level::Faster => 2,
@@ -295,36 +595,39 @@ fn compress_zstd(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String
         Level::Better => 19,
         Level::Best => 22,
     };
-    if src.is_dir() {
-        let dest = dest.join(format!("{file_name}.tar.zst"));
-        let tar_zst = File::create(dest)?;
-        let encoder = ZstdEncoder::new(tar_zst, level)?;
-        let mut tar_builder = tar::Builder::new(encoder);
-        append_regular_only(&mut tar_builder, src, ignore)?;
-        tar_builder.into_inner()?.finish()?;
-    } else {
-        let dest = dest.join(format!("{file_name}.zst"));
-        let dest_file = File::create(dest)?;
+    if let Some(src) = single_file(roots) {
+        let dest_file = File::create(archive)?;
         let mut reader = BufReader::new(File::open(src)?);
         let mut encoder = ZstdEncoder::new(dest_file, level)?;
         io::copy(&mut reader, &mut encoder)?;
         encoder.finish()?;
+    } else {
+        let tar_zst = File::create(archive)?;
+        let encoder = ZstdEncoder::new(tar_zst, level)?;
+        let mut tar_builder = tar::Builder::new(encoder);
+        append_regular_only(&mut tar_builder, roots, entries)?;
+        tar_builder.into_inner()?.finish()?;
     }
 
     Ok(())
 }
 
-/// Compresses a file or directory at `src` into a bz/tar.bz2 archive in the `dest` directory.
+/// Compresses the `roots` into a bz/tar.bz2 archive at `archive`.
 ///
 /// # Arguments
-/// * `src` - The source directory to compress.
-/// * `dest` - The destination directory
+/// * `roots` - The sources to compress.
+/// * `entries` - The pre-scanned entries of `roots`.
+/// * `archive` - The archive file to create.
 /// * `level` - Compression level.
 ///
 /// # Errors
 /// Returns an error if any IO error occurs.
-fn compress_bzip2(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]>) -> Result<()> {
-    let file_name = get_file_name(src);
+fn compress_bzip2(
+    roots: &[Root],
+    entries: &[PathBuf],
+    archive: &Path,
+    level: &Level,
+) -> Result<()> {
     let level = match level {
         Level::Fastest => BzCompression::fast(),
         Level::Faster => BzCompression::new(3),
@@ -332,38 +635,36 @@ fn compress_bzip2(src: &Path, dest: &Path, level: &Level, ignore: Option<&[Strin
         Level::Better => BzCompression::new(8),
         Level::Best => BzCompression::best(),
     };
-    if src.is_dir() {
-        let dest = dest.join(format!("{file_name}.tar.bz2"));
-        let tar_bz = File::create(dest)?;
-
-        let encoder = BzEncoder::new(tar_bz, level);
-        let mut tar_builder = tar::Builder::new(encoder);
-        append_regular_only(&mut tar_builder, src, ignore)?;
-        tar_builder.into_inner()?.finish()?;
-    } else {
-        let dest = dest.join(format!("{file_name}.bz2"));
-        let dest_file = File::create(dest)?;
+    if let Some(src) = single_file(roots) {
+        let dest_file = File::create(archive)?;
 
         let mut reader = BufReader::new(File::open(src)?);
         let mut encoder = BzEncoder::new(dest_file, level);
         io::copy(&mut reader, &mut encoder)?;
         encoder.finish()?;
+    } else {
+        let tar_bz = File::create(archive)?;
+
+        let encoder = BzEncoder::new(tar_bz, level);
+        let mut tar_builder = tar::Builder::new(encoder);
+        append_regular_only(&mut tar_builder, roots, entries)?;
+        tar_builder.into_inner()?.finish()?;
     }
 
     Ok(())
 }
 
-/// Compresses a file or directory at `src` into a xz/tar.xz archive in the `dest` directory.
+/// Compresses the `roots` into a xz/tar.xz archive at `archive`.
 ///
 /// # Arguments
-/// * `src` - The source directory to compress.
-/// * `dest` - The destination directory.
+/// * `roots` - The sources to compress.
+/// * `entries` - The pre-scanned entries of `roots`.
+/// * `archive` - The archive file to create.
 /// * `level` - Compression level (1-9).
 ///
 /// # Errors
 /// Returns an error if any IO error occurs.
-fn compress_xz(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]>) -> Result<()> {
-    let file_name = get_file_name(src);
+fn compress_xz(roots: &[Root], entries: &[PathBuf], archive: &Path, level: &Level) -> Result<()> {
     let level = match level {
         Level::Fastest => 1,
         Level::Faster => 3,
@@ -371,38 +672,36 @@ fn compress_xz(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]>
         Level::Better => 8,
         Level::Best => 9,
     };
-    if src.is_dir() {
-        let dest = dest.join(format!("{file_name}.tar.xz"));
-        let tar_xz = File::create(dest)?;
-
-        let encoder = XzEncoder::new(tar_xz, level);
-        let mut tar_builder = tar::Builder::new(encoder);
-        append_regular_only(&mut tar_builder, src, ignore)?;
-        tar_builder.into_inner()?.finish()?;
-    } else {
-        let dest = dest.join(format!("{file_name}.xz"));
-        let dest_file = File::create(dest)?;
+    if let Some(src) = single_file(roots) {
+        let dest_file = File::create(archive)?;
 
         let mut reader = BufReader::new(File::open(src)?);
         let mut encoder = XzEncoder::new(dest_file, level);
         io::copy(&mut reader, &mut encoder)?;
         encoder.finish()?;
+    } else {
+        let tar_xz = File::create(archive)?;
+
+        let encoder = XzEncoder::new(tar_xz, level);
+        let mut tar_builder = tar::Builder::new(encoder);
+        append_regular_only(&mut tar_builder, roots, entries)?;
+        tar_builder.into_inner()?.finish()?;
     }
 
     Ok(())
 }
 
-// Compresses a file or directory at `src` into a lz4/tar.lz4 archive in the `dest` directory.
+// Compresses the `roots` into a lz4/tar.lz4 archive at `archive`.
 ///
 /// # Arguments
-/// * `src` - The source directory to compress.
-/// * `dest` - The destination directory.
+/// * `roots` - The sources to compress.
+/// * `entries` - The pre-scanned entries of `roots`.
+/// * `archive` - The archive file to create.
 /// * `level` - Compression level (1-16).
 ///
 /// # Errors
 /// Returns an error if any IO error occurs.
-fn compress_lz4(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]>) -> Result<()> {
-    let file_name = get_file_name(src);
+fn compress_lz4(roots: &[Root], entries: &[PathBuf], archive: &Path, level: &Level) -> Result<()> {
     let level = match level {
         Level::Fastest => 1,
         Level::Faster => 3,
@@ -410,24 +709,22 @@ fn compress_lz4(src: &Path, dest: &Path, level: &Level, ignore: Option<&[String]
         Level::Better => 14,
         Level::Best => 16,
     };
-    if src.is_dir() {
-        let dest = dest.join(format!("{file_name}.tar.lz4"));
-        let tar_lz = File::create(dest)?;
-
-        let encoder = Lz4EncoderBuilder::new().level(level).build(tar_lz)?;
-        let mut tar_builder = tar::Builder::new(encoder);
-        append_regular_only(&mut tar_builder, src, ignore)?;
-        let (_, result) = tar_builder.into_inner()?.finish();
-        result?;
-    } else {
-        let dest = dest.join(format!("{file_name}.lz4"));
-        let dest_file = File::create(dest)?;
+    if let Some(src) = single_file(roots) {
+        let dest_file = File::create(archive)?;
 
         let mut reader = BufReader::new(File::open(src)?);
         let mut encoder = Lz4EncoderBuilder::new().level(level).build(dest_file)?;
         io::copy(&mut reader, &mut encoder)?;
         let (_, result) = encoder.finish();
         result?;
+    } else {
+        let tar_lz = File::create(archive)?;
+
+        let encoder = Lz4EncoderBuilder::new().level(level).build(tar_lz)?;
+        let mut tar_builder = tar::Builder::new(encoder);
+        append_regular_only(&mut tar_builder, roots, entries)?;
+        let (_, result) = tar_builder.into_inner()?.finish();
+        result?;
     }
 
     Ok(())
@@ -444,78 +741,80 @@ fn get_file_name(file: &Path) -> String {
     file.file_name().unwrap().to_string_lossy().into_owned()
 }
 
-/// Appends only regular files and directories from `src` into the provided tar archive builder.
+/// Appends only regular files and directories from `entries` into the provided tar archive builder.
 ///
-/// This helper skips symlinks and special files for safety and portability.
+/// This helper skips symlinks and special files for safety and portability. Each entry
+/// is stored under the top-level name of the root it belongs to.
 ///
 /// # Arguments
 /// * `tar` - The tar archive builder to append files/directories to.
-/// * `src` - The source directory to walk and archive.
+/// * `roots` - The sources the entries were scanned from.
+/// * `entries` - The pre-scanned entries of `roots`.
 ///
 /// # Errors
-/// Returns an error if any IO error occurs during traversal or archiving.
+/// Returns an error if any IO error occurs during archiving.
 fn append_regular_only<W: Write>(
     tar: &mut Builder<W>,
-    src: &Path,
-    ignore: Option<&[String]>,
+    roots: &[Root],
+    entries: &[PathBuf],
 ) -> Result<()> {
-    let prefix = src.parent().unwrap_or(Path::new(""));
-    let ignore_paths: Vec<PathBuf> = ignore
-        .map(|dirs| dirs.iter().map(|s| src.join(s)).collect())
-        .unwrap_or_default();
-
-    for entry in WalkDir::new(src) {
-        let entry = entry?;
-        let path = entry.path();
-        if ignore_paths.iter().any(|p| path.starts_with(p)) {
-            continue;
+    for path in entries {
+        if cancel::is_cancelled() {
+            bail!(HbackupError::Cancelled);
         }
+        let Some(md) = entry_metadata(path)? else {
+            continue;
+        };
 
-        let rel = path.strip_prefix(prefix).unwrap();
-        let md = fs::symlink_metadata(path)?;
+        let name = entry_name(roots, path)?;
         if md.is_dir() {
-            tar.append_dir(rel, path)?;
+            tar.append_dir(&name, path)?;
         } else if md.is_file() {
-            tar.append_path_with_name(path, rel)?;
+            tar.append_path_with_name(path, &name)?;
         }
     }
     Ok(())
 }
 
-/// Creates a filter function that determines whether a given path should be ignored based on the provided ignore list.
-fn make_filter(base: &Path, ignore: Option<&[String]>) -> impl Fn(&Path) -> bool {
-    let ignore_paths: Vec<PathBuf> = ignore
-        .map(|dirs| dirs.iter().map(|s| base.join(s)).collect())
-        .unwrap_or_default();
-    move |path| !ignore_paths.iter().any(|p| path.starts_with(p))
+/// Returns the metadata of a scanned entry, or `None` if it no longer exists.
+fn entry_metadata(path: &Path) -> Result<Option<fs::Metadata>> {
+    match fs::symlink_metadata(path) {
+        Ok(md) => Ok(Some(md)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("Skipping {path:?}: it no longer exists");
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
 }
 
-/// Compresses a file or directory at `src` into a tar archive in the `dest` directory.
+/// Creates a filter function that only accepts the pre-scanned `entries`.
+///
+/// Once cancellation is requested, every remaining path is rejected.
+fn make_filter(entries: &[PathBuf]) -> impl Fn(&Path) -> bool {
+    let entries: HashSet<PathBuf> = entries.iter().cloned().collect();
+    move |path| !cancel::is_cancelled() && entries.contains(path)
+}
+
+/// Compresses the `roots` into a tar archive at `archive`.
 ///
 /// # Arguments
-/// * `src` - The source file or directory to archive.
-/// * `dest` - The destination directory.
-/// * `ignore` - Optional list of files/directories to ignore.
+/// * `roots` - The sources to archive.
+/// * `entries` - The pre-scanned entries of `roots`.
+/// * `archive` - The archive file to create.
 ///
 /// # Errors
 /// Returns an error if any IO error occurs.
-fn compress_tar(src: &Path, dest: &Path, ignore: Option<&[String]>) -> Result<()> {
-    let file_name = get_file_name(src);
-
-    if src.is_dir() {
-        let dest = dest.join(format!("{file_name}.tar"));
-        let tar_file = File::create(dest)?;
-        let mut tar_builder = tar::Builder::new(tar_file);
-        append_regular_only(&mut tar_builder, src, ignore)?;
-        tar_builder.into_inner()?;
-    } else {
+fn compress_tar(roots: &[Root], entries: &[PathBuf], archive: &Path) -> Result<()> {
+    let tar_file = File::create(archive)?;
+    let mut tar_builder = tar::Builder::new(tar_file);
+    if let Some(src) = single_file(roots) {
         // For single files, create a tar archive containing just that file
-        let dest = dest.join(format!("{file_name}.tar"));
-        let tar_file = File::create(dest)?;
-        let mut tar_builder = tar::Builder::new(tar_file);
-        tar_builder.append_path_with_name(src, file_name)?;
-        tar_builder.into_inner()?;
+        tar_builder.append_path_with_name(src, &roots[0].name)?;
+    } else {
+        append_regular_only(&mut tar_builder, roots, entries)?;
     }
+    tar_builder.into_inner()?;
 
     Ok(())
 }
@@ -0,0 +1,243 @@
+//! Shell hooks that run around a backup job.
+//!
+//! A job may define a `pre_hook` that runs before the backup, a `post_hook` that runs
+//! after a successful backup and an `on_failure` hook that runs when the pre-hook or
+//! the backup itself fails. Hooks run through the platform shell with environment
+//! variables describing the job.
+//!
+//! A hook that is still running when the run is cancelled is stopped, together with
+//! the processes it started.
+use crate::cancel;
+use crate::error::HbackupError;
+use crate::job::Job;
+use anyhow::{Context, Result, bail};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval at which a running hook is polled for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs the pre-hook of `job`, if any. A failing pre-hook aborts the job.
+pub(crate) fn pre(job: &Job) -> Result<()> {
+    match &job.pre_hook {
+        Some(command) => run(job, "pre", command, "running", None),
+        None => Ok(()),
+    }
+}
+
+/// Runs the post-hook of `job`, if any, after a successful backup.
+pub(crate) fn post(job: &Job) -> Result<()> {
+    match &job.post_hook {
+        Some(command) => run(job, "post", command, "success", None),
+        None => Ok(()),
+    }
+}
+
+/// Runs the failure hook of `job`, if any. Errors of the hook itself are only reported,
+/// so they do not hide the original failure.
+pub(crate) fn failure(job: &Job, status: &str, err: &anyhow::Error) {
+    if let Some(command) = &job.on_failure
+        && let Err(e) = run(job, "on_failure", command, status, Some(err))
+    {
+        eprintln!("{e}");
+    }
+}
+
+fn run(
+    job: &Job,
+    hook: &'static str,
+    command: &str,
+    status: &str,
+    err: Option<&anyhow::Error>,
+) -> Result<()> {
+    let mut cmd = shell(command);
+    cmd.env("HBACKUP_JOB_ID", job.id.to_string())
+        .env("HBACKUP_SOURCE", join(&job.source))
+        .env("HBACKUP_TARGET", join(&job.target))
+        .env("HBACKUP_STATUS", status);
+    if job.compression.is_some() {
+        let archives: Vec<_> = job
+            .target
+            .iter()
+            .filter_map(|t| job.archive_path(t))
+            .collect();
+        cmd.env("HBACKUP_ARCHIVE", join(&archives));
+    }
+    if let Some(err) = err {
+        cmd.env("HBACKUP_ERROR", err.to_string());
+    }
+
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("failed to start {hook} hook `{command}`"))?;
+    // A failure hook that reports a cancellation must not be stopped by it.
+    let cancellable = !cancel::is_cancelled();
+    let deadline = job
+        .hook_timeout
+        .map(|secs| (secs, Instant::now() + Duration::from_secs(secs)));
+    let exit = loop {
+        if let Some(exit) = child.try_wait()? {
+            break exit;
+        }
+        if cancellable && cancel::is_cancelled() {
+            kill(&mut child);
+            let _ = child.wait();
+            bail!(HbackupError::Cancelled);
+        }
+        if let Some((secs, deadline)) = deadline
+            && Instant::now() >= deadline
+        {
+            kill(&mut child);
+            let _ = child.wait();
+            bail!(HbackupError::HookFailed(
+                hook,
+                format!("timed out after {secs}s")
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    if !exit.success() {
+        bail!(HbackupError::HookFailed(hook, exit.to_string()));
+    }
+    Ok(())
+}
+
+/// Passes a single source, target or archive as it is, and puts several of them on
+/// lines of their own.
+fn join(paths: &[PathBuf]) -> OsString {
+    let mut joined = OsString::new();
+    for (i, path) in paths.iter().enumerate() {
+        if i > 0 {
+            joined.push("\n");
+        }
+        joined.push(path);
+    }
+    joined
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    use std::os::unix::process::CommandExt;
+    let mut cmd = Command::new("sh");
+    // A group of its own lets a timeout stop everything the hook started.
+    cmd.arg("-c").arg(command).process_group(0);
+    cmd
+}
+
+/// Stops a hook together with the processes it started.
+#[cfg(not(windows))]
+fn kill(child: &mut Child) {
+    // The hook leads its own process group, see `shell`.
+    let group = child.id() as libc::pid_t;
+    // SAFETY: `killpg` only sends a signal and has no memory safety requirements.
+    if unsafe { libc::killpg(group, libc::SIGKILL) } != 0 {
+        let _ = child.kill();
+    }
+}
+
+#[cfg(windows)]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn job_with_hooks(dir: &TempDir) -> Job {
+        Job {
+            id: 7,
+            source: vec![dir.path().join("src")],
+            target: vec![dir.path().join("dst")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hook_environment() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let out = temp_dir.path().join("env.txt");
+        let mut job = job_with_hooks(&temp_dir);
+        job.pre_hook = Some(format!(
+            "echo \"$HBACKUP_JOB_ID $HBACKUP_STATUS $HBACKUP_SOURCE\" > '{}'",
+            out.display()
+        ));
+
+        pre(&job)?;
+        let env = fs::read_to_string(&out)?;
+        assert_eq!(env.trim(), format!("7 running {}", job.source[0].display()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failing_hook() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut job = job_with_hooks(&temp_dir);
+        job.post_hook = Some("exit 3".to_string());
+
+        let err = post(&job).unwrap_err();
+        assert!(err.to_string().contains("post hook failed"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hook_timeout() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut job = job_with_hooks(&temp_dir);
+        job.pre_hook = Some("sleep 5".to_string());
+        job.hook_timeout = Some(0);
+
+        let err = pre(&job).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hook_timeout_stops_started_processes() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let marker = temp_dir.path().join("marker");
+        let mut job = job_with_hooks(&temp_dir);
+        job.pre_hook = Some(format!("(sleep 1; touch '{}') & sleep 5", marker.display()));
+        job.hook_timeout = Some(0);
+
+        assert!(pre(&job).is_err());
+        thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hook_paths_with_separator() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let out = temp_dir.path().join("env.txt");
+        let mut job = job_with_hooks(&temp_dir);
+        job.source = vec![temp_dir.path().join("a:b")];
+        job.target = vec![temp_dir.path().join("c"), temp_dir.path().join("d:e")];
+        job.pre_hook = Some(format!(
+            "printf '%s|%s' \"$HBACKUP_SOURCE\" \"$HBACKUP_TARGET\" > '{}'",
+            out.display()
+        ));
+
+        pre(&job)?;
+        let expected = format!(
+            "{}|{}\n{}",
+            job.source[0].display(),
+            job.target[0].display(),
+            job.target[1].display()
+        );
+        assert_eq!(fs::read_to_string(&out)?, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+//! Verification of the archives written by compressed jobs.
+//!
+//! An archive is verified by decoding it completely, which catches truncated or
+//! corrupted files that the compressors would otherwise only report on restore, and
+//! by comparing the files it holds with the [manifest](crate::manifest) written
+//! next to it, or with the sources of its job if there is none. A deep verification
+//! also compares the size and SHA-256 hash of every file.
+//!
+//! The copied tree of an uncompressed job can only be verified against its manifest.
+
+use crate::file_util;
+use crate::job::{CompressFormat, Job};
+use crate::manifest::Manifest;
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// A regular file stored in an archive or expected in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Name of the file inside the archive.
+    pub name: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Hex-encoded SHA-256 hash of the content, if it was computed.
+    pub sha256: Option<String>,
+}
+
+/// The result of comparing an archive with what it should contain.
+#[derive(Debug, Default, PartialEq)]
+pub struct Outcome {
+    /// Number of files in the archive.
+    pub files: usize,
+    /// Files that are expected but not in the archive.
+    pub missing: Vec<PathBuf>,
+    /// Files in the archive that are not expected.
+    pub unexpected: Vec<PathBuf>,
+    /// Files whose size or hash differs from the expected one.
+    pub changed: Vec<PathBuf>,
+}
+
+impl Outcome {
+    /// Returns `true` if the archive holds exactly the expected files.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.changed.is_empty()
+    }
+
+    /// Returns a one-line summary of the differences, e.g. "2 missing, 1 changed".
+    pub fn summary(&self) -> String {
+        let parts: Vec<_> = [
+            (self.missing.len(), "missing"),
+            (self.unexpected.len(), "unexpected"),
+            (self.changed.len(), "changed"),
+        ]
+        .into_iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, what)| format!("{n} {what}"))
+        .collect();
+        if parts.is_empty() {
+            format!("{} files ok", self.files)
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+/// Verifies the `archive` a compressed job wrote into one of its targets against
+/// its manifest, or against the current sources of the job if the archive has no
+/// manifest. Expects expanded paths.
+///
+/// With `deep`, the size and hash of every file are compared as well, and so is the
+/// hash of the archive recorded in the manifest.
+///
+/// # Errors
+/// Returns an error if the archive cannot be decoded completely, or if the manifest
+/// or the sources cannot be read.
+pub fn verify_archive(job: &Job, archive: &Path, deep: bool) -> Result<Outcome> {
+    let manifest = match archive.parent() {
+        Some(dir) => Manifest::read(dir, job.id)?,
+        None => None,
+    }
+    .filter(|m| m.archive.as_deref().map(Path::new) == archive.file_name().map(Path::new));
+    if let Some(manifest) = manifest {
+        return verify_with_manifest(job, archive, &manifest, deep);
+    }
+    let Some(format) = &job.compression else {
+        bail!("job {} has no compression format", job.id);
+    };
+    let stored = decode(job, archive, format, deep)?;
+    let expected = source_entries(job, format, deep)?;
+    Ok(compare(&stored, &expected))
+}
+
+/// Verifies the `archive` of a compressed job against the given `manifest`, like
+/// [`verify_archive`] does.
+pub fn verify_with_manifest(
+    job: &Job,
+    archive: &Path,
+    manifest: &Manifest,
+    deep: bool,
+) -> Result<Outcome> {
+    let Some(format) = &job.compression else {
+        bail!("job {} has no compression format", job.id);
+    };
+    let stored = decode(job, archive, format, deep)?;
+    let mut outcome = compare(&stored, &manifest.entries());
+    if deep
+        && let Some(want) = &manifest.archive_sha256
+        && Some(want) != digest(File::open(archive)?, true)?.1.as_ref()
+        && let Some(name) = archive.file_name()
+    {
+        outcome.changed.push(name.into());
+    }
+    Ok(outcome)
+}
+
+/// Decodes the `archive` of a compressed job, see [`read_archive`].
+fn decode(job: &Job, archive: &Path, format: &CompressFormat, hash: bool) -> Result<Vec<Entry>> {
+    let tree = job.single_file().is_none();
+    read_archive(archive, format, tree, hash)
+        .with_context(|| format!("cannot decode {}", archive.display()))
+}
+
+/// Verifies the files an uncompressed job copied into `target` against the
+/// `manifest` of that target.
+///
+/// Only the files listed in the manifest are checked; with `deep`, their content is
+/// hashed and compared as well, otherwise only their size.
+pub fn verify_tree(target: &Path, manifest: &Manifest, deep: bool) -> Result<Outcome> {
+    let mut outcome = Outcome::default();
+    for file in &manifest.files {
+        let path = target.join(&file.path);
+        let Ok(md) = fs::metadata(&path) else {
+            outcome.missing.push(PathBuf::from(&file.path));
+            continue;
+        };
+        outcome.files += 1;
+        let same = md.len() == file.size
+            && (!deep
+                || digest(File::open(&path)?, true)?.1.as_deref() == Some(file.sha256.as_str()));
+        if !same {
+            outcome.changed.push(PathBuf::from(&file.path));
+        }
+    }
+    Ok(outcome)
+}
+
+/// Decodes `archive` completely and returns the regular files it holds.
+///
+/// `tree` tells whether the archive holds a directory tree or, for the stream
+/// formats, a single compressed file. With `hash`, the content of every file is
+/// hashed while it is decoded.
+pub fn read_archive(
+    archive: &Path,
+    format: &CompressFormat,
+    tree: bool,
+    hash: bool,
+) -> Result<Vec<Entry>> {
+    match format {
+        CompressFormat::Zip => read_zip(archive, hash),
+        CompressFormat::Sevenz => read_sevenz(archive, hash),
+        _ => {
+            let decoder = decoder(File::open(archive)?, format)?;
+            if tree || *format == CompressFormat::Tar {
+                read_tar(decoder, hash)
+            } else {
+                // A single file is stored as a plain stream named after it.
+                let name = archive.file_stem().context("invalid archive name")?;
+                let (size, sha256) = digest(decoder, hash)?;
+                Ok(vec![Entry {
+                    name: name.into(),
+                    size,
+                    sha256,
+                }])
+            }
+        }
+    }
+}
+
+/// Returns the files a compressed job stores from its sources, named as in its archive.
+pub fn source_entries(job: &Job, format: &CompressFormat, hash: bool) -> Result<Vec<Entry>> {
+    let scanned = job.scan_sources()?;
+    let mut entries = vec![];
+    for (path, name) in file_util::archived_files(&job.source, &scanned, format)? {
+        let file = File::open(&path).with_context(|| format!("cannot read {}", path.display()))?;
+        let (size, sha256) = digest(file, hash)?;
+        entries.push(Entry { name, size, sha256 });
+    }
+    Ok(entries)
+}
+
+/// Compares the `stored` files of an archive with the `expected` ones.
+///
+/// Sizes and hashes are only compared where both sides have a hash, so a shallow
+/// verification only checks that the same files are present.
+pub fn compare(stored: &[Entry], expected: &[Entry]) -> Outcome {
+    let stored_by_name: BTreeMap<_, _> = stored.iter().map(|e| (&e.name, e)).collect();
+    let expected_by_name: BTreeMap<_, _> = expected.iter().map(|e| (&e.name, e)).collect();
+    let mut outcome = Outcome {
+        files: stored.len(),
+        ..Default::default()
+    };
+    for (name, want) in &expected_by_name {
+        match stored_by_name.get(name) {
+            None => outcome.missing.push(name.to_path_buf()),
+            Some(got) => {
+                if let (Some(a), Some(b)) = (&got.sha256, &want.sha256)
+                    && (a != b || got.size != want.size)
+                {
+                    outcome.changed.push(name.to_path_buf());
+                }
+            }
+        }
+    }
+    outcome.unexpected = stored_by_name
+        .keys()
+        .filter(|name| !expected_by_name.contains_key(*name))
+        .map(|name| name.to_path_buf())
+        .collect();
+    outcome
+}
+
+/// Reads `reader` to the end, returning its length and, with `hash`, its hash.
+pub(crate) fn digest(mut reader: impl Read, hash: bool) -> io::Result<(u64, Option<String>)> {
+    if hash {
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut reader, &mut hasher)?;
+        Ok((size, Some(format!("{:x}", hasher.finalize()))))
+    } else {
+        Ok((io::copy(&mut reader, &mut io::sink())?, None))
+    }
+}
+
+/// Returns a reader decompressing `file` in one of the stream formats.
+fn decoder(file: File, format: &CompressFormat) -> Result<Box<dyn Read>> {
+    Ok(match format {
+        CompressFormat::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+        CompressFormat::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+        CompressFormat::Bzip2 => Box::new(bzip2::read::BzDecoder::new(file)),
+        CompressFormat::Xz => Box::new(xz2::read::XzDecoder::new(file)),
+        CompressFormat::Lz4 => Box::new(lz4::Decoder::new(file)?),
+        CompressFormat::Tar => Box::new(file),
+        CompressFormat::Zip | CompressFormat::Sevenz => {
+            bail!("{format:?} is not a stream format")
+        }
+    })
+}
+
+fn read_tar(reader: impl Read, hash: bool) -> Result<Vec<Entry>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.into_owned();
+        let (size, sha256) = digest(entry, hash)?;
+        entries.push(Entry { name, size, sha256 });
+    }
+    // Decode the rest of the stream, so a corrupted end is noticed as well.
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    Ok(entries)
+}
+
+fn read_zip(archive: &Path, hash: bool) -> Result<Vec<Entry>> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+    let mut entries = vec![];
+    for i in 0..zip.len() {
+        let file = zip.by_index(i)?;
+        if !file.is_file() {
+            continue;
+        }
+        let name = file
+            .enclosed_name()
+            .with_context(|| format!("unsafe entry name {:?}", file.name()))?;
+        // The CRC of an entry is checked once it has been read completely.
+        let (size, sha256) = digest(file, hash)?;
+        entries.push(Entry { name, size, sha256 });
+    }
+    Ok(entries)
+}
+
+fn read_sevenz(archive: &Path, hash: bool) -> Result<Vec<Entry>> {
+    let mut reader = sevenz_rust2::ArchiveReader::open(archive, sevenz_rust2::Password::empty())?;
+    let mut entries = vec![];
+    reader.for_each_entries(|entry, data| {
+        if !entry.is_directory() {
+            let (size, sha256) = digest(data, hash)?;
+            entries.push(Entry {
+                name: entry.name().into(),
+                size,
+                sha256,
+            });
+        }
+        Ok(true)
+    })?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::Level;
+    use std::fs;
+
+    #[test]
+    fn test_verify_archive_all_formats() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        fs::create_dir_all(src.join("sub"))?;
+        fs::write(src.join("a.txt"), "a")?;
+        fs::write(src.join("sub").join("b.txt"), "bb")?;
+        let target = temp_dir.path().join("backup");
+
+        for format in [
+            CompressFormat::Gzip,
+            CompressFormat::Zip,
+            CompressFormat::Sevenz,
+            CompressFormat::Zstd,
+            CompressFormat::Bzip2,
+            CompressFormat::Xz,
+            CompressFormat::Lz4,
+            CompressFormat::Tar,
+        ] {
+            let job = Job::temp_job(
+                vec![src.clone()],
+                vec![target.clone()],
+                Some(format.clone()),
+                None,
+                None,
+                None,
+            );
+            file_util::compression(&src, &target, &format, &Level::Fastest, None)?;
+            let archive = job.archive_path(&target).unwrap();
+            let outcome = verify_archive(&job, &archive, true)?;
+            assert!(outcome.is_ok(), "{format:?}: {outcome:?}");
+            assert_eq!(outcome.files, 2, "{format:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_archive_detects_changes() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("notes.txt");
+        fs::write(&src, "notes")?;
+        let target = temp_dir.path().join("backup");
+        let job = Job::temp_job(
+            vec![src.clone()],
+            vec![target.clone()],
+            Some(CompressFormat::Zstd),
+            None,
+            None,
+            None,
+        );
+        file_util::compression(&src, &target, &CompressFormat::Zstd, &Level::Default, None)?;
+        let archive = job.archive_path(&target).unwrap();
+        assert!(verify_archive(&job, &archive, true)?.is_ok());
+
+        fs::write(&src, "changed notes")?;
+        assert!(verify_archive(&job, &archive, false)?.is_ok());
+        let outcome = verify_archive(&job, &archive, true)?;
+        assert_eq!(outcome.changed, vec![PathBuf::from("notes.txt")]);
+
+        // A truncated archive cannot be decoded.
+        let data = fs::read(&archive)?;
+        fs::write(&archive, &data[..data.len() / 2])?;
+        assert!(verify_archive(&job, &archive, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare() {
+        let entry = |name: &str, hash: Option<&str>| Entry {
+            name: name.into(),
+            size: 1,
+            sha256: hash.map(str::to_string),
+        };
+        let stored = [entry("a", Some("1")), entry("b", Some("2"))];
+        let expected = [entry("a", Some("9")), entry("c", None)];
+        let outcome = compare(&stored, &expected);
+        assert_eq!(outcome.files, 2);
+        assert_eq!(outcome.missing, vec![PathBuf::from("c")]);
+        assert_eq!(outcome.unexpected, vec![PathBuf::from("b")]);
+        assert_eq!(outcome.changed, vec![PathBuf::from("a")]);
+        assert_eq!(outcome.summary(), "1 missing, 1 unexpected, 1 changed");
+    }
+}
@@ -1,13 +1,13 @@
+use crate::error::HbackupError;
 use crate::file_util;
 use crate::job::{BackupModel, Job};
 use anyhow::Context;
-use anyhow::Result;
+use anyhow::{Result, bail};
 use std::collections::HashSet;
 use std::time::{Duration, SystemTime};
 use std::{
     fs,
     path::{Path, PathBuf},
-    process,
 };
 use walkdir::WalkDir;
 
@@ -28,31 +28,38 @@ impl Item {
     fn new_delete(path: &Path) -> Self {
         Item::Delete(path.to_path_buf())
     }
+
+    /// Returns the path this item writes to or deletes.
+    pub(crate) fn dest(&self) -> &Path {
+        match self {
+            Item::Copy { dest, .. } => dest,
+            Item::Delete(dest) => dest,
+        }
+    }
 }
 
-pub(crate) fn get_item(job: Job) -> Result<Option<Item>> {
-    let src = job.source;
+pub(crate) fn get_item(job: &Job, target: &Path) -> Result<Option<Item>> {
+    let Some(src) = job.source.first() else {
+        bail!("job {} has no source", job.id);
+    };
     if !src.exists() {
-        eprintln!("The path {src:?} is not exists");
-        process::exit(1);
+        bail!(HbackupError::PathNotFound(src.clone()));
     } else if !src.is_file() {
-        eprintln!("The path {src:?} is not file");
-        process::exit(1);
+        bail!("{} is not a file", src.display());
     }
 
-    let dest = job.target;
-    let dest = if dest.exists() && dest.is_dir() {
+    let dest = if target.exists() && target.is_dir() {
         let file_name = src.file_name().with_context(|| "Invalid file name")?;
-        dest.join(file_name)
+        target.join(file_name)
     } else {
-        dest
+        target.to_path_buf()
     };
-    let model = job.model.unwrap_or_default();
+    let model = job.model.clone().unwrap_or_default();
     match model {
-        BackupModel::Full => Ok(Some(Item::new_copy(&src, &dest))),
+        BackupModel::Full => Ok(Some(Item::new_copy(src, &dest))),
         BackupModel::Mirror => {
-            if needs_update(&src, &dest)? {
-                Ok(Some(Item::new_copy(&src, &dest)))
+            if needs_update(src, &dest)? {
+                Ok(Some(Item::new_copy(src, &dest)))
             } else {
                 Ok(None)
             }
@@ -60,34 +67,109 @@ pub(crate) fn get_item(job: Job) -> Result<Option<Item>> {
     }
 }
 
-pub(crate) fn get_items(job: Job) -> Result<Vec<Item>> {
-    let src = job.source;
-    if !src.exists() {
-        eprintln!("The path {src:?} is not exists");
-        process::exit(1);
-    } else if !src.is_dir() {
-        eprintln!("The path {src:?} is not directory");
-        process::exit(1);
+/// Plans the items of a job that lays its sources out inside the `target` directory,
+/// each under its top-level name (see [`file_util::top_level_names`]).
+pub(crate) fn get_items(job: &Job, target: &Path) -> Result<Vec<Item>> {
+    let names = file_util::top_level_names(&job.source)?;
+    let model = job.model.clone().unwrap_or_default();
+    let mut items = vec![];
+    for (src, name) in job.source.iter().zip(names) {
+        if !src.exists() {
+            bail!(HbackupError::PathNotFound(src.clone()));
+        }
+        let dest = target.join(name);
+        if src.is_dir() {
+            let ignore_paths = job.excluded_paths(src);
+            items.extend(plan_tree(src, src, &dest, &ignore_paths, model.clone())?);
+        } else if model == BackupModel::Full || needs_update(src, &dest)? {
+            items.push(Item::new_copy(src, &dest));
+        }
+    }
+    Ok(items)
+}
+
+/// Plans the items that bring the `target` of a directory job up to date with the
+/// `changed` source paths only, instead of walking the whole source.
+///
+/// Changed directories are planned as a whole; paths that no longer exist are removed
+/// from the target of a mirror job.
+pub(crate) fn get_changed_items(
+    job: &Job,
+    target: &Path,
+    changed: &[PathBuf],
+) -> Result<Vec<Item>> {
+    let names = file_util::top_level_names(&job.source)?;
+    let mut items = vec![];
+    for (src, name) in job.source.iter().zip(names) {
+        let dest = target.join(name);
+        items.extend(get_changed_source_items(job, src, &dest, changed)?);
     }
+    Ok(items)
+}
 
-    let model = job.model.unwrap_or_default();
-    let src_name = src.file_name().with_context(|| "Invalid file name")?;
-    let dest = job.target.join(src_name);
+/// Plans the items for the `changed` paths inside one source of a job, which is
+/// backed up to `dest`.
+fn get_changed_source_items(
+    job: &Job,
+    src: &Path,
+    dest: &Path,
+    changed: &[PathBuf],
+) -> Result<Vec<Item>> {
+    let model = job.model.clone().unwrap_or_default();
+    let ignore_paths = job.excluded_paths(src);
+
+    let mut paths: Vec<_> = changed
+        .iter()
+        .filter(|p| p.starts_with(src) && !ignore_paths.iter().any(|i| p.starts_with(i)))
+        .collect();
+    paths.sort();
+    paths.dedup();
+    // A changed path below another changed path is covered by its ancestor.
+    let mut roots: Vec<&PathBuf> = vec![];
+    for path in paths {
+        if !roots.iter().any(|root| path.starts_with(root)) {
+            roots.push(path);
+        }
+    }
 
-    // keep previous behavior of including the src dir name in relative path by using parent
     let mut items = vec![];
-    let ignore_paths: Vec<_> = job
-        .ignore
-        .as_ref()
-        .map(|dirs| dirs.iter().map(|s| src.join(s)).collect())
-        .unwrap_or_default();
+    for root in roots {
+        let rel = root.strip_prefix(src)?;
+        let root_dest = if rel.as_os_str().is_empty() {
+            dest.to_path_buf()
+        } else {
+            dest.join(rel)
+        };
+        if root.is_dir() {
+            items.extend(plan_tree(src, root, dest, &ignore_paths, model.clone())?);
+        } else if root.exists() {
+            if model == BackupModel::Full || needs_update(root, &root_dest)? {
+                items.push(Item::new_copy(root, &root_dest));
+            }
+        } else if model == BackupModel::Mirror && root_dest.symlink_metadata().is_ok() {
+            items.push(Item::new_delete(&root_dest));
+        }
+    }
+    Ok(items)
+}
 
+/// Plans the items for the `root` subtree of the `src` directory, which is backed up
+/// to `dest`.
+fn plan_tree(
+    src: &Path,
+    root: &Path,
+    dest: &Path,
+    ignore_paths: &[PathBuf],
+    model: BackupModel,
+) -> Result<Vec<Item>> {
+    // keep previous behavior of including the src dir name in relative path by using parent
+    let mut items = vec![];
     let mut dest_set = HashSet::new();
 
-    for entry in WalkDir::new(&src) {
+    for entry in WalkDir::new(root) {
         let entry = entry?;
         let entry_path = entry.path();
-        let rel = entry_path.strip_prefix(&src)?;
+        let rel = entry_path.strip_prefix(src)?;
         let dest = dest.join(rel);
         if ignore_paths.iter().any(|p| entry_path.starts_with(p)) {
             continue;
@@ -106,6 +188,7 @@ pub(crate) fn get_items(job: Job) -> Result<Vec<Item>> {
     }
 
     if let BackupModel::Mirror = model {
+        let dest = dest.join(root.strip_prefix(src)?);
         if !dest.exists() {
             return Ok(items);
         }
@@ -209,11 +292,14 @@ fn needs_update(src: &Path, dest: &Path) -> Result<bool> {
 
     let s_mod = sm.modified().unwrap_or(SystemTime::UNIX_EPOCH);
     let d_mod = dm.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    Ok(is_newer(s_mod, d_mod))
+}
+
+/// Returns `true` if a source modified at `src` is newer than its copy modified at
+/// `dest`, allowing for file systems with coarse timestamps.
+pub(crate) fn is_newer(src: SystemTime, dest: SystemTime) -> bool {
     const TOLERANCE: Duration = Duration::from_secs(1);
-    if s_mod > d_mod + TOLERANCE {
-        return Ok(true);
-    }
-    Ok(false)
+    src > dest + TOLERANCE
 }
 
 #[cfg(test)]
@@ -292,4 +378,40 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_get_changed_items_mirror() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        let target = temp_dir.path().join("back");
+        fs::create_dir_all(src.join("sub"))?;
+        fs::create_dir_all(target.join("docs"))?;
+        let edited = create_test_file(&src, "a.txt", b"new");
+        create_test_file(&src, "b.txt", b"untouched");
+        create_test_file(&src.join("sub"), "c.txt", b"c");
+        let removed = create_test_file(&target.join("docs"), "gone.txt", b"old");
+        let job = Job {
+            source: vec![src.clone()],
+            target: vec![target.clone()],
+            model: Some(BackupModel::Mirror),
+            ..Default::default()
+        };
+
+        let changed = [
+            edited.clone(),
+            src.join("gone.txt"),
+            src.join("sub"),
+            src.join("sub").join("c.txt"),
+        ];
+        let items = get_changed_items(&job, &target, &changed)?;
+        let dests: Vec<_> = items.iter().map(|item| item.dest().to_path_buf()).collect();
+        assert!(dests.contains(&target.join("docs").join("a.txt")));
+        assert!(dests.contains(&target.join("docs").join("sub").join("c.txt")));
+        assert!(!dests.contains(&target.join("docs").join("b.txt")));
+        assert!(matches!(
+            items.iter().find(|i| i.dest() == removed),
+            Some(Item::Delete(_))
+        ));
+        Ok(())
+    }
 }
@@ -1,21 +1,39 @@
-use crate::file_util;
-use crate::item::{execute_item, execute_item_async, get_item, get_items};
-use anyhow::{Result, bail};
+use crate::error::HbackupError;
+use crate::item::{Item, execute_item, execute_item_async, get_changed_items, get_item, get_items};
+use crate::journal::Journal;
+use crate::manifest::Manifest;
+use crate::report::{RunReport, Status};
+use crate::{cancel, file_util, hook, integrity, lock, parity, template};
+use anyhow::{Result, anyhow, bail};
 use clap::ValueEnum;
 use futures::{StreamExt, stream::FuturesUnordered};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use std::{fmt, fs};
 use tokio::runtime::Builder as runtimeBuilder;
 
 /// Represents a single backup job with a unique id, source, target, and optional compression.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Job {
     /// Unique job id.
     pub id: u32,
-    /// Source file or directory path.
-    pub source: PathBuf,
-    /// Target file or directory path.
-    pub target: PathBuf,
+    /// Optional unique name the job can be addressed by instead of its id.
+    pub name: Option<String>,
+    /// Optional tags used to select groups of jobs.
+    pub tags: Option<Vec<String>>,
+    /// Whether `bk run` runs the job. Jobs are enabled unless this is `false`.
+    pub enabled: Option<bool>,
+    /// Source files or directories. Each source is backed up under its own name in the target.
+    ///
+    /// Paths are stored as templates and expanded when the job runs.
+    pub source: Vec<PathBuf>,
+    /// Target files or directories. Every target receives a full copy of the backup.
+    ///
+    /// Paths are stored as templates and expanded when the job runs.
+    pub target: Vec<PathBuf>,
     /// Optional compression format for this job.
     pub compression: Option<CompressFormat>,
     /// Optional compression level for this job.
@@ -24,6 +42,21 @@ pub struct Job {
     pub ignore: Option<Vec<String>>,
     /// Backup model
     pub model: Option<BackupModel>,
+    /// Shell command run before the backup. A failing pre-hook aborts the job.
+    pub pre_hook: Option<String>,
+    /// Shell command run after a successful backup.
+    pub post_hook: Option<String>,
+    /// Shell command run when the pre-hook or the backup fails.
+    pub on_failure: Option<String>,
+    /// Timeout in seconds for each hook. Hooks run without a timeout if unset.
+    pub hook_timeout: Option<u64>,
+    /// Schedule used by `bk daemon`: a cron expression, `@daily` or `every 6h`.
+    pub schedule: Option<String>,
+    /// Whether the archive of a compressed job is verified right after it is written.
+    pub verify: Option<bool>,
+    /// Size of the Reed-Solomon recovery records written beside the archive of a
+    /// compressed job, in percent of the archive.
+    pub parity: Option<u8>,
 }
 
 /// Supported compression formats for backup jobs.
@@ -58,8 +91,8 @@ pub enum BackupModel {
 
 impl Job {
     pub fn temp_job(
-        source: PathBuf,
-        target: PathBuf,
+        source: Vec<PathBuf>,
+        target: Vec<PathBuf>,
         compression: Option<CompressFormat>,
         level: Option<Level>,
         ignore: Option<Vec<String>>,
@@ -73,155 +106,985 @@ impl Job {
             level,
             ignore,
             model,
+            ..Default::default()
         }
     }
-}
 
-pub fn display_jobs(jobs: Vec<Job>) -> String {
-    if jobs.is_empty() {
-        return String::new();
+    /// Returns `true` unless the job was disabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
     }
-    let mut s = String::from('[');
-    for job in jobs {
-        let comp = match job.compression {
-            Some(CompressFormat::Gzip) => "Gzip",
-            Some(CompressFormat::Zip) => "Zip",
-            Some(CompressFormat::Sevenz) => "Sevenz",
-            Some(CompressFormat::Zstd) => "Zstd",
-            Some(CompressFormat::Bzip2) => "Bzip2",
-            Some(CompressFormat::Xz) => "Xz",
-            Some(CompressFormat::Lz4) => "Lz4",
-            Some(CompressFormat::Tar) => "Tar",
-            None => "",
-        };
-        let level = match job.level {
-            Some(Level::Fastest) => "Fastest",
-            Some(Level::Faster) => "Faster",
-            Some(Level::Default) => "Default",
-            Some(Level::Better) => "Better",
-            Some(Level::Best) => "Best",
-            None => "",
-        };
-        let model = match job.model {
-            Some(BackupModel::Full) => "Full",
-            Some(BackupModel::Mirror) => "Mirror",
-            None => "",
-        };
-        s.push_str(&format!(
-            "{{\n    id: {},\n    source: \"{}\",\n    target: \"{}\"",
-            job.id,
-            job.source.display(),
-            job.target.display()
-        ));
-        if !comp.is_empty() {
-            s.push_str(&format!(",\n    compression: \"{comp}\""));
-        }
-        if !level.is_empty() {
-            s.push_str(&format!(",\n    level: \"{level}\""));
-        }
-        if let Some(ignore) = &job.ignore {
-            s.push_str(&format!(",\n    ignore: {ignore:?}"));
-        }
-        if !model.is_empty() {
-            s.push_str(&format!(",\n    model: \"{model}\""));
-        }
-        s.push_str("\n},");
-    }
-    s.pop();
-    s.push(']');
-    s
-}
-
-/// Runs a backup job (single file or directory copy, with optional compression).
-pub fn run_job(job: &Job) -> Result<()> {
-    if let Some(ref format) = job.compression {
-        let level = job.level.as_ref().unwrap_or(&Level::Default);
-        file_util::compression(
-            &job.source,
-            &job.target,
+
+    /// Returns the source of a job that backs up a single file.
+    ///
+    /// Such a job copies the file to the target path itself unless the target is a
+    /// directory, while other jobs lay each source out inside the target directory.
+    pub fn single_file(&self) -> Option<&Path> {
+        match self.source.as_slice() {
+            [src] if !src.is_dir() => Some(src),
+            _ => None,
+        }
+    }
+
+    /// Returns the path of the archive a compressed job writes into `target`.
+    ///
+    /// The archive of a job with several sources is named after the job.
+    pub fn archive_path(&self, target: &Path) -> Option<PathBuf> {
+        let format = self.compression.as_ref()?;
+        Some(file_util::sources_archive_path(
+            &self.source,
+            &self.archive_name(),
+            target,
             format,
-            level,
-            job.ignore.as_deref(),
-        )?;
-    } else if job.source.is_dir() {
-        let target = &job.target;
-        if target.exists() && target.is_file() {
-            bail!(
-                "The file {target:?} already exists and a directory with the same name cannot be created."
-            );
+        ))
+    }
+
+    /// Returns the base name of the archive of a job with several sources.
+    fn archive_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("backup-{}", self.id),
         }
+    }
 
-        let items = get_items(job.clone())?;
-        let rt = runtimeBuilder::new_multi_thread().enable_all().build()?;
-        rt.block_on(async {
-            let mut tasks = FuturesUnordered::new();
-            for item in items {
-                tasks.push(execute_item_async(item));
+    /// Returns a copy of the job with the templates in its sources and targets
+    /// expanded for the current run (see [`template::expand`]).
+    ///
+    /// # Errors
+    /// Returns an error if a path cannot be expanded or expands to a relative path,
+    /// which would depend on the working directory of the run.
+    pub fn expanded(&self) -> Result<Job> {
+        let expand = |paths: &[PathBuf]| -> Result<Vec<PathBuf>> {
+            paths
+                .iter()
+                .map(|p| {
+                    let expanded = template::expand(p)?;
+                    if expanded.is_relative() {
+                        bail!("path {p:?} of job {} is not absolute", self.id);
+                    }
+                    Ok(expanded)
+                })
+                .collect()
+        };
+        Ok(Job {
+            source: expand(&self.source)?,
+            target: expand(&self.target)?,
+            ..self.clone()
+        })
+    }
+
+    /// Checks that the sources and targets of the job can be backed up together.
+    /// Expects expanded paths.
+    ///
+    /// A source may not be listed twice or lie inside another source, whose walk
+    /// would store its files a second time, and a target may not be or contain a
+    /// source, which the backup would write over. Targets inside a source are
+    /// allowed; they are [left out](Self::nested_targets) of the backup.
+    pub fn check_paths(&self) -> Result<()> {
+        for target in &self.target {
+            if let Some(src) = self.source.iter().find(|src| src.starts_with(target)) {
+                if src == target {
+                    bail!("target {} is also a source of the job", target.display());
+                }
+                bail!(
+                    "target {} contains source {}",
+                    target.display(),
+                    src.display()
+                );
+            }
+        }
+        for (i, src) in self.source.iter().enumerate() {
+            for (j, other) in self.source.iter().enumerate() {
+                if i == j || !src.starts_with(other) {
+                    continue;
+                }
+                if src == other {
+                    bail!("source {} is listed twice", src.display());
+                }
+                bail!(
+                    "source {} is inside source {}",
+                    src.display(),
+                    other.display()
+                );
             }
-            while let Some(res) = tasks.next().await {
-                res?;
+        }
+        Ok(())
+    }
+
+    /// Returns the targets of the job that lie inside one of its source directories,
+    /// each with that source. Expects expanded paths.
+    ///
+    /// Such targets are excluded from the backup, so it never copies itself.
+    pub fn nested_targets(&self) -> Vec<(&Path, &Path)> {
+        let mut nested = vec![];
+        for target in &self.target {
+            for src in self.source.iter().filter(|src| src.is_dir()) {
+                if target.starts_with(src) {
+                    nested.push((target.as_path(), src.as_path()));
+                }
             }
-            Ok::<(), anyhow::Error>(())
-        })?;
-    } else if let Some(item) = get_item(job.clone())? {
-        execute_item(item)?;
+        }
+        nested
+    }
+
+    /// Returns the paths left out of the backup of the source `src`: the entries of
+    /// the ignore list and the targets inside `src`. Expects expanded paths.
+    pub(crate) fn excluded_paths(&self, src: &Path) -> Vec<PathBuf> {
+        let ignored = self.ignore.iter().flatten().map(|s| src.join(s));
+        let targets = self
+            .target
+            .iter()
+            .filter(|target| target.starts_with(src))
+            .cloned();
+        ignored.chain(targets).collect()
+    }
+
+    /// Walks the sources and returns every file and directory the backup holds,
+    /// leaving out the [excluded paths](Self::excluded_paths). Expects expanded paths.
+    pub fn scan_sources(&self) -> Result<Vec<PathBuf>> {
+        let mut entries = vec![];
+        for src in &self.source {
+            entries.extend(file_util::scan_excluding(src, &self.excluded_paths(src))?);
+        }
+        Ok(entries)
+    }
+
+    /// Returns `true` if the job carries `tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags
+            .as_ref()
+            .is_some_and(|tags| tags.iter().any(|t| t == tag))
+    }
+}
+
+/// Reference to a job given on the command line: its numeric id or its name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobRef {
+    Id(u32),
+    Name(String),
+}
+
+impl JobRef {
+    /// Returns `true` if `job` is the referenced job.
+    pub fn matches(&self, job: &Job) -> bool {
+        match self {
+            JobRef::Id(id) => job.id == *id,
+            JobRef::Name(name) => job.name.as_deref() == Some(name),
+        }
+    }
+}
+
+impl FromStr for JobRef {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(id) => JobRef::Id(id),
+            Err(_) => JobRef::Name(s.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for JobRef {
+    /// Formats the reference for messages such as "Job with id 3 not found.".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobRef::Id(id) => write!(f, "with id {id}"),
+            JobRef::Name(name) => write!(f, "named {name:?}"),
+        }
+    }
+}
+
+/// Checks that `name` can be used as a job name.
+///
+/// Names must not be numeric, so they cannot be mistaken for ids, and must not
+/// contain commas, which separate lists of jobs on the command line.
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        bail!("job name must not be empty");
+    }
+    if name.parse::<u32>().is_ok() {
+        bail!("job name must not be a number: {name}");
+    }
+    if name.contains(',') {
+        bail!("job name must not contain a comma: {name}");
     }
     Ok(())
 }
 
-/// Runs multiple backup jobs concurrently.
-pub fn run_jobs(jobs: Vec<Job>) -> Result<()> {
+/// Output formats of `bk list`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ListFormat {
+    /// Aligned columns for people.
+    #[default]
+    Table,
+    Json,
+    Toml,
+    /// One row per job, list values separated by `;`.
+    Csv,
+}
+
+/// Formats `jobs` for `bk list`.
+pub fn format_jobs(jobs: Vec<Job>, format: ListFormat) -> Result<String> {
+    #[derive(Serialize)]
+    struct Jobs {
+        jobs: Vec<Job>,
+    }
+
+    Ok(match format {
+        ListFormat::Table => display_jobs(jobs),
+        ListFormat::Json => serde_json::to_string_pretty(&jobs)?,
+        ListFormat::Toml => toml::to_string_pretty(&Jobs { jobs })?,
+        ListFormat::Csv => jobs_to_csv(&jobs),
+    })
+}
+
+/// Renders `jobs` as an aligned table. Optional columns are only shown if a job
+/// uses them; hooks are only part of the other formats.
+pub fn display_jobs(jobs: Vec<Job>) -> String {
+    if jobs.is_empty() {
+        return String::new();
+    }
+    let fields = job_fields(&jobs, false);
+    let columns: Vec<_> = fields
+        .into_iter()
+        .filter(|(header, values)| {
+            matches!(*header, "ID" | "SOURCE" | "TARGET") || values.iter().any(|v| !v.is_empty())
+        })
+        .map(|(header, values)| {
+            let width = values
+                .iter()
+                .map(|v| v.chars().count())
+                .chain([header.len()])
+                .max()
+                .unwrap_or_default();
+            (header, values, width)
+        })
+        .collect();
+
+    let mut lines = vec![];
+    let headers: Vec<_> = columns.iter().map(|(h, _, w)| format!("{h:w$}")).collect();
+    lines.push(headers.join("  "));
+    for row in 0..jobs.len() {
+        let cells: Vec<_> = columns
+            .iter()
+            .map(|(_, values, w)| format!("{:w$}", values[row]))
+            .collect();
+        lines.push(cells.join("  "));
+    }
+    lines
+        .iter()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders `jobs` as CSV with a header row and every field of a job.
+fn jobs_to_csv(jobs: &[Job]) -> String {
+    fn quote(field: &str) -> String {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    let mut fields = job_fields(jobs, true);
+    fields.extend([
+        ("PRE_HOOK", column(jobs, |j| j.pre_hook.clone())),
+        ("POST_HOOK", column(jobs, |j| j.post_hook.clone())),
+        ("ON_FAILURE", column(jobs, |j| j.on_failure.clone())),
+        (
+            "HOOK_TIMEOUT",
+            column(jobs, |j| j.hook_timeout.map(|t| t.to_string())),
+        ),
+    ]);
+
+    let mut csv = String::new();
+    let headers: Vec<_> = fields.iter().map(|(h, _)| h.to_lowercase()).collect();
+    csv.push_str(&headers.join(","));
+    csv.push('\n');
+    for row in 0..jobs.len() {
+        let cells: Vec<_> = fields
+            .iter()
+            .map(|(_, values)| quote(&values[row]))
+            .collect();
+        csv.push_str(&cells.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Returns the headers and values of the fields shown in the table, one value
+/// per job.
+///
+/// For the table, list values are joined with `;`. With `csv`, list values are
+/// JSON arrays, flags are `true` or `false` and settings are named as on the
+/// command line, so the values can be parsed back.
+fn job_fields(jobs: &[Job], csv: bool) -> Vec<(&'static str, Vec<String>)> {
+    let list = |items: Vec<String>| -> String {
+        if csv {
+            serde_json::to_string(&items).unwrap_or_default()
+        } else {
+            items.join(";")
+        }
+    };
+    let paths = |paths: &[PathBuf]| list(paths.iter().map(|p| p.display().to_string()).collect());
+    let strings = |items: &Option<Vec<String>>| items.clone().map(list);
+    fn setting<T: ValueEnum + fmt::Debug>(value: &Option<T>, csv: bool) -> Option<String> {
+        let value = value.as_ref()?;
+        if csv {
+            value.to_possible_value().map(|v| v.get_name().to_string())
+        } else {
+            Some(format!("{value:?}"))
+        }
+    }
+    // The table only marks the unusual state of a flag.
+    let flag = |value: bool, marked: bool, mark: &str| {
+        if csv {
+            Some(value.to_string())
+        } else {
+            (value == marked).then(|| mark.to_string())
+        }
+    };
+
+    vec![
+        ("ID", column(jobs, |j| Some(j.id.to_string()))),
+        ("NAME", column(jobs, |j| j.name.clone())),
+        ("TAGS", column(jobs, |j| strings(&j.tags))),
+        (
+            "ENABLED",
+            column(jobs, |j| flag(j.is_enabled(), false, "no")),
+        ),
+        ("SOURCE", column(jobs, |j| Some(paths(&j.source)))),
+        ("TARGET", column(jobs, |j| Some(paths(&j.target)))),
+        (
+            "COMPRESSION",
+            column(jobs, |j| setting(&j.compression, csv)),
+        ),
+        ("LEVEL", column(jobs, |j| setting(&j.level, csv))),
+        ("MODEL", column(jobs, |j| setting(&j.model, csv))),
+        ("IGNORE", column(jobs, |j| strings(&j.ignore))),
+        ("SCHEDULE", column(jobs, |j| j.schedule.clone())),
+        (
+            "VERIFY",
+            column(jobs, |j| flag(j.verify == Some(true), true, "yes")),
+        ),
+        (
+            "PARITY",
+            column(jobs, |j| {
+                j.parity
+                    .map(|p| if csv { p.to_string() } else { format!("{p}%") })
+            }),
+        ),
+    ]
+}
+
+/// Returns the value of a field for each job, with unset fields left empty.
+fn column(jobs: &[Job], get: impl Fn(&Job) -> Option<String>) -> Vec<String> {
+    jobs.iter().map(|j| get(j).unwrap_or_default()).collect()
+}
+
+/// Maximum number of items copied concurrently within a single job.
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Options that control how jobs are run.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Skip the work recorded in the journal of a previously interrupted run.
+    pub resume: bool,
+    /// How long to wait for another run of the same job to release its target lock.
+    pub wait: Option<Duration>,
+}
+
+/// Runs a backup job (single file or directory copy, with optional compression)
+/// together with its hooks.
+///
+/// The path templates of the job are expanded first.
+pub fn run_job(job: &Job, opts: &RunOptions) -> Result<()> {
+    run_job_with_targets(job, opts).0
+}
+
+/// Runs a backup job like [`run_job`], and also returns the state of each target of
+/// a job with several targets.
+pub fn run_job_with_targets(job: &Job, opts: &RunOptions) -> (Result<()>, Vec<(PathBuf, Status)>) {
+    match prepare(job) {
+        Ok(job) => {
+            let result = run_expanded(&job, opts);
+            let targets = target_statuses(&job, &result);
+            (result, targets)
+        }
+        Err(e) => (finish_hooks(job, Err(e)), vec![]),
+    }
+}
+
+/// Expands the path templates of a job and [checks](Job::check_paths) its paths.
+fn prepare(job: &Job) -> Result<Job> {
+    let job = job.expanded()?;
+    job.check_paths()?;
+    Ok(job)
+}
+
+/// Runs a job whose path templates were already expanded, together with its hooks.
+fn run_expanded(job: &Job, opts: &RunOptions) -> Result<()> {
+    if cancel::is_cancelled() {
+        bail!(HbackupError::Cancelled);
+    }
+    let _locks = lock_targets(job, opts.wait)?;
+    let result = hook::pre(job).and_then(|_| backup(job, opts));
+    finish_hooks(job, result)
+}
+
+/// Acquires the locks of all targets of a job.
+fn lock_targets(job: &Job, wait: Option<Duration>) -> Result<Vec<lock::Lock>> {
+    let paths: BTreeSet<_> = job
+        .target
+        .iter()
+        .map(|t| lock::target_lock_path(t))
+        .collect();
+    paths.iter().map(|path| lock::acquire(path, wait)).collect()
+}
+
+/// Runs the backup of a job without its hooks.
+///
+/// The sources are read for the first target that completes; the other targets copy
+/// the files from it (see [`copy_from_first`]).
+fn backup(job: &Job, opts: &RunOptions) -> Result<()> {
+    if job.compression.is_some() {
+        return compress_job(job, opts);
+    }
+    let rt = runtimeBuilder::new_multi_thread().enable_all().build()?;
+    let mut failures = TargetFailures::default();
+    let mut first = None;
+    for target in &job.target {
+        let result = if job.single_file().is_none() {
+            plan_items(job, target, opts).and_then(|(items, mut journal)| {
+                let items = copy_from_first(items, first.as_ref(), target);
+                let copied = copied_files(&items, target);
+                rt.block_on(execute_items(items, Some(&mut journal)))?;
+                journal.finish()?;
+                Ok(copied)
+            })
+        } else {
+            get_item(job, target)
+                .and_then(|item| item.map_or(Ok(()), execute_item))
+                .map(|_| BTreeSet::new())
+        };
+        let result = result.and_then(|copied| write_manifest(job, target).map(|_| copied));
+        let result = record_first(&mut first, target, result);
+        failures.record(job, target, result)?;
+    }
+    failures.finish(job)
+}
+
+/// Brings the targets of a job up to date after the `changed` source paths were
+/// modified, together with its hooks.
+///
+/// Uncompressed directory jobs only copy or delete what changed; other jobs are
+/// run in full. The target locks are not waited for, so the caller can retry later.
+pub fn sync_changes(job: &Job, changed: &[PathBuf]) -> Result<()> {
+    let job = &match prepare(job) {
+        Ok(job) => job,
+        Err(e) => return finish_hooks(job, Err(e)),
+    };
+    if job.compression.is_some() || job.single_file().is_some() {
+        return run_expanded(job, &RunOptions::default());
+    }
+    if cancel::is_cancelled() {
+        bail!(HbackupError::Cancelled);
+    }
+    let _locks = lock_targets(job, None)?;
+    let result = hook::pre(job).and_then(|_| {
+        let rt = runtimeBuilder::new_multi_thread().enable_all().build()?;
+        let mut failures = TargetFailures::default();
+        let mut first = None;
+        for target in &job.target {
+            let result = if target.is_file() {
+                Err(anyhow!("destination must be a directory"))
+            } else {
+                get_changed_items(job, target, changed).and_then(|items| {
+                    let items = copy_from_first(items, first.as_ref(), target);
+                    let copied = copied_files(&items, target);
+                    rt.block_on(execute_items(items, None)).map(|_| copied)
+                })
+            };
+            let result = record_first(&mut first, target, result);
+            failures.record(job, target, result)?;
+        }
+        failures.finish(job)
+    });
+    finish_hooks(job, result)
+}
+
+/// Runs multiple backup jobs concurrently and returns the outcome of each job.
+///
+/// Jobs that write to a common target run one after the other, so they do not
+/// contend for its lock.
+pub fn run_jobs(jobs: Vec<Job>, opts: &RunOptions) -> Result<RunReport> {
     let rt = runtimeBuilder::new_multi_thread().enable_all().build()?;
 
-    rt.block_on(async move {
+    let mut outcomes = rt.block_on(async move {
         let mut set = tokio::task::JoinSet::new();
-        for job in jobs {
+        for chain in sharing_targets(jobs) {
+            let opts = opts.clone();
             set.spawn(async move {
-                if let Err(e) = run_job_async(&job).await {
-                    eprintln!("Failed to run job with id {}: {}\n", job.id, e);
+                let mut outcomes = vec![];
+                for job in chain {
+                    outcomes.push(run_one(job, &opts).await);
                 }
+                outcomes
             });
         }
+        let mut outcomes = vec![];
         while let Some(res) = set.join_next().await {
-            if let Err(e) = res {
-                eprintln!("Failed to run job: {e}\n");
+            match res {
+                Ok(chain) => outcomes.extend(chain),
+                Err(e) => eprintln!("Failed to run job: {e}\n"),
             }
         }
+        outcomes
     });
 
+    outcomes.sort_by_key(|(id, _, _)| *id);
+    let mut report = RunReport::new();
+    for (id, status, targets) in outcomes {
+        report.push_targets(id, status, targets);
+    }
+    Ok(report)
+}
+
+/// Runs one of the jobs of [`run_jobs`] and returns its outcome.
+async fn run_one(job: Job, opts: &RunOptions) -> (u32, Status, Vec<(PathBuf, Status)>) {
+    let (job, result) = match prepare(&job) {
+        Ok(job) => {
+            let result = run_job_async(&job, opts).await;
+            (job, result)
+        }
+        Err(e) => {
+            let result = tokio::task::block_in_place(|| finish_hooks(&job, Err(e)));
+            (job, result)
+        }
+    };
+    let targets = target_statuses(&job, &result);
+    let status = match result {
+        Ok(()) => Status::Success,
+        Err(e) if cancel::is_cancellation(&e) => Status::Cancelled,
+        Err(e) if lock::is_contention(&e) => {
+            eprintln!("Skipped job with id {}: {}\n", job.id, e);
+            Status::Locked
+        }
+        Err(e) => {
+            eprintln!("Failed to run job with id {}: {}\n", job.id, e);
+            Status::Failed(e.to_string())
+        }
+    };
+    (job.id, status, targets)
+}
+
+/// Groups `jobs` into chains whose jobs share a target lock with another job of
+/// the chain, keeping their order.
+fn sharing_targets(jobs: Vec<Job>) -> Vec<Vec<Job>> {
+    let mut chains: Vec<(BTreeSet<PathBuf>, Vec<Job>)> = vec![];
+    for job in jobs {
+        let targets = job
+            .expanded()
+            .map_or_else(|_| job.target.clone(), |j| j.target);
+        let mut locks: BTreeSet<_> = targets.iter().map(|t| lock::target_lock_path(t)).collect();
+        let mut jobs = vec![];
+        let mut i = 0;
+        while i < chains.len() {
+            if chains[i].0.is_disjoint(&locks) {
+                i += 1;
+            } else {
+                let (other_locks, other_jobs) = chains.remove(i);
+                locks.extend(other_locks);
+                jobs.extend(other_jobs);
+            }
+        }
+        jobs.push(job);
+        chains.push((locks, jobs));
+    }
+    chains.into_iter().map(|(_, jobs)| jobs).collect()
+}
+
+/// Returns the state of each target of a job with several targets after a run that
+/// reached its targets.
+fn target_statuses(job: &Job, result: &Result<()>) -> Vec<(PathBuf, Status)> {
+    if job.target.len() < 2 {
+        return vec![];
+    }
+    let failed = match result {
+        Ok(()) => &vec![],
+        Err(e) => match e.downcast_ref() {
+            Some(HbackupError::TargetsFailed(failed, _)) => failed,
+            _ => return vec![],
+        },
+    };
+    job.target
+        .iter()
+        .map(|target| {
+            let status = match failed.iter().find(|(t, _)| t == target) {
+                Some((_, e)) => Status::Failed(e.clone()),
+                None => Status::Success,
+            };
+            (target.clone(), status)
+        })
+        .collect()
+}
+
+/// Runs a backup job (single file or directory copy, with optional compression)
+/// whose path templates were already expanded, together with its hooks.
+async fn run_job_async(job: &Job, opts: &RunOptions) -> Result<()> {
+    if cancel::is_cancelled() {
+        bail!(HbackupError::Cancelled);
+    }
+    let _locks = tokio::task::block_in_place(|| lock_targets(job, opts.wait))?;
+    let result = match tokio::task::block_in_place(|| hook::pre(job)) {
+        Ok(()) => backup_async(job, opts).await,
+        Err(e) => Err(e),
+    };
+    tokio::task::block_in_place(|| finish_hooks(job, result))
+}
+
+/// Runs the backup of a job without its hooks.
+async fn backup_async(job: &Job, opts: &RunOptions) -> Result<()> {
+    if job.compression.is_some() {
+        let job = job.clone();
+        let opts = opts.clone();
+        return tokio::task::spawn_blocking(move || compress_job(&job, &opts)).await?;
+    }
+    let mut failures = TargetFailures::default();
+    let mut first = None;
+    for target in &job.target {
+        let result = if job.single_file().is_none() {
+            match plan_items(job, target, opts) {
+                Ok((items, mut journal)) => {
+                    let items = copy_from_first(items, first.as_ref(), target);
+                    let copied = copied_files(&items, target);
+                    match execute_items(items, Some(&mut journal)).await {
+                        Ok(()) => journal.finish().map(|_| copied),
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            match get_item(job, target) {
+                Ok(Some(item)) => execute_item_async(item).await.map(|_| BTreeSet::new()),
+                Ok(None) => Ok(BTreeSet::new()),
+                Err(e) => Err(e),
+            }
+        };
+        let result = result.and_then(|copied| {
+            tokio::task::block_in_place(|| write_manifest(job, target)).map(|_| copied)
+        });
+        let result = record_first(&mut first, target, result);
+        failures.record(job, target, result)?;
+    }
+    failures.finish(job)
+}
+
+/// Failures of the individual targets of a job, collected so that a failing target
+/// does not keep the other targets from being backed up.
+#[derive(Default)]
+struct TargetFailures(Vec<(PathBuf, String)>);
+
+impl TargetFailures {
+    /// Records the result of backing up to `target`.
+    ///
+    /// Cancellation is returned right away, since it stops all targets. A job with a
+    /// single target fails with the original error.
+    fn record(&mut self, job: &Job, target: &Path, result: Result<()>) -> Result<()> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if cancel::is_cancellation(&e) || job.target.len() < 2 => Err(e),
+            Err(e) => {
+                eprintln!(
+                    "Failed to back up job with id {} to {target:?}: {e}",
+                    job.id
+                );
+                self.0.push((target.to_path_buf(), e.to_string()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Fails with [`HbackupError::TargetsFailed`] if any target failed.
+    fn finish(self, job: &Job) -> Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        bail!(HbackupError::TargetsFailed(self.0, job.target.len()))
+    }
+}
+
+/// Runs the post-hook after a successful backup, or the failure hook otherwise.
+fn finish_hooks(job: &Job, result: Result<()>) -> Result<()> {
+    match result {
+        Ok(()) => hook::post(job),
+        Err(e) => {
+            let status = if cancel::is_cancellation(&e) {
+                "cancelled"
+            } else {
+                "failed"
+            };
+            hook::failure(job, status, &e);
+            Err(e)
+        }
+    }
+}
+
+/// Compresses the sources of a job once and places the archive in each target.
+///
+/// The archive is written into the first target that accepts it and then copied to
+/// the remaining targets, each of which also receives the manifest and the recovery
+/// records of the archive. With verification, each copy is checked as well.
+fn compress_job(job: &Job, opts: &RunOptions) -> Result<()> {
+    let mut archive: Option<(PathBuf, Manifest)> = None;
+    let mut failures = TargetFailures::default();
+    for target in &job.target {
+        let result = match &archive {
+            Some((path, manifest)) => copy_archive(path, target)
+                .and_then(|_| verify_copy(job, path, target, manifest))
+                .and_then(|_| copy_parity(job, path, target))
+                .and_then(|_| manifest.write(target)),
+            None => compress_into(job, target, opts).and_then(|(path, entries)| {
+                let manifest = Manifest::build(job, &entries, target, Some(&path), None)?;
+                verify_after_run(job, &path, &manifest)?;
+                write_parity(job, &path)?;
+                manifest.write(target)?;
+                archive = Some((path, manifest));
+                Ok(())
+            }),
+        };
+        failures.record(job, target, result)?;
+    }
+    failures.finish(job)
+}
+
+/// Compresses the sources of a job into the `target` directory and returns the
+/// path of the archive together with the entries it was built from.
+///
+/// The entries found by the pre-scan are journaled, so a resumed run only has to
+/// rebuild the archive.
+fn compress_into(job: &Job, target: &Path, opts: &RunOptions) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let Some(format) = &job.compression else {
+        bail!("job {} has no compression format", job.id);
+    };
+    let level = job.level.as_ref().unwrap_or(&Level::Default);
+    if let Some(src) = job.source.iter().find(|src| !src.exists()) {
+        bail!(HbackupError::PathNotFound(src.clone()));
+    }
+    if target.exists() && !target.is_dir() {
+        bail!("destination must be a directory");
+    }
+
+    let mut journal = Journal::open(target, job.id, opts.resume)?;
+    let entries = match journal.scanned() {
+        Some(entries) => entries.to_vec(),
+        None => {
+            let entries = job.scan_sources()?;
+            journal.record_scan(&entries)?;
+            entries
+        }
+    };
+    let name = job.archive_name();
+    file_util::compress_sources(&job.source, &entries, target, &name, format, level)?;
+    journal.finish()?;
+    let archive = file_util::sources_archive_path(&job.source, &name, target, format);
+    Ok((archive, entries))
+}
+
+/// Writes the manifest of an uncompressed backup into the `target` directory,
+/// hashing the files copied there. A single file copied to a target that is not a
+/// directory gets no manifest.
+fn write_manifest(job: &Job, target: &Path) -> Result<()> {
+    if !target.is_dir() {
+        return Ok(());
+    }
+    let previous = Manifest::read(target, job.id).ok().flatten();
+    Manifest::build(job, &job.scan_sources()?, target, None, previous.as_ref())?.write(target)
+}
+
+/// Verifies a freshly written archive against its new `manifest` if the job asks
+/// for it (see [`Job::verify`]).
+fn verify_after_run(job: &Job, archive: &Path, manifest: &Manifest) -> Result<()> {
+    if job.verify != Some(true) {
+        return Ok(());
+    }
+    let outcome = integrity::verify_with_manifest(job, archive, manifest, false)?;
+    if !outcome.is_ok() {
+        bail!("verification of {archive:?} failed: {}", outcome.summary());
+    }
+    Ok(())
+}
+
+/// Checks the copy of `archive` in `target` against the hash of the archive in its
+/// `manifest` if the job asks for verification (see [`Job::verify`]).
+fn verify_copy(job: &Job, archive: &Path, target: &Path, manifest: &Manifest) -> Result<()> {
+    if job.verify != Some(true) {
+        return Ok(());
+    }
+    let Some(name) = archive.file_name() else {
+        bail!("invalid archive path {archive:?}");
+    };
+    let copy = target.join(name);
+    let (_, sha256) = integrity::digest(fs::File::open(&copy)?, true)?;
+    if sha256 != manifest.archive_sha256 {
+        bail!("verification of {copy:?} failed: the copy differs from {archive:?}");
+    }
     Ok(())
 }
 
-/// Runs a backup job (single file or directory copy, with optional compression).
-async fn run_job_async(job: &Job) -> Result<()> {
-    if let Some(ref format) = job.compression {
-        let level = job.level.as_ref().unwrap_or(&Level::Default);
-        let src = job.source.clone();
-        let tgt = job.target.clone();
-        let fmt = format.clone();
-        let lvl = level.clone();
-        let ignore = job.ignore.clone();
-        tokio::task::spawn_blocking(move || {
-            file_util::compression(&src, &tgt, &fmt, &lvl, ignore.as_deref())
+/// Writes the recovery records of a freshly written archive if the job asks for them
+/// (see [`Job::parity`]). Records left from a run with parity enabled are removed, as
+/// they no longer match the archive.
+fn write_parity(job: &Job, archive: &Path) -> Result<()> {
+    let path = parity::parity_path(archive);
+    match job.parity {
+        Some(redundancy) => parity::write(archive, redundancy).map(|_| ()),
+        None if path.exists() => Ok(fs::remove_file(path)?),
+        None => Ok(()),
+    }
+}
+
+/// Copies the recovery records of an archive written into another target of the job
+/// into the `target` directory, or removes stale ones if the job writes none.
+fn copy_parity(job: &Job, archive: &Path, target: &Path) -> Result<()> {
+    let path = parity::parity_path(archive);
+    if job.parity.is_some() {
+        return copy_archive(&path, target);
+    }
+    match path.file_name().map(|name| target.join(name)) {
+        Some(stale) if stale.exists() => Ok(fs::remove_file(stale)?),
+        _ => Ok(()),
+    }
+}
+
+/// Copies an archive written into another target of the job into the `target` directory.
+fn copy_archive(archive: &Path, target: &Path) -> Result<()> {
+    if cancel::is_cancelled() {
+        bail!(HbackupError::Cancelled);
+    }
+    if target.exists() && !target.is_dir() {
+        bail!("destination must be a directory");
+    }
+    fs::create_dir_all(target)?;
+    file_util::copy(archive, target)
+}
+
+/// The first target of a run that completed, with the files the run copied into it,
+/// relative to the target.
+type FirstTarget<'a> = (&'a Path, BTreeSet<PathBuf>);
+
+/// Makes the files planned for `target` be copied from the `first` target of the
+/// run, so each source is only read once.
+///
+/// Only files this run copied into `first` are taken from there; a copy that was
+/// already in place may be stale or damaged, so everything else is read from its
+/// source.
+fn copy_from_first(items: Vec<Item>, first: Option<&FirstTarget>, target: &Path) -> Vec<Item> {
+    let Some((first, copied)) = first else {
+        return items;
+    };
+    items
+        .into_iter()
+        .map(|item| match item {
+            Item::Copy { src, dest } => match dest.strip_prefix(target) {
+                Ok(rel) if copied.contains(rel) => Item::Copy {
+                    src: first.join(rel),
+                    dest,
+                },
+                _ => Item::Copy { src, dest },
+            },
+            item => item,
+        })
+        .collect()
+}
+
+/// Returns the files the `items` planned for `target` copy, relative to the target.
+fn copied_files(items: &[Item], target: &Path) -> BTreeSet<PathBuf> {
+    items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Copy { src, dest } if src.is_file() => dest.strip_prefix(target).ok(),
+            _ => None,
         })
-        .await??;
-    } else if job.source.is_dir() {
-        let target = &job.target;
-        if target.exists() && target.is_file() {
-            bail!(
-                "The file {target:?} already exists and a directory with the same name cannot be created."
-            );
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// Remembers `target` and the files copied into it as the `first` target of the run
+/// if it is the first one that completed.
+fn record_first<'a>(
+    first: &mut Option<FirstTarget<'a>>,
+    target: &'a Path,
+    result: Result<BTreeSet<PathBuf>>,
+) -> Result<()> {
+    let copied = result?;
+    if first.is_none() {
+        *first = Some((target, copied));
+    }
+    Ok(())
+}
+
+/// Plans the items of a directory job for one of its targets and opens the journal
+/// of that target.
+///
+/// When resuming, items completed by the interrupted run are left out, unless the
+/// source of a copy changed since.
+fn plan_items(job: &Job, target: &Path, opts: &RunOptions) -> Result<(Vec<Item>, Journal)> {
+    if target.exists() && target.is_file() {
+        bail!(
+            "The file {target:?} already exists and a directory with the same name cannot be created."
+        );
+    }
+
+    let items = get_items(job, target)?;
+    let mut journal = Journal::open(target, job.id, opts.resume)?;
+    let mut planned = vec![];
+    for item in items {
+        match &item {
+            Item::Copy { src, dest } if journal.is_copied(src, dest) => continue,
+            Item::Copy { src, dest } => journal.plan_copy(src, dest),
+            Item::Delete(dest) if journal.is_done(dest) => continue,
+            Item::Delete(_) => {}
         }
-        let items = get_items(job.clone())?;
-        let mut tasks = FuturesUnordered::new();
-        for item in items {
-            tasks.push(execute_item_async(item));
+        planned.push(item);
+    }
+    Ok((planned, journal))
+}
+
+/// Executes the items of a job with bounded concurrency, journaling each completed item
+/// when a journal is given.
+///
+/// Once cancellation is requested no further item is started, while items that are
+/// already being copied are allowed to finish.
+async fn execute_items(items: Vec<Item>, mut journal: Option<&mut Journal>) -> Result<()> {
+    let mut pending = items.into_iter();
+    let mut tasks = FuturesUnordered::new();
+    loop {
+        while tasks.len() < MAX_IN_FLIGHT && !cancel::is_cancelled() {
+            match pending.next() {
+                Some(item) => tasks.push(async move {
+                    let dest = item.dest().to_path_buf();
+                    execute_item_async(item).await.map(|_| dest)
+                }),
+                None => break,
+            }
         }
-        while let Some(res) = tasks.next().await {
-            res?;
+        match tasks.next().await {
+            Some(res) => {
+                let dest = res?;
+                if let Some(journal) = journal.as_deref_mut() {
+                    journal.record_done(&dest)?;
+                }
+            }
+            None => break,
         }
-    } else if let Some(item) = get_item(job.clone())? {
-        execute_item_async(item).await?;
+    }
+    if pending.len() > 0 {
+        bail!(HbackupError::Cancelled);
     }
     Ok(())
 }
@@ -236,30 +1099,33 @@ mod tests {
         let jobs = vec![
             Job {
                 id: 1,
-                source: PathBuf::from("/test/source1"),
-                target: PathBuf::from("/test/target1"),
+                source: vec![PathBuf::from("/test/source1")],
+                target: vec![PathBuf::from("/test/target1")],
                 compression: Some(CompressFormat::Zip),
                 level: Some(Level::Fastest),
                 ignore: None,
                 model: None,
+                ..Default::default()
             },
             Job {
                 id: 2,
-                source: PathBuf::from("/test/source2"),
-                target: PathBuf::from("/test/target2"),
+                source: vec![PathBuf::from("/test/source2")],
+                target: vec![PathBuf::from("/test/target2")],
                 compression: Some(CompressFormat::Zstd),
                 level: Some(Level::Best),
                 ignore: Some(vec!["*.tmp".to_string()]),
                 model: None,
+                ..Default::default()
             },
         ];
 
         let display_str = display_jobs(jobs);
+        let lines: Vec<_> = display_str.lines().collect();
 
-        assert!(display_str.starts_with('['));
-        assert!(display_str.ends_with(']'));
-        assert!(display_str.contains("id: 1"));
-        assert!(display_str.contains("id: 2"));
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("ID  SOURCE"));
+        assert!(lines[1].starts_with("1   /test/source1"));
+        assert!(lines[2].starts_with("2   /test/source2"));
         assert!(display_str.contains("Zip"));
         assert!(display_str.contains("Zstd"));
     }
@@ -287,12 +1153,13 @@ mod tests {
         for (i, format) in formats.iter().enumerate() {
             let job = Job {
                 id: (i + 1) as u32,
-                source: PathBuf::from("/test/source"),
-                target: PathBuf::from("/test/target"),
+                source: vec![PathBuf::from("/test/source")],
+                target: vec![PathBuf::from("/test/target")],
                 compression: Some(format.clone()),
                 level: Some(Level::Default),
                 ignore: None,
                 model: None,
+                ..Default::default()
             };
 
             let display_str = display_jobs(vec![job]);
@@ -313,12 +1180,13 @@ mod tests {
         for (i, level) in levels.iter().enumerate() {
             let job = Job {
                 id: (i + 1) as u32,
-                source: PathBuf::from("/test/source"),
-                target: PathBuf::from("/test/target"),
+                source: vec![PathBuf::from("/test/source")],
+                target: vec![PathBuf::from("/test/target")],
                 compression: Some(CompressFormat::Gzip),
                 level: Some(level.clone()),
                 ignore: None,
                 model: None,
+                ..Default::default()
             };
 
             let display_str = display_jobs(vec![job]);
@@ -333,12 +1201,13 @@ mod tests {
         for (i, model) in models.iter().enumerate() {
             let job = Job {
                 id: (i + 1) as u32,
-                source: PathBuf::from("/test/source"),
-                target: PathBuf::from("/test/target"),
+                source: vec![PathBuf::from("/test/source")],
+                target: vec![PathBuf::from("/test/target")],
                 compression: None,
                 level: None,
                 ignore: None,
                 model: Some(model.clone()),
+                ..Default::default()
             };
 
             let display_str = display_jobs(vec![job]);
@@ -350,34 +1219,98 @@ mod tests {
     fn test_job_display_without_optional_fields() {
         let job = Job {
             id: 1,
-            source: PathBuf::from("/test/source"),
-            target: PathBuf::from("/test/target"),
+            source: vec![PathBuf::from("/test/source")],
+            target: vec![PathBuf::from("/test/target")],
             compression: None,
             level: None,
             ignore: None,
             model: None,
+            ..Default::default()
         };
 
         let display_str = display_jobs(vec![job]);
 
-        // Should contain required fields
-        assert!(display_str.contains("id: 1"));
-        assert!(display_str.contains("source: \"/test/source\""));
-        assert!(display_str.contains("target: \"/test/target\""));
+        // Optional columns are left out when no job uses them
+        assert_eq!(
+            display_str,
+            "ID  SOURCE        TARGET\n1   /test/source  /test/target"
+        );
+    }
+
+    #[test]
+    fn test_job_display_with_name_and_tags() {
+        let job = Job {
+            id: 1,
+            name: Some("docs".to_string()),
+            tags: Some(vec!["nightly".to_string(), "usb".to_string()]),
+            source: vec![PathBuf::from("/test/source")],
+            target: vec![PathBuf::from("/test/target")],
+            ..Default::default()
+        };
+
+        let display_str = display_jobs(vec![job.clone()]);
+        assert!(display_str.contains("NAME"));
+        assert!(display_str.contains("docs"));
+        assert!(display_str.contains("nightly;usb"));
+        assert!(job.has_tag("usb"));
+        assert!(!job.has_tag("weekly"));
+        assert!(!display_str.contains("ENABLED"));
+    }
+
+    #[test]
+    fn test_job_display_disabled() {
+        let job = Job {
+            id: 1,
+            enabled: Some(false),
+            ..Default::default()
+        };
+        assert!(!job.is_enabled());
+        assert!(Job::default().is_enabled());
+        assert!(display_jobs(vec![job]).contains("ENABLED"));
+    }
+
+    #[test]
+    fn test_job_ref() {
+        let job = Job {
+            id: 3,
+            name: Some("docs".to_string()),
+            ..Default::default()
+        };
+        let by_id: JobRef = "3".parse().unwrap();
+        let by_name: JobRef = "docs".parse().unwrap();
+        assert_eq!(by_id, JobRef::Id(3));
+        assert!(by_id.matches(&job));
+        assert!(by_name.matches(&job));
+        assert!(!JobRef::Name("other".to_string()).matches(&job));
+        assert_eq!(by_id.to_string(), "with id 3");
+
+        assert!(validate_name("docs").is_ok());
+        assert!(validate_name("42").is_err());
+        assert!(validate_name("a,b").is_err());
+        assert!(validate_name(" ").is_err());
+    }
 
-        // Should not contain optional fields when they're None
-        assert!(!display_str.contains("compression:"));
-        assert!(!display_str.contains("level:"));
-        assert!(!display_str.contains("ignore:"));
-        assert!(!display_str.contains("model:"));
+    #[test]
+    fn test_expanded_rejects_relative_paths() {
+        let job = Job {
+            source: vec![PathBuf::from("/data/{hostname}")],
+            target: vec![PathBuf::from("/backup")],
+            ..Default::default()
+        };
+        assert!(job.expanded().is_ok());
+        let job = Job {
+            source: vec![PathBuf::from("docs-{date}")],
+            ..job
+        };
+        assert!(job.expanded().is_err());
     }
 
     #[test]
     fn test_job_display_with_ignore_patterns() {
         let job = Job {
             id: 1,
-            source: PathBuf::from("/test/source"),
-            target: PathBuf::from("/test/target"),
+            source: vec![PathBuf::from("/test/source")],
+            target: vec![PathBuf::from("/test/target")],
             compression: None,
             level: None,
             ignore: Some(vec![
@@ -386,14 +1319,13 @@ mod tests {
                 "cache/".to_string(),
             ]),
             model: None,
+            ..Default::default()
         };
 
         let display_str = display_jobs(vec![job]);
 
-        assert!(display_str.contains("ignore:"));
-        assert!(display_str.contains("*.log"));
-        assert!(display_str.contains("*.tmp"));
-        assert!(display_str.contains("cache/"));
+        assert!(display_str.contains("IGNORE"));
+        assert!(display_str.contains("*.log;*.tmp;cache/"));
     }
 
     #[test]
@@ -406,8 +1338,8 @@ mod tests {
         let model = Some(BackupModel::Mirror);
 
         let job = Job::temp_job(
-            source.clone(),
-            target.clone(),
+            vec![source.clone()],
+            vec![target.clone()],
             compression.clone(),
             level.clone(),
             ignore.clone(),
@@ -415,8 +1347,8 @@ mod tests {
         );
 
         assert_eq!(job.id, 0);
-        assert_eq!(job.source, source);
-        assert_eq!(job.target, target);
+        assert_eq!(job.source, vec![source]);
+        assert_eq!(job.target, vec![target]);
         assert_eq!(job.compression, compression);
         assert_eq!(job.level, level);
         assert_eq!(job.ignore, ignore);
@@ -433,12 +1365,13 @@ mod tests {
     fn test_job_serialization() {
         let job = Job {
             id: 42,
-            source: PathBuf::from("/home/user/documents"),
-            target: PathBuf::from("/backup/documents"),
+            source: vec![PathBuf::from("/home/user/documents")],
+            target: vec![PathBuf::from("/backup/documents")],
             compression: Some(CompressFormat::Zstd),
             level: Some(Level::Better),
             ignore: Some(vec!["*.tmp".to_string(), ".DS_Store".to_string()]),
             model: Some(BackupModel::Mirror),
+            ..Default::default()
         };
 
         // Test serialization to TOML
@@ -465,38 +1398,345 @@ mod tests {
         let jobs = vec![
             Job {
                 id: 1,
-                source: PathBuf::from("/path1"),
-                target: PathBuf::from("/target1"),
+                source: vec![PathBuf::from("/path1")],
+                target: vec![PathBuf::from("/target1")],
                 compression: Some(CompressFormat::Gzip),
                 level: Some(Level::Fastest),
                 ignore: None,
                 model: Some(BackupModel::Full),
+                ..Default::default()
             },
             Job {
                 id: 2,
-                source: PathBuf::from("/path2"),
-                target: PathBuf::from("/target2"),
+                source: vec![PathBuf::from("/path2")],
+                target: vec![PathBuf::from("/target2")],
                 compression: None,
                 level: None,
                 ignore: Some(vec!["*.log".to_string()]),
                 model: Some(BackupModel::Mirror),
+                ..Default::default()
             },
         ];
 
-        let display_str = display_jobs(jobs);
+        let display_str = display_jobs(jobs.clone());
+
+        // Columns are aligned across jobs
+        let lines: Vec<_> = display_str.lines().collect();
+        let column = lines[0].find("MODEL").unwrap();
+        assert_eq!(lines[1].find("Full"), Some(column));
+        assert_eq!(lines[2].find("Mirror"), Some(column));
+        assert!(lines[1].contains("Gzip"));
+        assert!(lines[2].contains("*.log"));
+
+        // The other formats can be parsed back
+        let json = format_jobs(jobs.clone(), ListFormat::Json).unwrap();
+        let parsed: Vec<Job> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[1].model, Some(BackupModel::Mirror));
+        let toml = format_jobs(jobs.clone(), ListFormat::Toml).unwrap();
+        assert!(toml.contains("[[jobs]]"));
+    }
+
+    #[test]
+    fn test_jobs_to_csv() {
+        let job = Job {
+            id: 1,
+            name: Some("docs".to_string()),
+            source: vec![PathBuf::from("/a,b"), PathBuf::from("/c")],
+            target: vec![PathBuf::from("/t")],
+            pre_hook: Some("echo \"hi\"".to_string()),
+            ..Default::default()
+        };
+        let csv = format_jobs(vec![job.clone()], ListFormat::Csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert!(lines[0].starts_with("id,name,tags,enabled,source,target,"));
+        assert!(
+            lines[1].starts_with("1,docs,,true,\"[\"\"/a,b\"\",\"\"/c\"\"]\",\"[\"\"/t\"\"]\",")
+        );
+        assert!(lines[1].contains(",\"echo \"\"hi\"\"\","));
+
+        // List items keep their `;`, and settings are named as on the command line.
+        let job = Job {
+            tags: Some(vec!["a;b".to_string(), "c".to_string()]),
+            enabled: Some(false),
+            compression: Some(CompressFormat::Sevenz),
+            model: Some(BackupModel::Mirror),
+            verify: Some(true),
+            ..job
+        };
+        let csv = format_jobs(vec![job], ListFormat::Csv).unwrap();
+        let row = csv.lines().nth(1).unwrap();
+        assert!(
+            row.starts_with("1,docs,\"[\"\"a;b\"\",\"\"c\"\"]\",false,"),
+            "{row}"
+        );
+        assert!(row.contains(",sevenz,,mirror,,,true,"), "{row}");
+    }
+
+    #[test]
+    fn test_run_job_resume_skips_completed_items() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        std::fs::create_dir_all(&src)?;
+        std::fs::write(src.join("a.txt"), "a")?;
+        std::fs::write(src.join("b.txt"), "b")?;
+        let target = temp_dir.path().join("back");
+
+        // Pretend an interrupted run already copied a.txt and b.txt.
+        let mut journal = Journal::open(&target, 0, false)?;
+        for name in ["a.txt", "b.txt"] {
+            let dest = target.join("docs").join(name);
+            journal.plan_copy(&src.join(name), &dest);
+            journal.record_done(&dest)?;
+        }
+        drop(journal);
+        // b.txt is edited before the run is resumed.
+        std::fs::write(src.join("b.txt"), "B")?;
+        let file = std::fs::File::options()
+            .write(true)
+            .open(src.join("b.txt"))?;
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(60))?;
+
+        let job = Job::temp_job(vec![src], vec![target.clone()], None, None, None, None);
+        run_job(
+            &job,
+            &RunOptions {
+                resume: true,
+                ..Default::default()
+            },
+        )?;
+
+        assert!(!target.join("docs").join("a.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(target.join("docs").join("b.txt"))?,
+            "B"
+        );
+        assert!(!crate::journal::journal_path(&target, 0).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_job_with_multiple_sources() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let docs = temp_dir.path().join("docs");
+        std::fs::create_dir_all(&docs)?;
+        std::fs::write(docs.join("a.txt"), "a")?;
+        let rc = temp_dir.path().join(".bashrc");
+        std::fs::write(&rc, "rc")?;
+        let target = temp_dir.path().join("back");
+
+        let job = Job::temp_job(vec![docs, rc], vec![target.clone()], None, None, None, None);
+        run_job(&job, &RunOptions::default())?;
+        assert!(target.join("docs").join("a.txt").exists());
+        assert_eq!(std::fs::read_to_string(target.join(".bashrc"))?, "rc");
+
+        let job = Job {
+            name: Some("home".to_string()),
+            compression: Some(CompressFormat::Tar),
+            ..job
+        };
+        run_job(&job, &RunOptions::default())?;
+        assert_eq!(job.archive_path(&target), Some(target.join("home.tar")));
+        assert!(target.join("home.tar").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_job_rejects_overlapping_sources() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let docs = temp_dir.path().join("docs");
+        std::fs::create_dir_all(docs.join("inner"))?;
+        std::fs::write(docs.join("inner").join("a.txt"), "a")?;
+        let target = temp_dir.path().join("back");
 
-        // Should start with [ and end with ]
-        assert!(display_str.starts_with('['));
-        assert!(display_str.ends_with(']'));
+        let job = Job::temp_job(
+            vec![docs.clone(), docs.join("inner")],
+            vec![target.clone()],
+            Some(CompressFormat::Tar),
+            None,
+            None,
+            None,
+        );
+        let err = run_job(&job, &RunOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("is inside source"), "{err}");
+        assert!(!target.exists());
+
+        let job = Job {
+            source: vec![docs.clone(), docs],
+            ..job
+        };
+        let err = run_job(&job, &RunOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("is listed twice"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_job_excludes_target_inside_source() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let home = temp_dir.path().join("home");
+        std::fs::create_dir_all(&home)?;
+        std::fs::write(home.join("a.txt"), "a")?;
+        let target = home.join("backups");
+
+        let job = Job::temp_job(
+            vec![home.clone()],
+            vec![target.clone()],
+            None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(job.nested_targets(), [(target.as_path(), home.as_path())]);
+        run_job(&job, &RunOptions::default())?;
+        run_job(&job, &RunOptions::default())?;
+        assert!(target.join("home").join("a.txt").exists());
+        assert!(!target.join("home").join("backups").exists());
+
+        let job = Job {
+            compression: Some(CompressFormat::Tar),
+            ..job
+        };
+        run_job(&job, &RunOptions::default())?;
+        let archive = job.archive_path(&target).unwrap();
+        let mut tar = tar::Archive::new(std::fs::File::open(&archive)?);
+        let mut paths = vec![];
+        for entry in tar.entries()? {
+            paths.push(entry?.path()?.into_owned());
+        }
+        assert!(paths.contains(&PathBuf::from("home/a.txt")), "{paths:?}");
+        assert!(!paths.iter().any(|p| p.starts_with("home/backups")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_sharing_a_target_run_in_turn() {
+        let job = |id, target: &str| Job {
+            id,
+            source: vec![PathBuf::from(format!("/src/{id}"))],
+            target: vec![PathBuf::from(target)],
+            ..Default::default()
+        };
+        let chains = sharing_targets(vec![
+            job(1, "/backup/shared"),
+            job(2, "/backup/other"),
+            job(3, "/backup/shared"),
+        ]);
+        let ids: Vec<Vec<_>> = chains
+            .iter()
+            .map(|chain| chain.iter().map(|job| job.id).collect())
+            .collect();
+        assert_eq!(ids, [vec![2], vec![1, 3]]);
+    }
 
-        // Should contain both jobs
-        assert!(display_str.contains("id: 1"));
-        assert!(display_str.contains("id: 2"));
+    #[test]
+    fn test_copy_from_first_target() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        std::fs::create_dir_all(&src)?;
+        std::fs::write(src.join("a.txt"), "a")?;
+        std::fs::write(src.join("b.txt"), "b")?;
+        let local = temp_dir.path().join("local");
+        let usb = temp_dir.path().join("usb");
+        let job = Job::temp_job(
+            vec![src.clone()],
+            vec![local.clone()],
+            None,
+            None,
+            None,
+            None,
+        );
+        run_job(&job, &RunOptions::default())?;
+
+        // Only the files copied into the first target by this run are taken from it.
+        let first = (
+            local.as_path(),
+            BTreeSet::from([PathBuf::from("docs/a.txt")]),
+        );
+        let items = copy_from_first(get_items(&job, &usb)?, Some(&first), &usb);
+        let sources: Vec<_> = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Copy { src, .. } if src.is_file() => Some(src.clone()),
+                _ => None,
+            })
+            .collect();
+        assert!(sources.contains(&local.join("docs").join("a.txt")));
+        assert!(sources.contains(&src.join("b.txt")));
+        assert_eq!(sources.len(), 2);
+
+        // A damaged copy of the same size that the run leaves in place in the first
+        // target is not spread to the others.
+        let copy = local.join("docs").join("b.txt");
+        std::fs::write(&copy, "X")?;
+        let file = std::fs::File::options().write(true).open(&copy)?;
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(60))?;
+        let job = Job {
+            target: vec![local.clone(), usb.clone()],
+            ..job
+        };
+        run_job(&job, &RunOptions::default())?;
+        assert_eq!(
+            std::fs::read_to_string(usb.join("docs").join("b.txt"))?,
+            "b"
+        );
+        assert_eq!(
+            std::fs::read_to_string(usb.join("docs").join("a.txt"))?,
+            "a"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_job_fans_out_to_targets() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        std::fs::create_dir_all(&src)?;
+        std::fs::write(src.join("a.txt"), "a")?;
+        let local = temp_dir.path().join("local");
+        let usb = temp_dir.path().join("usb");
+        // A file where a directory is expected makes this target fail.
+        let broken = temp_dir.path().join("broken");
+        std::fs::write(&broken, "not a directory")?;
+
+        let targets = vec![local.clone(), broken.clone(), usb.clone()];
+        let job = Job::temp_job(vec![src], targets, None, None, None, None);
+        let err = run_job(&job, &RunOptions::default()).unwrap_err();
+        assert!(local.join("docs").join("a.txt").exists());
+        assert!(usb.join("docs").join("a.txt").exists());
+        let statuses = target_statuses(&job, &Err(err));
+        assert_eq!(statuses[0], (local.clone(), Status::Success));
+        assert!(matches!(&statuses[1], (t, Status::Failed(_)) if t == &broken));
+        assert_eq!(statuses[2], (usb.clone(), Status::Success));
+        let (_, statuses) = run_job_with_targets(&job, &RunOptions::default());
+        assert_eq!(statuses.len(), 3);
+        assert!(matches!(&statuses[1], (t, Status::Failed(_)) if t == &broken));
+
+        // The archive is written once and copied to the other targets.
+        let job = Job {
+            id: 3,
+            compression: Some(CompressFormat::Gzip),
+            ..job
+        };
+        let err = run_job(&job, &RunOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "1 of 3 targets failed");
+        assert!(local.join("docs.tar.gz").exists());
+        assert_eq!(
+            std::fs::read(local.join("docs.tar.gz"))?,
+            std::fs::read(usb.join("docs.tar.gz"))?
+        );
 
-        // Should have proper structure with braces
-        let open_braces = display_str.matches('{').count();
-        let close_braces = display_str.matches('}').count();
-        assert_eq!(open_braces, close_braces);
-        assert_eq!(open_braces, 2); // One for each job
+        // With verification, each copy is checked against the archive.
+        let job = Job {
+            verify: Some(true),
+            target: vec![local.clone(), usb.clone()],
+            ..job
+        };
+        run_job(&job, &RunOptions::default())?;
+        let manifest = Manifest::read(&usb, job.id)?.unwrap();
+        let archive = local.join("docs.tar.gz");
+        verify_copy(&job, &archive, &usb, &manifest)?;
+        std::fs::write(usb.join("docs.tar.gz"), "damaged")?;
+        let err = verify_copy(&job, &archive, &usb, &manifest).unwrap_err();
+        assert!(err.to_string().contains("the copy differs"), "{err}");
+        Ok(())
     }
 }
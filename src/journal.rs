@@ -0,0 +1,375 @@
+//! Run journal used to resume interrupted backups.
+//!
+//! While a job runs, the journal records the pre-scanned archive entries and every
+//! completed item in a small line-based file inside the target directory. When the
+//! run succeeds the journal is removed; when it is interrupted, `bk run --resume`
+//! reads it back and skips the work that was already done.
+//!
+//! A copied file is recorded with the size and modification time its source had
+//! when the copy was planned, so a source changed since is copied again.
+//!
+//! Paths are stored with `\`, line breaks and bytes that are not valid UTF-8
+//! escaped, so every file name round-trips exactly.
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Marks a completed item, followed by its destination path.
+const DONE: &str = "done\t";
+/// Marks a completed copy, followed by the size and modification time of its
+/// source and its destination path.
+const COPIED: &str = "copied\t";
+/// Marks an archive entry found by the pre-scan, followed by its source path.
+const SCAN: &str = "scan\t";
+/// Marks the end of a complete pre-scan.
+const SCANNED: &str = "scanned";
+
+/// Progress of a single job, persisted in its target directory.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    path: PathBuf,
+    file: File,
+    done: HashSet<PathBuf>,
+    copied: HashMap<PathBuf, SourceState>,
+    planned: HashMap<PathBuf, SourceState>,
+    scan: Option<Vec<PathBuf>>,
+}
+
+/// Size and modification time of the source of a copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SourceState {
+    size: u64,
+    mtime_ns: u128,
+}
+
+impl SourceState {
+    fn of(src: &Path) -> Option<SourceState> {
+        let md = fs::metadata(src).ok()?;
+        let mtime = md.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(SourceState {
+            size: md.len(),
+            mtime_ns: mtime.as_nanos(),
+        })
+    }
+
+    fn parse(line: &str) -> Option<(SourceState, PathBuf)> {
+        let mut parts = line.splitn(3, '\t');
+        let size = parts.next()?.parse().ok()?;
+        let mtime_ns = parts.next()?.parse().ok()?;
+        Some((SourceState { size, mtime_ns }, decode(parts.next()?)))
+    }
+}
+
+impl Journal {
+    /// Opens the journal of job `id` in the `dir` directory.
+    ///
+    /// Previous progress is only loaded when `resume` is set; otherwise the journal
+    /// starts empty.
+    pub(crate) fn open(dir: &Path, id: u32, resume: bool) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Self::open_at(journal_path(dir, id), resume)
+    }
+
+    /// Opens a journal kept in the file `path`, like [`Journal::open`] does.
+    pub(crate) fn open_at(path: PathBuf, resume: bool) -> Result<Self> {
+        let mut done = HashSet::new();
+        let mut copied = HashMap::new();
+        let mut scan = None;
+        if resume && path.exists() {
+            let mut entries = vec![];
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if let Some(p) = line.strip_prefix(DONE) {
+                    done.insert(decode(p));
+                } else if let Some((state, p)) =
+                    line.strip_prefix(COPIED).and_then(SourceState::parse)
+                {
+                    copied.insert(p, state);
+                } else if let Some(p) = line.strip_prefix(SCAN) {
+                    entries.push(decode(p));
+                } else if line == SCANNED {
+                    scan = Some(std::mem::take(&mut entries));
+                }
+            }
+        }
+
+        let mut file = File::create(&path)?;
+        if let Some(entries) = &scan {
+            write_scan(&mut file, entries)?;
+        }
+        for p in &done {
+            writeln!(file, "{DONE}{}", encode(p))?;
+        }
+        for (p, state) in &copied {
+            write_copied(&mut file, p, state)?;
+        }
+        file.flush()?;
+        let file = OpenOptions::new().append(true).open(&path)?;
+
+        Ok(Self {
+            path,
+            file,
+            done,
+            copied,
+            planned: HashMap::new(),
+            scan,
+        })
+    }
+
+    /// Returns `true` if the item writing to `dest` completed in a previous run.
+    pub(crate) fn is_done(&self, dest: &Path) -> bool {
+        self.done.contains(dest)
+    }
+
+    /// Returns `true` if `src` was copied to `dest` in a previous run and has the
+    /// same size and modification time as then.
+    pub(crate) fn is_copied(&self, src: &Path, dest: &Path) -> bool {
+        self.copied
+            .get(dest)
+            .is_some_and(|state| Some(*state) == SourceState::of(src))
+    }
+
+    /// Remembers the current state of `src` before it is copied to `dest`, so the
+    /// copy is recorded with it (see [`Journal::is_copied`]).
+    pub(crate) fn plan_copy(&mut self, src: &Path, dest: &Path) {
+        if let Some(state) = SourceState::of(src) {
+            self.planned.insert(dest.to_path_buf(), state);
+        }
+    }
+
+    /// Records that the item writing to `dest` completed.
+    ///
+    /// A copy planned with [`Journal::plan_copy`] is recorded with the state of its
+    /// source.
+    pub(crate) fn record_done(&mut self, dest: &Path) -> Result<()> {
+        match self.planned.remove(dest) {
+            Some(state) => {
+                write_copied(&mut self.file, dest, &state)?;
+                self.copied.insert(dest.to_path_buf(), state);
+            }
+            None => {
+                writeln!(self.file, "{DONE}{}", encode(dest))?;
+                self.done.insert(dest.to_path_buf());
+            }
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Returns the archive entries of a complete pre-scan, if one was recorded.
+    pub(crate) fn scanned(&self) -> Option<&[PathBuf]> {
+        self.scan.as_deref()
+    }
+
+    /// Records the archive entries found by the pre-scan.
+    pub(crate) fn record_scan(&mut self, entries: &[PathBuf]) -> Result<()> {
+        write_scan(&mut self.file, entries)?;
+        self.file.flush()?;
+        self.scan = Some(entries.to_vec());
+        Ok(())
+    }
+
+    /// Removes the journal after the job completed successfully.
+    pub(crate) fn finish(self) -> Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Returns the path of the journal of job `id` in the `dir` directory.
+pub(crate) fn journal_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!(".hbackup-journal-{id}"))
+}
+
+fn write_copied(file: &mut File, dest: &Path, state: &SourceState) -> Result<()> {
+    let SourceState { size, mtime_ns } = state;
+    writeln!(file, "{COPIED}{size}\t{mtime_ns}\t{}", encode(dest))?;
+    Ok(())
+}
+
+fn write_scan(file: &mut File, entries: &[PathBuf]) -> Result<()> {
+    let mut buf = String::new();
+    for p in entries {
+        buf.push_str(SCAN);
+        buf.push_str(&encode(p));
+        buf.push('\n');
+    }
+    buf.push_str(SCANNED);
+    buf.push('\n');
+    file.write_all(buf.as_bytes())?;
+    Ok(())
+}
+
+/// Encodes `path` as a single line of UTF-8 text.
+fn encode(path: &Path) -> String {
+    let mut out = String::new();
+    for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                c => out.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("\\x{b:02x}"));
+        }
+    }
+    out
+}
+
+/// Decodes a path written by [`encode`]. Unknown escapes are kept as they are.
+fn decode(line: &str) -> PathBuf {
+    let hex = |h: u8, l: u8| {
+        let digit = |b: u8| char::from(b).to_digit(16);
+        Some((digit(h)? * 16 + digit(l)?) as u8)
+    };
+    let mut bytes = Vec::with_capacity(line.len());
+    let mut rest = line.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        let (byte, tail) = match rest {
+            [b'\\', tail @ ..] => (b'\\', tail),
+            [b'n', tail @ ..] => (b'\n', tail),
+            [b'r', tail @ ..] => (b'\r', tail),
+            [b'x', h, l, tail @ ..] if hex(*h, *l).is_some() => (hex(*h, *l).unwrap_or(0), tail),
+            _ => (b'\\', rest),
+        };
+        bytes.push(byte);
+        rest = tail;
+    }
+    PathBuf::from(os_string(bytes))
+}
+
+#[cfg(unix)]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_journal_resume() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let entries = vec![PathBuf::from("/src/a"), PathBuf::from("/src/a/b.txt")];
+
+        let mut journal = Journal::open(temp_dir.path(), 1, false)?;
+        assert!(journal.scanned().is_none());
+        journal.record_scan(&entries)?;
+        journal.record_done(Path::new("/dest/a/b.txt"))?;
+        drop(journal);
+
+        let journal = Journal::open(temp_dir.path(), 1, true)?;
+        assert_eq!(journal.scanned(), Some(entries.as_slice()));
+        assert!(journal.is_done(Path::new("/dest/a/b.txt")));
+        assert!(!journal.is_done(Path::new("/dest/a/c.txt")));
+        drop(journal);
+
+        // Resuming twice keeps the recorded progress.
+        let journal = Journal::open(temp_dir.path(), 1, true)?;
+        assert!(journal.is_done(Path::new("/dest/a/b.txt")));
+        journal.finish()?;
+        assert!(!journal_path(temp_dir.path(), 1).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_without_resume_starts_empty() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        let mut journal = Journal::open(temp_dir.path(), 2, false)?;
+        journal.record_done(Path::new("/dest/file"))?;
+        drop(journal);
+
+        let journal = Journal::open(temp_dir.path(), 2, false)?;
+        assert!(!journal.is_done(Path::new("/dest/file")));
+        assert!(journal.scanned().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_round_trips_unusual_names() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut entries = vec![
+            PathBuf::from("/src/line\nbreak.txt"),
+            PathBuf::from("/src/back\\slash\\n.txt"),
+        ];
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            entries.push(PathBuf::from(std::ffi::OsStr::from_bytes(
+                b"/src/caf\xe9.txt",
+            )));
+        }
+
+        let mut journal = Journal::open(temp_dir.path(), 4, false)?;
+        journal.record_scan(&entries)?;
+        for entry in &entries {
+            journal.record_done(entry)?;
+        }
+        drop(journal);
+
+        let journal = Journal::open(temp_dir.path(), 4, true)?;
+        assert_eq!(journal.scanned(), Some(entries.as_slice()));
+        assert!(entries.iter().all(|e| journal.is_done(e)));
+        assert!(!journal.is_done(Path::new("/src/line")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_copies_follow_their_source() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let src = temp_dir.path().join("a.txt");
+        fs::write(&src, "a")?;
+        let dest = Path::new("/dest/a.txt");
+
+        let mut journal = Journal::open(temp_dir.path(), 5, false)?;
+        journal.plan_copy(&src, dest);
+        journal.record_done(dest)?;
+        drop(journal);
+
+        let journal = Journal::open(temp_dir.path(), 5, true)?;
+        assert!(journal.is_copied(&src, dest));
+        assert!(!journal.is_done(dest));
+        drop(journal);
+
+        // A source changed since the copy is copied again.
+        let file = File::options().write(true).open(&src)?;
+        file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))?;
+        let journal = Journal::open(temp_dir.path(), 5, true)?;
+        assert!(!journal.is_copied(&src, dest));
+        Ok(())
+    }
+
+    #[test]
+    fn test_incomplete_scan_is_ignored() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        fs::write(journal_path(temp_dir.path(), 3), "scan\t/src/a\n")?;
+
+        let journal = Journal::open(temp_dir.path(), 3, true)?;
+        assert!(journal.scanned().is_none());
+
+        Ok(())
+    }
+}
@@ -1,4 +1,16 @@
+pub mod cancel;
+pub mod diff;
 pub mod error;
 pub mod file_util;
+pub(crate) mod hook;
+pub mod integrity;
 pub(crate) mod item;
 pub mod job;
+pub(crate) mod journal;
+pub mod lock;
+pub mod manifest;
+pub mod parity;
+pub mod report;
+pub mod schedule;
+pub mod scrub;
+pub mod template;
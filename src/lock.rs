@@ -0,0 +1,151 @@
+//! Advisory file locks that keep concurrent `bk` processes apart.
+//!
+//! A lock is held on a small lock file for as long as the returned [`Lock`] is alive.
+//! The operating system releases it when the process exits, so a crashed run never
+//! leaves a stale lock behind.
+use crate::error::HbackupError;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval at which a contended lock is retried while waiting.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An exclusive advisory lock, released when dropped.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+/// Acquires an exclusive lock on `path`, creating the lock file if needed.
+///
+/// Without `wait` the lock is only tried once; otherwise it is retried until the
+/// timeout elapses. Contention is reported as [`HbackupError::Locked`].
+pub fn acquire(path: &Path, wait: Option<Duration>) -> Result<Lock> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    let deadline = Instant::now() + wait.unwrap_or_default();
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(Lock { _file: file }),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(RETRY_INTERVAL);
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(HbackupError::Locked(path.to_path_buf()).into());
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+}
+
+/// Returns the directory holding the target locks: the runtime directory of the
+/// user, or the cache directory where there is none.
+pub fn lock_dir() -> PathBuf {
+    dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .unwrap_or_else(env::temp_dir)
+        .join("hbackup")
+        .join("locks")
+}
+
+/// Returns the lock file guarding `target`, shared by every job writing to it.
+///
+/// The lock file lives in [`lock_dir`] and is named after the canonical path of the
+/// target, so it is the same whether or not the target exists yet, and nothing is
+/// written next to a target that is a mount point.
+pub fn target_lock_path(target: &Path) -> PathBuf {
+    let digest = Sha256::digest(canonical(target).as_os_str().as_encoded_bytes());
+    let hex: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+    lock_dir().join(format!("target-{hex}.lock"))
+}
+
+/// Resolves `path` through its longest existing ancestor, keeping the rest as it is.
+fn canonical(path: &Path) -> PathBuf {
+    let mut rest = vec![];
+    let mut dir = path;
+    loop {
+        if let Ok(resolved) = dir.canonicalize() {
+            return rest.iter().rev().fold(resolved, |p, name| p.join(name));
+        }
+        match (dir.parent(), dir.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                dir = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Returns `true` if `err` was caused by a lock held by another process.
+pub fn is_contention(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref(), Some(HbackupError::Locked(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_lock_contention() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.lock");
+
+        let lock = acquire(&path, None)?;
+        let err = acquire(&path, Some(Duration::from_millis(200))).unwrap_err();
+        assert!(is_contention(&err));
+
+        drop(lock);
+        acquire(&path, None)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_lock_path() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file = temp_dir.path().join("data.txt");
+        fs::write(&file, b"data")?;
+
+        let path = target_lock_path(&file);
+        assert!(path.starts_with(lock_dir()));
+        assert_ne!(path, target_lock_path(temp_dir.path()));
+        // Different spellings of the same target share the lock.
+        assert_eq!(
+            path,
+            target_lock_path(&temp_dir.path().join(".").join("data.txt"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_lock_path_survives_target_creation() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let target = temp_dir.path().join("backup").join("docs");
+
+        // A run that creates the target must still contend with one that started
+        // before the target existed.
+        let before = target_lock_path(&target);
+        let lock = acquire(&before, None)?;
+        fs::create_dir_all(&target)?;
+        let after = target_lock_path(&target);
+        assert_eq!(before, after);
+        assert!(is_contention(&acquire(&after, None).unwrap_err()));
+        assert_eq!(fs::read_dir(temp_dir.path().join("backup"))?.count(), 1);
+
+        drop(lock);
+        acquire(&after, None)?;
+        Ok(())
+    }
+}
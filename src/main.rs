@@ -1,38 +1,82 @@
 mod application;
+mod check;
+mod compare;
 mod constants;
-mod error;
+mod daemon;
+mod export;
+mod migration;
 mod sysexits;
+mod systemd;
+mod verify;
+mod watch;
 
-use crate::application::{Application, config_file, init_config};
-use anyhow::{Result, bail};
-use clap::{Parser, Subcommand, ValueEnum};
-use error::HbackupError;
-use hbackup::job::{BackupModel, CompressFormat, Job, Level, display_jobs, run_job, run_jobs};
+use crate::application::{
+    Application, config_file, config_lock_file, init_config, profile_file, set_config_file,
+};
+use anyhow::{Context, Result, bail};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use hbackup::cancel::{self, is_cancellation};
+use hbackup::error::{self, HbackupError};
+use hbackup::job::{
+    BackupModel, CompressFormat, Job, JobRef, Level, ListFormat, RunOptions, format_jobs,
+    run_job_with_targets, run_jobs, validate_name,
+};
+use hbackup::lock::{self, Lock};
+use hbackup::report::{RunReport, Status};
+use hbackup::schedule::Schedule;
+use hbackup::scrub::ScrubOptions;
+use hbackup::template;
 use std::io::{self, ErrorKind, Write};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
 
 /// Entry point for the hbackup CLI application.
 /// Parses command-line arguments and dispatches to the appropriate command handler.
-#[tokio::main]
-async fn main() -> Result<()> {
-    let subcommand = Opt::parse().subcommand.unwrap_or_else(|| {
+fn main() -> Result<()> {
+    let opt = Opt::parse();
+    let wait = opt.wait.map(Duration::from_secs);
+    let subcommand = opt.subcommand.unwrap_or_else(|| {
         eprintln!("bk requires at least one command to execute. See 'bk --help' for usage.");
         process::exit(sysexits::EX_KEYWORD);
     });
 
+    select_config(opt.config, opt.profile)?;
     init_config();
 
     match subcommand {
         Command::Add {
             source,
             target,
+            add_target,
             compression,
             level,
             ignore,
             model,
+            name,
+            tags,
+            hooks,
+            schedule,
+            verify,
+            parity,
         } => {
-            add(source, target, compression, level, ignore, model)?;
+            let add_params = AddParams {
+                source,
+                target,
+                add_target,
+                compression,
+                level,
+                ignore,
+                model,
+                name,
+                tags,
+                hooks,
+                schedule,
+                verify,
+                parity,
+            };
+            let _lock = lock_config(wait)?;
+            add(add_params)?;
         }
         Command::Run {
             source,
@@ -42,28 +86,52 @@ async fn main() -> Result<()> {
             level,
             ignore,
             model,
+            resume,
+            tag,
+            skip_disabled,
         } => {
+            install_signal_handlers();
+            let opts = RunOptions { resume, wait };
             match (id, source, target) {
                 (Some(ids), _, _) => {
-                    run_by_id(ids);
+                    run_by_id(ids, skip_disabled, &opts)?;
+                }
+                _ if tag.is_some() => {
+                    run_by_tag(tag.as_deref().unwrap_or_default(), &opts)?;
                 }
                 (_, Some(source), Some(target)) => {
-                    let source = canonicalize(source)?;
-                    let target = canonicalize(target)?;
+                    // The job expands its paths like a configured one does.
+                    let source = template::escape(&canonicalize(source)?);
+                    let target = template::escape(&canonicalize(target)?);
                     if compression.is_some() && model == Some(BackupModel::Mirror) {
                         bail!(HbackupError::InvalidCompressionForMirror);
                     }
 
                     // The temporary job id is set to 0
-                    let job = Job::temp_job(source, target, compression, level, ignore, model);
-                    run_job(&job)?;
+                    let job = Job::temp_job(
+                        vec![source],
+                        vec![target],
+                        compression,
+                        level,
+                        ignore,
+                        model,
+                    );
+                    run_single(&job, &opts)?;
                 }
-                _ => run()?,
+                _ => run(&opts)?,
             }
         }
-        Command::List { id, gte, lte } => {
-            let jobs = if let Some(ids) = id {
-                Application::list_by_ids(ids)
+        Command::List {
+            id,
+            gte,
+            lte,
+            tag,
+            expanded,
+            filter,
+            format,
+        } => {
+            let mut jobs = if let Some(ids) = id {
+                Application::list_by_refs(ids)
             } else if let Some(gte) = gte {
                 Application::list_by_gte(gte)
             } else if let Some(lte) = lte {
@@ -71,38 +139,125 @@ async fn main() -> Result<()> {
             } else {
                 Application::get_jobs()
             };
-            println!("{}", display_jobs(jobs));
+            if let Some(tag) = tag {
+                jobs.retain(|job| job.has_tag(&tag));
+            }
+            jobs.retain(|job| filter.matches(job));
+            if expanded {
+                jobs = jobs
+                    .into_iter()
+                    .map(|job| match job.expanded() {
+                        Ok(expanded) => expanded,
+                        Err(e) => {
+                            eprintln!("Cannot expand the paths of job {}: {e}", job.id);
+                            job
+                        }
+                    })
+                    .collect();
+            }
+            let output = format_jobs(jobs, format)?;
+            if !output.is_empty() {
+                println!("{}", output.trim_end());
+            }
         }
         Command::Delete { id, all, yes } => {
+            let _lock = lock_config(wait)?;
             delete(id, all, yes)?;
         }
         Command::Edit {
             id,
             source,
+            add_source,
             target,
+            add_target,
             compression,
             level,
             ignore,
             clear,
             model,
             swap,
+            name,
+            tags,
+            hooks,
+            schedule,
+            verify,
+            parity,
         } => {
             let edit_params = EditParams {
                 id,
                 source,
+                add_source,
                 target,
+                add_target,
                 compression,
                 level,
                 ignore,
                 clear,
                 model,
                 swap,
+                name,
+                tags,
+                hooks,
+                schedule,
+                verify,
+                parity,
             };
+            let _lock = lock_config(wait)?;
             edit(edit_params)?;
         }
-        Command::Config => {
-            println!("   {}", config_file().display());
+        Command::Enable { id } => {
+            let _lock = lock_config(wait)?;
+            set_enabled(id, true)?;
         }
+        Command::Disable { id } => {
+            let _lock = lock_config(wait)?;
+            set_enabled(id, false)?;
+        }
+        Command::Daemon => {
+            install_signal_handlers();
+            daemon::run()?;
+        }
+        Command::Watch { id, debounce } => {
+            install_signal_handlers();
+            watch::run(id, Duration::from_millis(debounce))?;
+        }
+        Command::Schedule { action } => match action {
+            ScheduleCommand::Install { id, print } => systemd::install(id, print)?,
+            ScheduleCommand::Uninstall { id } => systemd::uninstall(id)?,
+            ScheduleCommand::Status { id } => systemd::status(id)?,
+        },
+        Command::Check { id } => check::run(id)?,
+        Command::Verify { id, deep } => verify::run(id, deep)?,
+        Command::Scrub {
+            id,
+            repair,
+            resume,
+            max_rate,
+        } => {
+            install_signal_handlers();
+            let opts = ScrubOptions {
+                repair,
+                resume,
+                max_rate,
+                wait,
+            };
+            verify::scrub(id, &opts)?;
+        }
+        Command::Diff { id, checksum, json } => compare::run(id, checksum, json)?,
+        Command::Repair { id } => verify::repair(id, wait)?,
+        Command::Export { id, format } => export::export(id, format)?,
+        Command::Import {
+            file,
+            format,
+            replace,
+        } => {
+            let _lock = lock_config(wait)?;
+            export::import(&file, format, replace)?;
+        }
+        Command::Config { action } => match action {
+            None => println!("   {}", config_file().display()),
+            Some(ConfigCommand::Show { resolved }) => show_config(resolved)?,
+        },
     }
     Ok(())
 }
@@ -114,6 +269,15 @@ struct Opt {
     /// Subcommand to execute.
     #[command(subcommand)]
     pub subcommand: Option<Command>,
+    /// Seconds to wait for a configuration or job lock held by another bk process
+    #[arg(long, global = true, value_name = "SECONDS")]
+    pub wait: Option<u64>,
+    /// Configuration file to use instead of the default one [env: HBACKUP_CONFIG]
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "profile")]
+    pub config: Option<PathBuf>,
+    /// Use the configuration file of a profile, `hbackup/<NAME>.toml`
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
 }
 
 /// Supported hbackup commands.
@@ -121,10 +285,14 @@ struct Opt {
 enum Command {
     /// Add a new backup job to the configuration.
     Add {
-        /// Source file or directory path.
-        source: PathBuf,
+        /// Source file or directory paths. Each source is backed up under its own name.
+        #[arg(required = true, num_args = 1..)]
+        source: Vec<PathBuf>,
         /// Target file or directory path.
         target: PathBuf,
+        /// Additional target that receives its own copy of the backup (repeatable)
+        #[arg(long)]
+        add_target: Vec<PathBuf>,
         /// Compression format.
         #[arg(short, long)]
         compression: Option<CompressFormat>,
@@ -136,6 +304,24 @@ enum Command {
         /// Backup model
         #[arg(short, long, required = false)]
         model: Option<BackupModel>,
+        /// Unique name the job can be addressed by instead of its id
+        #[arg(short, long, value_parser = parse_name)]
+        name: Option<String>,
+        /// Tags used to select groups of jobs (comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        tags: Option<Vec<String>>,
+        #[command(flatten)]
+        hooks: HookArgs,
+        /// Schedule for `bk daemon`: a cron expression, `@daily` or `every 6h`
+        #[arg(long, value_parser = parse_schedule)]
+        schedule: Option<String>,
+        /// Verify the archive right after each run (compressed jobs only)
+        #[arg(long, requires = "compression")]
+        verify: bool,
+        /// Write Reed-Solomon recovery records of this size beside the archive, in
+        /// percent of the archive (compressed jobs only)
+        #[arg(long, value_name = "PERCENT", requires = "compression", value_parser = clap::value_parser!(u8).range(1..=100))]
+        parity: Option<u8>,
     },
     /// Run backup jobs.
     Run {
@@ -151,33 +337,53 @@ enum Command {
         /// Compression level
         #[arg(short, long, required = false, requires = "compression")]
         level: Option<Level>,
-        /// Job id(s) to run.
+        /// Job id(s) or name(s) to run.
         #[arg(short, long, required = false, value_delimiter = ',', conflicts_with_all = ["source", "target", "compression"])]
-        id: Option<Vec<u32>>,
+        id: Option<Vec<JobRef>>,
         /// Ignore a specific list of files or directories
         #[arg(short = 'g', long, value_delimiter = ',')]
         ignore: Option<Vec<String>>,
         /// Backup model
         #[arg(short, long, required = false)]
         model: Option<BackupModel>,
+        /// Resume an interrupted run, skipping the work it already completed
+        #[arg(long)]
+        resume: bool,
+        /// Run all jobs carrying this tag.
+        #[arg(long, conflicts_with_all = ["source", "target", "compression", "id"])]
+        tag: Option<String>,
+        /// Skip the disabled jobs among those selected with --id.
+        #[arg(long, requires = "id")]
+        skip_disabled: bool,
     },
     /// List all backup jobs.
     List {
-        /// List jobs by ids.
+        /// List jobs by ids or names.
         #[arg(short, long, required = false, value_delimiter = ',', conflicts_with_all = ["gte", "lte"])]
-        id: Option<Vec<u32>>,
+        id: Option<Vec<JobRef>>,
         /// List jobs by id greater than or equal to.
         #[arg(short = 'g', long, required = false, conflicts_with_all = ["id", "lte"])]
         gte: Option<u32>,
         /// List jobs by id less than or equal to.
         #[arg(short = 'l', long, required = false, conflicts_with_all = ["id", "gte"])]
         lte: Option<u32>,
+        /// List only jobs carrying this tag.
+        #[arg(long)]
+        tag: Option<String>,
+        /// Show sources and targets with their templates expanded.
+        #[arg(short, long)]
+        expanded: bool,
+        #[command(flatten)]
+        filter: ListFilter,
+        /// Output format.
+        #[arg(short, long, value_enum, default_value_t)]
+        format: ListFormat,
     },
     /// Delete backup jobs by id or delete all jobs.
     Delete {
-        /// Delete multiple jobs by ids. Cannot be used with --all.
+        /// Delete multiple jobs by ids or names. Cannot be used with --all.
         #[arg(value_delimiter = ',', conflicts_with = "all")]
-        id: Option<Vec<u32>>,
+        id: Option<Vec<JobRef>>,
         /// Delete all jobs. Cannot be used with --id.
         #[arg(short, long, conflicts_with = "id")]
         all: bool,
@@ -185,37 +391,279 @@ enum Command {
         #[arg(short = 'y', long, conflicts_with = "id")]
         yes: bool,
     },
-    /// Edit a backup job by id. At least one of source/target/compression/level/ignore/clear must be provided.
+    /// Edit a backup job by id. At least one field to change must be provided.
+    #[command(group(
+        ArgGroup::new("changes")
+            .required(true)
+            .multiple(true)
+            .args([
+                "source",
+                "add_source",
+                "target",
+                "add_target",
+                "compression",
+                "level",
+                "ignore",
+                "model",
+                "clear",
+                "swap",
+                "pre_hook",
+                "post_hook",
+                "on_failure",
+                "hook_timeout",
+                "schedule",
+                "name",
+                "tags",
+                "verify",
+                "parity",
+            ])
+    ))]
     Edit {
-        /// Edit job by id.
-        id: u32,
-        /// New source file or directory path
-        #[arg(short, long, required_unless_present_any = ["target", "compression", "level", "ignore", "model", "clear", "swap"])]
-        source: Option<PathBuf>,
-        /// New target file or directory path
-        #[arg(short, long, required_unless_present_any = ["source", "compression", "level", "ignore", "model", "clear", "swap"])]
-        target: Option<PathBuf>,
+        /// Edit job by id or name.
+        id: JobRef,
+        /// New source file or directory path, replacing the current sources (repeatable)
+        #[arg(short, long)]
+        source: Option<Vec<PathBuf>>,
+        /// Additional source file or directory path (repeatable)
+        #[arg(long)]
+        add_source: Option<Vec<PathBuf>>,
+        /// New target file or directory path, replacing the current targets (repeatable)
+        #[arg(short, long)]
+        target: Option<Vec<PathBuf>>,
+        /// Additional target file or directory path (repeatable)
+        #[arg(long)]
+        add_target: Option<Vec<PathBuf>>,
         /// Compression format
-        #[arg(short, long, required_unless_present_any = ["source", "target", "level", "ignore", "model", "clear", "swap"])]
+        #[arg(short, long)]
         compression: Option<CompressFormat>,
         /// Compression level
-        #[arg(short, long, required_unless_present_any = ["source", "target", "compression", "ignore", "model", "clear", "swap"])]
+        #[arg(short, long)]
         level: Option<Level>,
         /// Ignore a specific list of files or directories
-        #[arg(short = 'g', long, value_delimiter = ',', required_unless_present_any = ["source", "target", "compression", "level", "model", "clear", "swap"])]
+        #[arg(short = 'g', long, value_delimiter = ',')]
         ignore: Option<Vec<String>>,
         /// Backup model
-        #[arg(short, long, required_unless_present_any = ["source", "target", "compression", "level", "ignore", "clear", "swap"])]
+        #[arg(short, long)]
         model: Option<BackupModel>,
-        /// Clear specified fields (comma-separated: compression,level,ignore)
-        #[arg(long, value_delimiter = ',', required_unless_present_any = ["source", "target", "compression", "level", "ignore", "model", "swap"])]
+        /// Clear specified fields (comma-separated, e.g. compression,level,ignore)
+        #[arg(long, value_delimiter = ',')]
         clear: Option<Vec<ClearField>>,
         /// Swap source and target paths(only supports file-to-file swap)
-        #[arg(long, conflicts_with_all = ["source", "target"], required_unless_present_any = ["source", "target", "compression", "level", "ignore", "model", "clear"])]
+        #[arg(long, conflicts_with_all = ["source", "add_source", "target", "add_target"])]
         swap: bool,
+        /// New unique name of the job
+        #[arg(short, long, value_parser = parse_name)]
+        name: Option<String>,
+        /// New tags of the job (comma-separated), replacing the current ones
+        #[arg(long, value_delimiter = ',')]
+        tags: Option<Vec<String>>,
+        #[command(flatten)]
+        hooks: HookArgs,
+        /// Schedule for `bk daemon`: a cron expression, `@daily` or `every 6h`
+        #[arg(long, value_parser = parse_schedule)]
+        schedule: Option<String>,
+        /// Verify the archive right after each run (compressed jobs only)
+        #[arg(long)]
+        verify: bool,
+        /// Write Reed-Solomon recovery records of this size beside the archive, in
+        /// percent of the archive (compressed jobs only)
+        #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(1..=100))]
+        parity: Option<u8>,
+    },
+    /// Enable disabled jobs by id or name.
+    Enable {
+        /// Job ids or names (comma-separated).
+        #[arg(required = true, value_delimiter = ',')]
+        id: Vec<JobRef>,
+    },
+    /// Disable jobs by id or name without deleting them. `bk run` skips disabled jobs.
+    Disable {
+        /// Job ids or names (comma-separated).
+        #[arg(required = true, value_delimiter = ',')]
+        id: Vec<JobRef>,
+    },
+    /// Run jobs on their schedule until interrupted.
+    Daemon,
+    /// Back up jobs continuously as their sources change.
+    Watch {
+        /// Only watch these job ids or names.
+        #[arg(short, long, value_delimiter = ',')]
+        id: Option<Vec<JobRef>>,
+        /// Milliseconds without further changes before they are backed up
+        #[arg(long, default_value_t = 2000)]
+        debounce: u64,
+    },
+    /// Manage systemd user timers that run scheduled jobs without `bk daemon`.
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleCommand,
+    },
+    /// Check jobs for missing sources, unwritable targets and invalid settings.
+    Check {
+        /// Only check these job ids or names.
+        #[arg(short, long, value_delimiter = ',')]
+        id: Option<Vec<JobRef>>,
+    },
+    /// Decode the archives of compressed jobs and compare them with the sources.
+    Verify {
+        /// Only verify these job ids or names.
+        #[arg(short, long, value_delimiter = ',')]
+        id: Option<Vec<JobRef>>,
+        /// Also compare the size and SHA-256 hash of every file.
+        #[arg(long)]
+        deep: bool,
+    },
+    /// Re-read backups and compare every file with the hashes of their manifest.
+    Scrub {
+        /// Only scrub these job ids or names.
+        #[arg(short, long, value_delimiter = ',')]
+        id: Option<Vec<JobRef>>,
+        /// Copy corrupted or missing files again from the source if it is unchanged
+        #[arg(long)]
+        repair: bool,
+        /// Resume an interrupted scrub, skipping the files it already checked
+        #[arg(long)]
+        resume: bool,
+        /// Maximum read rate in bytes per second, e.g. `512K`, `20M` or `1G`
+        #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+        max_rate: Option<u64>,
+    },
+    /// Show how the sources of a job differ from its backups.
+    Diff {
+        /// Job id or name.
+        id: JobRef,
+        /// Compare the SHA-256 hash of files instead of their modification time.
+        #[arg(long)]
+        checksum: bool,
+        /// Print the differences as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Reconstruct damaged archives from the recovery records written beside them.
+    Repair {
+        /// Only repair these job ids or names.
+        #[arg(short, long, value_delimiter = ',')]
+        id: Option<Vec<JobRef>>,
+    },
+    /// Print jobs as JSON, TOML or YAML.
+    Export {
+        /// Only export these job ids or names.
+        #[arg(short, long, value_delimiter = ',')]
+        id: Option<Vec<JobRef>>,
+        /// Output format.
+        #[arg(short, long, value_enum, default_value = "toml")]
+        format: export::Format,
+    },
+    /// Import jobs from a file written by `bk export`.
+    Import {
+        /// File to import, or `-` for stdin.
+        file: PathBuf,
+        /// Input format. Guessed from the file extension if not given, TOML otherwise.
+        #[arg(short, long, value_enum)]
+        format: Option<export::Format>,
+        /// Replace all existing jobs with the imported ones, instead of adding to them.
+        #[arg(long)]
+        replace: bool,
     },
     /// Display the absolute path of the configuration file and manage config backup/reset/rollback.
-    Config,
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigCommand>,
+    },
+}
+
+/// Actions of the `config` command.
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the configuration file.
+    Show {
+        /// Print the effective jobs, with included files and host overrides applied.
+        #[arg(long)]
+        resolved: bool,
+    },
+}
+
+/// Actions of the `schedule` command.
+#[derive(Subcommand, Debug)]
+enum ScheduleCommand {
+    /// Write and enable systemd units for scheduled jobs.
+    Install {
+        /// Only install the units of these job ids or names.
+        #[arg(short, long, value_delimiter = ',')]
+        id: Option<Vec<JobRef>>,
+        /// Print the unit files instead of writing them.
+        #[arg(short, long)]
+        print: bool,
+    },
+    /// Disable and remove the systemd units of jobs.
+    Uninstall {
+        /// Only remove the units of these job ids or names.
+        #[arg(short, long, value_delimiter = ',')]
+        id: Option<Vec<JobRef>>,
+    },
+    /// Show whether the systemd units of scheduled jobs are installed.
+    Status {
+        /// Only show these job ids or names.
+        #[arg(short, long, value_delimiter = ',')]
+        id: Option<Vec<JobRef>>,
+    },
+}
+
+/// Filters of the list command.
+#[derive(Args, Debug)]
+struct ListFilter {
+    /// List only jobs with a source containing this text
+    #[arg(long, value_name = "TEXT")]
+    source: Option<String>,
+    /// List only jobs with a target containing this text
+    #[arg(long, value_name = "TEXT")]
+    target: Option<String>,
+    /// List only jobs using this compression format
+    #[arg(long)]
+    compression: Option<CompressFormat>,
+    /// List only jobs using this backup model
+    #[arg(long)]
+    model: Option<BackupModel>,
+}
+
+impl ListFilter {
+    /// Returns `true` if `job` passes all given filters.
+    fn matches(&self, job: &Job) -> bool {
+        let contains = |paths: &[PathBuf], text: &Option<String>| {
+            text.as_ref().is_none_or(|text| {
+                paths
+                    .iter()
+                    .any(|path| path.to_string_lossy().contains(text.as_str()))
+            })
+        };
+        contains(&job.source, &self.source)
+            && contains(&job.target, &self.target)
+            && self
+                .compression
+                .as_ref()
+                .is_none_or(|c| job.compression.as_ref() == Some(c))
+            && self
+                .model
+                .as_ref()
+                .is_none_or(|m| job.model.clone().unwrap_or_default() == *m)
+    }
+}
+
+/// Shell hooks that run around a backup job.
+#[derive(Args, Debug)]
+struct HookArgs {
+    /// Shell command run before the backup; a failing pre-hook aborts the job
+    #[arg(long)]
+    pre_hook: Option<String>,
+    /// Shell command run after a successful backup
+    #[arg(long)]
+    post_hook: Option<String>,
+    /// Shell command run when the pre-hook or the backup fails
+    #[arg(long)]
+    on_failure: Option<String>,
+    /// Timeout in seconds for each hook
+    #[arg(long)]
+    hook_timeout: Option<u64>,
 }
 
 /// Fields that can be cleared in the edit command
@@ -229,89 +677,333 @@ enum ClearField {
     Ignore,
     /// Clear backup model
     Model,
+    /// Clear pre-hook command
+    PreHook,
+    /// Clear post-hook command
+    PostHook,
+    /// Clear failure hook command
+    OnFailure,
+    /// Clear hook timeout
+    HookTimeout,
+    /// Clear schedule
+    Schedule,
+    /// Clear job name
+    Name,
+    /// Clear tags
+    Tags,
+    /// Stop verifying the archive after each run
+    Verify,
+    /// Stop writing recovery records beside the archive
+    Parity,
+}
+
+/// Parameters for adding a backup job
+struct AddParams {
+    pub source: Vec<PathBuf>,
+    pub target: PathBuf,
+    pub add_target: Vec<PathBuf>,
+    pub compression: Option<CompressFormat>,
+    pub level: Option<Level>,
+    pub ignore: Option<Vec<String>>,
+    pub model: Option<BackupModel>,
+    pub name: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub hooks: HookArgs,
+    pub schedule: Option<String>,
+    pub verify: bool,
+    pub parity: Option<u8>,
 }
 
 /// Parameters for editing a backup job
 struct EditParams {
-    pub id: u32,
-    pub source: Option<PathBuf>,
-    pub target: Option<PathBuf>,
+    pub id: JobRef,
+    pub source: Option<Vec<PathBuf>>,
+    pub add_source: Option<Vec<PathBuf>>,
+    pub target: Option<Vec<PathBuf>>,
+    pub add_target: Option<Vec<PathBuf>>,
     pub compression: Option<CompressFormat>,
     pub level: Option<Level>,
     pub ignore: Option<Vec<String>>,
     pub clear: Option<Vec<ClearField>>,
     pub model: Option<BackupModel>,
     pub swap: bool,
+    pub name: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub hooks: HookArgs,
+    pub schedule: Option<String>,
+    pub verify: bool,
+    pub parity: Option<u8>,
 }
 
 /// Adds a new backup job to the configuration file.
-fn add(
-    source: PathBuf,
-    target: PathBuf,
-    comp: Option<CompressFormat>,
-    level: Option<Level>,
-    ignore: Option<Vec<String>>,
-    model: Option<BackupModel>,
-) -> Result<()> {
-    let source = canonicalize(source)?;
-    let target = canonicalize(target)?;
-    if comp.is_some() && model == Some(BackupModel::Mirror) {
+fn add(params: AddParams) -> Result<()> {
+    let AddParams {
+        source,
+        target,
+        add_target,
+        compression,
+        level,
+        ignore,
+        model,
+        name,
+        tags,
+        hooks,
+        schedule,
+        verify,
+        parity,
+    } = params;
+    let source = source
+        .into_iter()
+        .map(job_path)
+        .collect::<Result<Vec<_>>>()?;
+    let target = std::iter::once(target)
+        .chain(add_target)
+        .map(job_path)
+        .collect::<Result<Vec<_>>>()?;
+    if compression.is_some() && model == Some(BackupModel::Mirror) {
         return Err(HbackupError::InvalidCompressionForMirror.into());
     }
 
+    let mut job = Job::temp_job(source, target, compression, level, ignore, model);
+    job.pre_hook = hooks.pre_hook;
+    job.post_hook = hooks.post_hook;
+    job.on_failure = hooks.on_failure;
+    job.hook_timeout = hooks.hook_timeout;
+    job.schedule = schedule;
+    job.name = name;
+    job.tags = tags;
+    job.verify = verify.then_some(true);
+    job.parity = parity;
+
+    check_nesting(&job)?;
+
     let mut app = Application::load_config();
-    app.add_job(source, target, comp, level, ignore, model)?;
+    if let Some(name) = &job.name {
+        app.check_name(name, job.id)?;
+    }
+    app.push_job(job)?;
     app.write()?;
 
     Ok(())
 }
 
+/// Selects the configuration file from `--config`, `--profile` or the
+/// `HBACKUP_CONFIG` environment variable, in that order of precedence.
+fn select_config(config: Option<PathBuf>, profile: Option<String>) -> Result<()> {
+    let path = match (config, profile) {
+        (Some(path), _) => path,
+        (None, Some(name)) => profile_file(&name)?,
+        (None, None) => match std::env::var_os("HBACKUP_CONFIG") {
+            Some(path) if !path.is_empty() => PathBuf::from(path),
+            _ => return Ok(()),
+        },
+    };
+    set_config_file(&path)
+}
+
+/// Prints the configuration file, or the effective configuration if `resolved`.
+fn show_config(resolved: bool) -> Result<()> {
+    let app = Application::load_config();
+    if !resolved {
+        print!("{}", toml::to_string_pretty(&app)?);
+        return Ok(());
+    }
+    let effective = Application {
+        jobs: app.resolve()?,
+        ..Application::new()
+    };
+    print!("{}", toml::to_string_pretty(&effective)?);
+    Ok(())
+}
+
+/// Installs SIGINT/SIGTERM handlers for the duration of a run.
+///
+/// The first signal asks running jobs to stop scheduling new work and clean up;
+/// a second signal terminates the process immediately.
+fn install_signal_handlers() {
+    std::thread::spawn(|| {
+        let rt = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(rt) => rt,
+            Err(e) => {
+                eprintln!("Failed to install signal handlers: {e}");
+                return;
+            }
+        };
+        rt.block_on(async {
+            wait_for_signal().await;
+            cancel::cancel();
+            eprintln!("\nCancelling backup, waiting for in-flight copies to finish...");
+            eprintln!("Press Ctrl-C again to force quit.");
+            wait_for_signal().await;
+            eprintln!("\nForce quit.");
+            process::exit(sysexits::EX_SIGINT);
+        });
+    });
+}
+
+/// Waits for the next SIGINT (Ctrl-C) or SIGTERM.
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
 /// Runs all backup jobs defined in the configuration.
-fn run() -> Result<()> {
+fn run(opts: &RunOptions) -> Result<()> {
     let jobs = Application::get_jobs();
     if jobs.is_empty() {
         println!("No jobs are backed up!");
-    } else if jobs.len() == 1 {
-        run_job(&jobs[0])?;
+        return Ok(());
+    }
+    run_enabled(jobs, opts)
+}
+
+/// Runs the enabled jobs among `jobs`, noting the disabled ones in the run summary.
+fn run_enabled(jobs: Vec<Job>, opts: &RunOptions) -> Result<()> {
+    let (jobs, disabled): (Vec<_>, Vec<_>) = jobs.into_iter().partition(Job::is_enabled);
+    if jobs.len() == 1 && disabled.is_empty() {
+        return run_single(&jobs[0], opts);
+    }
+    let mut report = if jobs.is_empty() {
+        RunReport::new()
     } else {
-        run_jobs(jobs)?;
+        run_jobs(jobs, opts)?
+    };
+    for job in disabled {
+        report.push(job.id, Status::Disabled);
     }
+    finish(report);
     Ok(())
 }
 
-/// Runs a backup job by its id.
-fn run_by_id(ids: Vec<u32>) {
+/// Runs a single backup job, recording a cancellation or lock contention in the
+/// run report. A job with several targets reports the state of each target.
+fn run_single(job: &Job, opts: &RunOptions) -> Result<()> {
+    let (result, targets) = run_job_with_targets(job, opts);
+    let mut report = RunReport::new();
+    match result {
+        Err(e) if is_cancellation(&e) => report.push_targets(job.id, Status::Cancelled, targets),
+        Err(e) if lock::is_contention(&e) => {
+            eprintln!("{e}");
+            report.push(job.id, Status::Locked);
+        }
+        Err(e) if !targets.is_empty() => {
+            eprintln!("Failed to run job with id {}: {e}\n", job.id);
+            report.push_targets(job.id, Status::Failed(e.to_string()), targets);
+        }
+        Ok(()) if !targets.is_empty() => report.push_targets(job.id, Status::Success, targets),
+        res => return res,
+    }
+    finish(report);
+    Ok(())
+}
+
+/// Prints the run report and exits with a matching status code if the run
+/// was cancelled, any job failed or any job was locked by another run.
+fn finish(report: RunReport) {
+    print!("{report}");
+    if report.is_cancelled() {
+        process::exit(sysexits::EX_SIGINT);
+    } else if report.has_failures() {
+        process::exit(sysexits::EX_IOERR);
+    } else if report.is_locked() {
+        process::exit(sysexits::EX_TEMPFAIL);
+    }
+}
+
+/// Locks the configuration file for a read-modify-write, exiting with
+/// `EX_TEMPFAIL` if another bk process holds the lock.
+fn lock_config(wait: Option<Duration>) -> Result<Lock> {
+    match lock::acquire(&config_lock_file(), wait) {
+        Err(e) if lock::is_contention(&e) => {
+            eprintln!("{e}");
+            process::exit(sysexits::EX_TEMPFAIL);
+        }
+        res => res,
+    }
+}
+
+/// Runs backup jobs by their ids or names, including disabled ones unless
+/// `skip_disabled` is set.
+fn run_by_id(ids: Vec<JobRef>, skip_disabled: bool, opts: &RunOptions) -> Result<()> {
     let jobs = Application::get_jobs();
     if jobs.is_empty() {
         println!("No jobs are backed up!");
-        return;
+        return Ok(());
     }
     let mut vec = vec![];
     for id in ids {
-        match jobs.iter().find(|j| j.id == id) {
+        match jobs.iter().find(|j| id.matches(j)) {
             Some(job) => {
                 vec.push(job.clone());
             }
             None => {
-                eprintln!("Job with id {id} not found.");
+                eprintln!("Job {id} not found.");
             }
         }
     }
+    if skip_disabled && !vec.is_empty() {
+        return run_enabled(vec, opts);
+    }
+    run_selected(vec, opts);
+    Ok(())
+}
+
+/// Runs all enabled backup jobs carrying `tag`.
+fn run_by_tag(tag: &str, opts: &RunOptions) -> Result<()> {
+    let jobs: Vec<_> = Application::get_jobs()
+        .into_iter()
+        .filter(|job| job.has_tag(tag))
+        .collect();
+    if jobs.is_empty() {
+        eprintln!("No jobs are tagged {tag:?}.");
+        process::exit(1);
+    }
+    run_enabled(jobs, opts)
+}
+
+/// Runs the selected backup jobs, exiting with an error if none were selected.
+fn run_selected(vec: Vec<Job>, opts: &RunOptions) {
     if vec.is_empty() {
         process::exit(1);
     } else if vec.len() == 1 {
-        if let Err(e) = run_job(&vec[0]) {
+        if let Err(e) = run_single(&vec[0], opts) {
             eprintln!("Failed to run job with id {}: {e}\n", vec[0].id);
             process::exit(sysexits::EX_IOERR);
         }
-    } else if let Err(e) = run_jobs(vec) {
-        eprintln!("Failed to run jobs: {e}\n");
-        process::exit(sysexits::EX_IOERR);
+    } else {
+        match run_jobs(vec, opts) {
+            Ok(report) => finish(report),
+            Err(e) => {
+                eprintln!("Failed to run jobs: {e}\n");
+                process::exit(sysexits::EX_IOERR);
+            }
+        }
     }
 }
 
 /// Deletes a job by id or deletes all jobs.
-fn delete(id: Option<Vec<u32>>, all: bool, yes: bool) -> Result<()> {
+fn delete(id: Option<Vec<JobRef>>, all: bool, yes: bool) -> Result<()> {
     if all {
         let mut app = Application::load_config();
         if app.jobs.is_empty() {
@@ -343,12 +1035,21 @@ fn delete(id: Option<Vec<u32>>, all: bool, yes: bool) -> Result<()> {
     } else if let Some(ids) = id {
         let mut app = Application::load_config();
         let mut msg = String::new();
-        ids.into_iter().for_each(|id| match app.remove_job(id) {
-            Some(_) => msg.push_str(&format!("Job with id {id} deleted successfully.\n")),
-            None => msg.push_str(&format!(
-                "Job deletion failed. Job with id {id} cannot be found.\n"
-            )),
-        });
+        for id in ids {
+            let removed = match app.find_job(&id) {
+                Ok(job) => job
+                    .map(|job| job.id)
+                    .and_then(|job_id| app.remove_job(job_id)),
+                Err(e) => {
+                    msg.push_str(&format!("Job deletion failed. {e}\n"));
+                    continue;
+                }
+            };
+            match removed {
+                Some(_) => msg.push_str(&format!("Job {id} deleted successfully.\n")),
+                None => msg.push_str(&format!("Job deletion failed. Job {id} cannot be found.\n")),
+            }
+        }
         app.write()?;
         msg.remove(msg.len() - 1);
         println!("{}", msg);
@@ -358,36 +1059,87 @@ fn delete(id: Option<Vec<u32>>, all: bool, yes: bool) -> Result<()> {
     Ok(())
 }
 
+/// Enables or disables jobs by id or name.
+fn set_enabled(ids: Vec<JobRef>, enabled: bool) -> Result<()> {
+    let mut app = Application::load_config();
+    let state = if enabled { "enabled" } else { "disabled" };
+    for id in ids {
+        let job_id = match app.find_job(&id) {
+            Ok(job) => job.map(|job| job.id),
+            Err(e) => {
+                eprintln!("{e}");
+                continue;
+            }
+        };
+        match app.jobs.iter_mut().find(|j| Some(j.id) == job_id) {
+            Some(job) => {
+                job.enabled = if enabled { None } else { Some(false) };
+                println!("Job {id} {state}.");
+            }
+            None => eprintln!("Job {id} not found."),
+        }
+    }
+    app.write()
+}
+
 /// Edits a job by id, updating its source, target, and/or compression settings.
 fn edit(params: EditParams) -> Result<()> {
     let EditParams {
         id,
         source,
+        add_source,
         target,
+        add_target,
         compression,
         level,
         ignore,
         model,
         clear,
         swap,
+        name,
+        tags,
+        hooks,
+        schedule,
+        verify,
+        parity,
     } = params;
-    let source = source.map(canonicalize);
-    let target = target.map(canonicalize);
+    let source = source
+        .map(|paths| paths.into_iter().map(job_path).collect::<Result<Vec<_>>>())
+        .transpose()?;
+    let add_source = add_source
+        .map(|paths| paths.into_iter().map(job_path).collect::<Result<Vec<_>>>())
+        .transpose()?;
+    let target = target
+        .map(|paths| paths.into_iter().map(job_path).collect::<Result<Vec<_>>>())
+        .transpose()?;
+    let add_target = add_target
+        .map(|paths| paths.into_iter().map(job_path).collect::<Result<Vec<_>>>())
+        .transpose()?;
     if compression.is_some() && model == Some(BackupModel::Mirror) {
         bail!(HbackupError::InvalidCompressionForMirror);
     }
 
     let mut app = Application::load_config();
-    if app.jobs.is_empty() {
-        println!("Job with id {id} not found.");
-        return Ok(());
+    let job_id = app.find_job(&id)?.map(|job| job.id);
+    if let (Some(name), Some(job_id)) = (&name, job_id) {
+        app.check_name(name, job_id)?;
     }
-    if let Some(job) = app.jobs.iter_mut().find(|j| j.id == id) {
-        if let Some(path) = source {
-            job.source = path?;
+    if let Some(job) = app.jobs.iter_mut().find(|j| Some(j.id) == job_id) {
+        if let Some(paths) = source {
+            job.source = paths;
+        }
+        for path in add_source.into_iter().flatten() {
+            if !job.source.contains(&path) {
+                job.source.push(path);
+            }
         }
-        if let Some(path) = target {
-            job.target = path?;
+        if let Some(paths) = target {
+            job.target = paths;
+        }
+        for path in add_target.into_iter().flatten() {
+            if !job.target.contains(&path) {
+                job.target.push(path);
+            }
         }
         // Handle clear operations first
         if let Some(clear_fields) = &clear {
@@ -406,6 +1158,33 @@ fn edit(params: EditParams) -> Result<()> {
                     ClearField::Model => {
                         job.model = None;
                     }
+                    ClearField::PreHook => {
+                        job.pre_hook = None;
+                    }
+                    ClearField::PostHook => {
+                        job.post_hook = None;
+                    }
+                    ClearField::OnFailure => {
+                        job.on_failure = None;
+                    }
+                    ClearField::HookTimeout => {
+                        job.hook_timeout = None;
+                    }
+                    ClearField::Schedule => {
+                        job.schedule = None;
+                    }
+                    ClearField::Name => {
+                        job.name = None;
+                    }
+                    ClearField::Tags => {
+                        job.tags = None;
+                    }
+                    ClearField::Verify => {
+                        job.verify = None;
+                    }
+                    ClearField::Parity => {
+                        job.parity = None;
+                    }
                 }
             }
         }
@@ -427,36 +1206,148 @@ fn edit(params: EditParams) -> Result<()> {
         if let Some(model) = model {
             job.model = Some(model)
         }
+        if let Some(cmd) = hooks.pre_hook {
+            job.pre_hook = Some(cmd);
+        }
+        if let Some(cmd) = hooks.post_hook {
+            job.post_hook = Some(cmd);
+        }
+        if let Some(cmd) = hooks.on_failure {
+            job.on_failure = Some(cmd);
+        }
+        if let Some(secs) = hooks.hook_timeout {
+            job.hook_timeout = Some(secs);
+        }
+        if let Some(schedule) = schedule {
+            job.schedule = Some(schedule);
+        }
+        if let Some(name) = name {
+            job.name = Some(name);
+        }
+        if let Some(tags) = tags {
+            job.tags = Some(tags);
+        }
+        if verify {
+            if job.compression.is_none() {
+                bail!("The compression format is not set, and only archives can be verified.");
+            }
+            job.verify = Some(true);
+        }
+        if let Some(percent) = parity {
+            if job.compression.is_none() {
+                bail!(
+                    "The compression format is not set, and recovery records are only written for archives."
+                );
+            }
+            job.parity = Some(percent);
+        }
 
         if job.compression.is_some() && job.model == Some(BackupModel::Mirror) {
             bail!(HbackupError::InvalidCompressionForMirror);
         }
 
         if swap {
-            if !job.target.exists() {
+            let ([source], [target]) = (job.source.as_mut_slice(), job.target.as_mut_slice())
+            else {
                 bail!(
-                    "Cannot swap source and target paths for job id {id} because target path does not exist.\ntarget path: {:?}",
-                    job.target
+                    "Cannot swap source and target paths for job {id} because it has several sources or targets."
                 );
-            } else if !(job.target.is_file() && job.source.is_file()) {
+            };
+            if !target.exists() {
+                bail!(
+                    "Cannot swap source and target paths for job {id} because target path does not exist.\ntarget path: {:?}",
+                    target
+                );
+            } else if !(target.is_file() && source.is_file()) {
                 // only support: file-to-file swap for now
                 bail!(
-                    "Cannot swap source and target paths for job id {id} because both source and target paths must be files.\nsource path: {:?}\ntarget path: {:?}",
-                    job.source,
-                    job.target
+                    "Cannot swap source and target paths for job {id} because both source and target paths must be files.\nsource path: {:?}\ntarget path: {:?}",
+                    source,
+                    target
                 );
             }
-            std::mem::swap(&mut job.source, &mut job.target);
+            std::mem::swap(source, target);
         }
+        check_nesting(job)?;
 
         app.write()?;
-        println!("Job with id {id} edited successfully.");
+        println!("Job {id} edited successfully.");
     } else {
-        println!("Job with id {id} not found.");
+        println!("Job {id} not found.");
+    }
+    Ok(())
+}
+
+/// Refuses the paths of `job` that [`Job::check_paths`] rejects, and notes the targets inside a source directory, which are left out of the backup.
+fn check_nesting(job: &Job) -> Result<()> {
+    // Templates that cannot be expanded here were already reported by `job_path`.
+    let Ok(job) = job.expanded() else {
+        return Ok(());
+    };
+    job.check_paths()?;
+    for (target, src) in job.nested_targets() {
+        eprintln!(
+            "Note: target {} is inside source {}; it is left out of the backup.",
+            target.display(),
+            src.display()
+        );
     }
     Ok(())
 }
 
+/// Validates a job name given on the command line.
+fn parse_name(s: &str) -> Result<String> {
+    validate_name(s)?;
+    Ok(s.to_string())
+}
+
+/// Validates a schedule given on the command line, keeping it as written.
+fn parse_schedule(s: &str) -> Result<String> {
+    s.parse::<Schedule>()?;
+    Ok(s.to_string())
+}
+
+/// Parses a rate in bytes per second with an optional `K`, `M` or `G` suffix
+/// (powers of 1024).
+fn parse_rate(s: &str) -> Result<u64> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let factor = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => bail!("invalid rate {s:?}: expected a number with an optional K, M or G suffix"),
+    };
+    let rate = digits
+        .parse::<u64>()
+        .with_context(|| format!("invalid rate {s:?}"))?;
+    if rate == 0 {
+        bail!("the rate must be greater than zero");
+    }
+    Ok(rate * factor)
+}
+
+/// Resolves a source or target given for a job on the command line.
+///
+/// Path templates are stored as given and expanded each time the job runs; other
+/// paths are canonicalized and escaped. A template that expands to a relative path is anchored
+/// at the current directory, like other paths are.
+fn job_path(path: PathBuf) -> Result<PathBuf> {
+    if template::is_template(&path) {
+        // Report unset variables now rather than at the first run.
+        if template::expand(&path)?.is_relative() {
+            return Ok(template::escape(&std::env::current_dir()?).join(path));
+        }
+        Ok(path)
+    } else {
+        // A symbolic link may resolve to a path that reads as a template.
+        Ok(template::escape(&canonicalize(path)?))
+    }
+}
+
 /// Returns the canonical, absolute form of the path with all intermediate
 /// components normalized and symbolic links resolved.
 fn canonicalize(path: impl AsRef<Path>) -> Result<PathBuf> {
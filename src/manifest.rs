@@ -0,0 +1,415 @@
+//! Checksum manifests written next to every backup.
+//!
+//! After a successful run, each target directory receives a JSON manifest that lists
+//! every backed up file with its size, modification time, mode and SHA-256 hash,
+//! together with the job, the machine and the release that wrote it. A
+//! `sha256sum`-compatible file is written beside it, so a backup can be audited
+//! with standard tools: `sha256sum -c .hbackup-manifest-1.sha256`.
+//!
+//! For a compressed job, the files are named as in the archive and the checksum file
+//! lists the archive itself. For an uncompressed job, the paths are relative to the
+//! target directory.
+
+use crate::integrity::{self, Entry};
+use crate::job::{CompressFormat, Job};
+use crate::{file_util, template};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// The manifest of a backup in one target directory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// Id of the job that wrote the backup.
+    pub job: u32,
+    /// Name of the machine the backup was written on.
+    pub hostname: String,
+    /// Version of hbackup that wrote the backup.
+    pub version: String,
+    /// Time the manifest was written, in RFC 3339 format.
+    pub created: String,
+    /// File name of the archive of a compressed job.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+    /// Hex-encoded SHA-256 hash of the archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_sha256: Option<String>,
+    /// Every file of the backup.
+    pub files: Vec<FileEntry>,
+}
+
+/// A file listed in a [`Manifest`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileEntry {
+    /// Path inside the archive, or relative to the target directory, with `/` separators.
+    pub path: String,
+    /// Size in bytes.
+    pub size: u64,
+    /// Modification time of the source file, in seconds since the Unix epoch.
+    pub mtime: i64,
+    /// Permission bits of the source file.
+    pub mode: u32,
+    /// Hex-encoded SHA-256 hash of the content.
+    pub sha256: String,
+    /// State of the copy in the target when it was hashed, see [`Stamp`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stamp: Option<Stamp>,
+}
+
+/// Identifies the state of a copied file when it was hashed.
+///
+/// The hash of a copy is taken over by the next run only while its stamp is the
+/// same. Unlike the modification time, the change time cannot be set back, so a
+/// copy rewritten within the same second is hashed again.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    /// Inode number.
+    pub ino: u64,
+    /// Modification time, in nanoseconds since the Unix epoch.
+    pub mtime_ns: i64,
+    /// Status change time, in nanoseconds since the Unix epoch.
+    pub ctime_ns: i64,
+}
+
+impl Stamp {
+    #[cfg(unix)]
+    fn of(md: &fs::Metadata) -> Option<Stamp> {
+        use std::os::unix::fs::MetadataExt;
+        let nanos = |secs: i64, nsec: i64| secs.saturating_mul(1_000_000_000).saturating_add(nsec);
+        Some(Stamp {
+            ino: md.ino(),
+            mtime_ns: nanos(md.mtime(), md.mtime_nsec()),
+            ctime_ns: nanos(md.ctime(), md.ctime_nsec()),
+        })
+    }
+
+    /// Without a change time, hashes are never taken over.
+    #[cfg(not(unix))]
+    fn of(_md: &fs::Metadata) -> Option<Stamp> {
+        None
+    }
+}
+
+impl Manifest {
+    /// Builds the manifest of the backup of `job` in `target` from the pre-scanned
+    /// `entries` of its sources. Expects expanded paths.
+    ///
+    /// The sizes and hashes are taken from what was written: the copies in `target`
+    /// for an uncompressed job, or the files stored in the `archive` of a compressed
+    /// one. The hash of a copy is taken over from `previous` while the copy is
+    /// untouched (see [`Stamp`]).
+    pub fn build(
+        job: &Job,
+        entries: &[PathBuf],
+        target: &Path,
+        archive: Option<&Path>,
+        previous: Option<&Manifest>,
+    ) -> Result<Manifest> {
+        let (files, mut stored) = match (&job.compression, archive) {
+            (Some(format), Some(archive)) => (
+                file_util::archived_files(&job.source, entries, format)?,
+                Some(stored_entries(job, archive, format)?),
+            ),
+            _ => (file_util::stored_files(&job.source, entries)?, None),
+        };
+        let known: HashMap<_, _> = previous
+            .iter()
+            .flat_map(|m| &m.files)
+            .map(|f| (f.path.as_str(), f))
+            .collect();
+
+        let mut listed = Vec::with_capacity(files.len());
+        for (path, name) in files {
+            // The modification time and mode are those of the source.
+            let md = fs::metadata(&path)?;
+            let slashed = slash_path(&name);
+            let (size, sha256, stamp) = match (&mut stored, archive) {
+                (Some(stored), Some(archive)) => {
+                    let (size, sha256) = stored.remove(&slashed).with_context(|| {
+                        format!("{slashed} is missing from {}", archive.display())
+                    })?;
+                    (size, sha256, None)
+                }
+                _ => {
+                    let previous = known.get(slashed.as_str()).copied();
+                    // A file that appeared after the copy is not part of the backup.
+                    match hash_copy(&target.join(&name), previous)? {
+                        Some(copy) => copy,
+                        None => continue,
+                    }
+                }
+            };
+            listed.push(FileEntry {
+                path: slashed,
+                size,
+                mtime: mtime(&md),
+                mode: mode(&md),
+                sha256,
+                stamp,
+            });
+        }
+        listed.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Manifest {
+            job: job.id,
+            hostname: template::hostname(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            created: chrono::Local::now().to_rfc3339(),
+            archive: archive
+                .and_then(|a| a.file_name())
+                .map(|name| name.to_string_lossy().into_owned()),
+            archive_sha256: archive.map(hash_file).transpose()?,
+            files: listed,
+        })
+    }
+
+    /// Reads the manifest of job `id` from the `dir` directory, if there is one.
+    pub fn read(dir: &Path, id: u32) -> Result<Option<Manifest>> {
+        let path = manifest_path(dir, id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)?;
+        let manifest = serde_json::from_str(&content)
+            .with_context(|| format!("invalid manifest {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    /// Writes the manifest and its checksum file into the `dir` directory.
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let path = manifest_path(dir, self.job);
+        write_replacing(&path, &(serde_json::to_string_pretty(self)? + "\n"))?;
+        write_replacing(&sums_path(dir, self.job), &self.sums())
+    }
+
+    /// Returns the files of the manifest as they are compared by [`integrity::compare`].
+    pub fn entries(&self) -> Vec<Entry> {
+        self.files
+            .iter()
+            .map(|f| Entry {
+                name: PathBuf::from(&f.path),
+                size: f.size,
+                sha256: Some(f.sha256.clone()),
+            })
+            .collect()
+    }
+
+    /// Returns the content of the checksum file, in the format of `sha256sum`.
+    fn sums(&self) -> String {
+        if let (Some(archive), Some(sha256)) = (&self.archive, &self.archive_sha256) {
+            return format!("{sha256}  {archive}\n");
+        }
+        self.files
+            .iter()
+            .map(|f| format!("{}  {}\n", f.sha256, f.path))
+            .collect()
+    }
+}
+
+/// Returns the path of the manifest of job `id` in the `dir` directory.
+pub fn manifest_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!(".hbackup-manifest-{id}.json"))
+}
+
+/// Returns the path of the checksum file of job `id` in the `dir` directory.
+pub fn sums_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!(".hbackup-manifest-{id}.sha256"))
+}
+
+/// Writes `content` to a temporary file first, so an interrupted run never leaves
+/// a truncated manifest behind.
+fn write_replacing(path: &Path, content: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Returns the size and hash of every file stored in `archive`, by name.
+fn stored_entries(
+    job: &Job,
+    archive: &Path,
+    format: &CompressFormat,
+) -> Result<HashMap<String, (u64, String)>> {
+    let tree = job.single_file().is_none();
+    let entries = integrity::read_archive(archive, format, tree, true)
+        .with_context(|| format!("cannot decode {}", archive.display()))?;
+    Ok(entries
+        .into_iter()
+        .map(|e| (slash_path(&e.name), (e.size, e.sha256.unwrap_or_default())))
+        .collect())
+}
+
+/// Returns the size, hash and stamp of the copy at `path`, or `None` if there is no
+/// copy. The hash of its `previous` entry is taken over if the copy was not touched
+/// since.
+fn hash_copy(
+    path: &Path,
+    previous: Option<&FileEntry>,
+) -> Result<Option<(u64, String, Option<Stamp>)>> {
+    let md = match fs::metadata(path) {
+        Ok(md) => md,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+    };
+    let stamp = Stamp::of(&md);
+    let sha256 = match previous {
+        Some(f) if stamp.is_some() && f.stamp == stamp && f.size == md.len() => f.sha256.clone(),
+        _ => hash_file(path)?,
+    };
+    Ok(Some((md.len(), sha256, stamp)))
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let file = File::open(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let (_, sha256) = integrity::digest(file, true)?;
+    Ok(sha256.unwrap_or_default())
+}
+
+/// Joins the components of a relative path with `/`, independent of the platform.
+pub(crate) fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn mtime(md: &fs::Metadata) -> i64 {
+    md.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+#[cfg(unix)]
+pub(crate) fn mode(md: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    md.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+pub(crate) fn mode(md: &fs::Metadata) -> u32 {
+    if md.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::Level;
+
+    /// Copies the file `rel` of `src` to the same place under `dest`.
+    fn copy_to(src: &Path, dest: &Path, rel: &str) -> Result<()> {
+        let path = dest.join(rel);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::copy(src.join(rel), path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_and_write_manifest() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        fs::create_dir_all(src.join("sub"))?;
+        fs::write(src.join("a.txt"), "a")?;
+        fs::write(src.join("sub").join("b.txt"), "bb")?;
+        let target = temp_dir.path().join("backup");
+        copy_to(temp_dir.path(), &target, "docs/a.txt")?;
+        copy_to(temp_dir.path(), &target, "docs/sub/b.txt")?;
+
+        let job = Job {
+            id: 7,
+            source: vec![src.clone()],
+            target: vec![target.clone()],
+            ..Default::default()
+        };
+        let manifest = Manifest::build(&job, &job.scan_sources()?, &target, None, None)?;
+        let paths: Vec<_> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["docs/a.txt", "docs/sub/b.txt"]);
+        assert_eq!(manifest.files[1].size, 2);
+        assert_eq!(
+            manifest.files[0].sha256,
+            "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb"
+        );
+        assert!(manifest.archive.is_none());
+
+        manifest.write(&target)?;
+        assert_eq!(Manifest::read(&target, 7)?, Some(manifest.clone()));
+        let sums = fs::read_to_string(sums_path(&target, 7))?;
+        assert!(sums.ends_with("  docs/sub/b.txt\n"), "{sums}");
+        assert_eq!(Manifest::read(&target, 8)?, None);
+
+        // The hash of an untouched copy is taken over from the previous manifest.
+        #[cfg(unix)]
+        {
+            let mut previous = manifest;
+            previous.files[0].sha256 = "cached".to_string();
+            let entries = job.scan_sources()?;
+            let manifest = Manifest::build(&job, &entries, &target, None, Some(&previous))?;
+            assert_eq!(manifest.files[0].sha256, "cached");
+        }
+
+        // A compressed job lists the files stored in the archive, and the archive in
+        // the checksum file.
+        let job = Job {
+            compression: Some(CompressFormat::Sevenz),
+            ..job
+        };
+        let entries = job.scan_sources()?;
+        let format = CompressFormat::Sevenz;
+        file_util::compress_sources(
+            &job.source,
+            &entries,
+            &target,
+            "docs",
+            &format,
+            &Level::Default,
+        )?;
+        let archive = file_util::sources_archive_path(&job.source, "docs", &target, &format);
+        let manifest = Manifest::build(&job, &entries, &target, Some(&archive), None)?;
+        assert_eq!(manifest.files[0].path, "a.txt");
+        assert_eq!(manifest.files[1].size, 2);
+        assert_eq!(manifest.archive.as_deref(), Some("docs.7z"));
+        assert!(manifest.sums().ends_with("  docs.7z\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_hashes_the_copies() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("a.txt"), "aa")?;
+        let target = temp_dir.path().join("backup");
+        copy_to(temp_dir.path(), &target, "docs/a.txt")?;
+        let job = Job {
+            id: 1,
+            source: vec![src.clone()],
+            target: vec![target.clone()],
+            ..Default::default()
+        };
+        let first = Manifest::build(&job, &job.scan_sources()?, &target, None, None)?;
+
+        // The copy is rewritten within the same second, keeping its size and
+        // modification time.
+        let copy = target.join("docs").join("a.txt");
+        let mtime = fs::metadata(&copy)?.modified()?;
+        fs::write(&copy, "bb")?;
+        File::options()
+            .write(true)
+            .open(&copy)?
+            .set_modified(mtime)?;
+        let second = Manifest::build(&job, &job.scan_sources()?, &target, None, Some(&first))?;
+        assert_ne!(second.files[0].sha256, first.files[0].sha256);
+        assert_eq!(second.files[0].sha256, hash_file(&copy)?);
+        assert_ne!(second.files[0].sha256, hash_file(&src.join("a.txt"))?);
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+//! Schema versions of the configuration file and the migrations between them.
+//!
+//! The configuration is upgraded as a raw TOML document before it is deserialized,
+//! so a migration can rename or reshape fields that the current `Job` no longer
+//! accepts. Migrations are applied in order until the current version is reached.
+
+use crate::Result;
+use anyhow::{anyhow, bail};
+use hbackup::template;
+use std::fmt;
+use std::path::Path;
+use toml::{Table, Value};
+
+/// Schema version written by this release.
+pub(crate) const CURRENT_VERSION: &str = "1.4";
+
+/// Version assumed for configuration files that do not declare one.
+const INITIAL_VERSION: &str = "1.0";
+
+/// A migration step upgrading a document from one version to the next.
+type Step = fn(&mut Table) -> Result<()>;
+
+/// Ordered migration steps, each upgrading from the first version to the second.
+const MIGRATIONS: &[(&str, &str, Step)] = &[
+    ("1.0", "1.1", v1_0_to_v1_1),
+    ("1.1", "1.2", v1_1_to_v1_2),
+    ("1.2", "1.3", v1_2_to_v1_3),
+    ("1.3", "1.4", v1_3_to_v1_4),
+];
+
+/// The configuration was written by a newer release than this one.
+#[derive(Debug)]
+pub(crate) struct UnsupportedVersion(pub String);
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "config version {} is newer than the supported version {CURRENT_VERSION}; \
+             please upgrade hbackup",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+/// Upgrades `doc` to [`CURRENT_VERSION`] in place.
+///
+/// Returns the original version if any migration was applied.
+pub(crate) fn migrate(doc: &mut Table) -> Result<Option<String>> {
+    let original = match doc.get("version") {
+        Some(Value::String(v)) => v.clone(),
+        Some(v) => bail!("invalid config version: {v}"),
+        None => INITIAL_VERSION.to_string(),
+    };
+    if parse_version(&original)? > parse_version(CURRENT_VERSION)? {
+        bail!(UnsupportedVersion(original));
+    }
+
+    let mut version = original.clone();
+    for (from, to, step) in MIGRATIONS {
+        if parse_version(&version)? <= parse_version(from)? {
+            step(doc)?;
+            version = to.to_string();
+        }
+    }
+    if version == original {
+        return Ok(None);
+    }
+    doc.insert("version".to_string(), Value::String(version));
+    Ok(Some(original))
+}
+
+/// Parses a `major.minor` version.
+fn parse_version(version: &str) -> Result<(u32, u32)> {
+    let invalid = || anyhow!("invalid config version: {version}");
+    let (major, minor) = version.split_once('.').ok_or_else(invalid)?;
+    Ok((
+        major.parse().map_err(|_| invalid())?,
+        minor.parse().map_err(|_| invalid())?,
+    ))
+}
+
+/// Returns the job tables of the document.
+fn jobs(doc: &mut Table) -> impl Iterator<Item = &mut Table> {
+    doc.get_mut("jobs")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_table_mut)
+}
+
+/// 1.0 -> 1.1: splits comma-separated `ignore` strings into lists and makes the
+/// default `Full` model explicit.
+fn v1_0_to_v1_1(doc: &mut Table) -> Result<()> {
+    for job in jobs(doc) {
+        if let Some(Value::String(ignore)) = job.get("ignore") {
+            let list = ignore
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|s| Value::String(s.to_string()))
+                .collect();
+            job.insert("ignore".to_string(), Value::Array(list));
+        }
+        job.entry("model")
+            .or_insert_with(|| Value::String("Full".to_string()));
+    }
+    Ok(())
+}
+
+/// 1.1 -> 1.2: turns the single `source` path of each job into a list of sources.
+fn v1_1_to_v1_2(doc: &mut Table) -> Result<()> {
+    wrap_in_list(doc, "source");
+    Ok(())
+}
+
+/// 1.2 -> 1.3: turns the single `target` path of each job into a list of targets.
+fn v1_2_to_v1_3(doc: &mut Table) -> Result<()> {
+    wrap_in_list(doc, "target");
+    Ok(())
+}
+
+/// 1.3 -> 1.4: escapes the source and target paths of each job, which were stored
+/// as plain paths before they became templates.
+fn v1_3_to_v1_4(doc: &mut Table) -> Result<()> {
+    for job in jobs(doc) {
+        for key in ["source", "target"] {
+            let paths = job.get_mut(key).and_then(Value::as_array_mut);
+            for path in paths.into_iter().flatten() {
+                if let Value::String(s) = path {
+                    *s = template::escape(Path::new(s.as_str()))
+                        .to_string_lossy()
+                        .into_owned();
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the string value of `key` in each job with a list holding it.
+fn wrap_in_list(doc: &mut Table, key: &str) {
+    for job in jobs(doc) {
+        if let Some(value @ Value::String(_)) = job.get(key) {
+            let list = Value::Array(vec![value.clone()]);
+            job.insert(key.to_string(), list);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_from_1_0() -> Result<()> {
+        let mut doc: Table = toml::from_str(
+            r#"
+            version = "1.0"
+
+            [[jobs]]
+            id = 1
+            source = "/src/$RECYCLE.BIN"
+            target = "/dst/{date}"
+            ignore = "target, .git"
+            "#,
+        )?;
+
+        assert_eq!(migrate(&mut doc)?, Some("1.0".to_string()));
+        assert_eq!(doc["version"].as_str(), Some(CURRENT_VERSION));
+        let job = &doc["jobs"][0];
+        assert_eq!(job["ignore"][0].as_str(), Some("target"));
+        assert_eq!(job["ignore"][1].as_str(), Some(".git"));
+        assert_eq!(job["model"].as_str(), Some("Full"));
+        // Plain paths are escaped, so they are not expanded as templates.
+        assert_eq!(job["source"][0].as_str(), Some("/src/$$RECYCLE.BIN"));
+        assert_eq!(job["target"][0].as_str(), Some("/dst/{{date}"));
+
+        // Migrating again is a no-op.
+        assert_eq!(migrate(&mut doc)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_from_1_3_keeps_plain_paths() -> Result<()> {
+        let mut doc: Table = toml::from_str(
+            r#"
+            version = "1.3"
+
+            [[jobs]]
+            id = 1
+            source = ["/data/a$$b", "/data/$work"]
+            target = ["/backup"]
+            "#,
+        )?;
+
+        assert_eq!(migrate(&mut doc)?, Some("1.3".to_string()));
+        let source = &doc["jobs"][0]["source"];
+        assert_eq!(source[0].as_str(), Some("/data/a$$$$b"));
+        assert_eq!(source[1].as_str(), Some("/data/$$work"));
+        assert_eq!(doc["jobs"][0]["target"][0].as_str(), Some("/backup"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuse_newer_version() -> Result<()> {
+        let mut doc: Table = toml::from_str("version = \"9.0\"\njobs = []\n")?;
+        let err = migrate(&mut doc).unwrap_err();
+        assert!(err.downcast_ref::<UnsupportedVersion>().is_some());
+
+        let mut doc: Table = toml::from_str("version = \"one\"\njobs = []\n")?;
+        assert!(migrate(&mut doc).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+//! Reed-Solomon recovery records for archives.
+//!
+//! A compressed archive is all-or-nothing: a single damaged sector makes everything
+//! after it unreadable. A job can therefore write recovery records beside its
+//! archive (`docs.tar.xz.par`), from which `bk repair` reconstructs damaged parts.
+//!
+//! The archive is split into blocks of [`DATA_SHARDS`] equally sized shards. For each
+//! block, the recovery file holds the SHA-256 hash of every data and parity shard,
+//! which tells damaged shards apart, followed by the parity shards themselves. A
+//! block survives as many damaged shards as it has parity shards.
+
+use anyhow::{Context, Result, bail};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Identifies a recovery file and the version of its layout.
+const MAGIC: &[u8] = b"HBPAR1\n";
+/// Number of data shards in a block.
+pub const DATA_SHARDS: usize = 64;
+/// Largest shard size; smaller archives use smaller shards.
+const MAX_SHARD_SIZE: usize = 64 * 1024;
+/// Size of a shard hash.
+const HASH_SIZE: usize = 32;
+
+/// Shards or shard hashes of a block.
+type Shards = Vec<Vec<u8>>;
+
+/// Layout of a recovery file, stored as a JSON line after the magic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Header {
+    /// Size of the protected archive in bytes.
+    archive_size: u64,
+    /// Hex-encoded SHA-256 hash of the whole archive.
+    archive_sha256: String,
+    shard_size: usize,
+    data_shards: usize,
+    parity_shards: usize,
+}
+
+impl Header {
+    fn block_size(&self) -> u64 {
+        (self.shard_size * self.data_shards) as u64
+    }
+
+    fn blocks(&self) -> u64 {
+        self.archive_size.div_ceil(self.block_size())
+    }
+}
+
+/// The result of checking or repairing an archive with its recovery records.
+#[derive(Debug, Default, PartialEq)]
+pub struct RepairReport {
+    /// Number of blocks of the archive.
+    pub blocks: u64,
+    /// Number of damaged shards of the archive that were reconstructed.
+    pub repaired_shards: usize,
+    /// Number of blocks with more damaged shards than parity shards.
+    pub unrecoverable_blocks: u64,
+    /// Whether the recovery records themselves were damaged and written again.
+    pub records_rewritten: bool,
+}
+
+impl RepairReport {
+    /// Returns `true` if the archive is intact now.
+    pub fn is_ok(&self) -> bool {
+        self.unrecoverable_blocks == 0
+    }
+}
+
+/// Returns the path of the recovery file of `archive`.
+pub fn parity_path(archive: &Path) -> PathBuf {
+    let mut name = archive.as_os_str().to_owned();
+    name.push(".par");
+    PathBuf::from(name)
+}
+
+/// Returns the number of parity shards per block for `redundancy` percent.
+pub fn parity_shards(redundancy: u8) -> usize {
+    (DATA_SHARDS * usize::from(redundancy.clamp(1, 100))).div_ceil(100)
+}
+
+/// Writes the recovery file of `archive` with `redundancy` percent of parity data
+/// and returns its path.
+pub fn write(archive: &Path, redundancy: u8) -> Result<PathBuf> {
+    write_records(archive, parity_shards(redundancy))
+}
+
+fn write_records(archive: &Path, parity_shards: usize) -> Result<PathBuf> {
+    let archive_size = fs::metadata(archive)?.len();
+    let shard_size = (archive_size as usize)
+        .div_ceil(DATA_SHARDS)
+        .clamp(1, MAX_SHARD_SIZE);
+    let header = Header {
+        archive_size,
+        archive_sha256: hash_file(archive)?,
+        shard_size,
+        data_shards: DATA_SHARDS,
+        parity_shards,
+    };
+    let rs = ReedSolomon::new(header.data_shards, header.parity_shards)?;
+
+    let path = parity_path(archive);
+    let tmp = path.with_extension("par.tmp");
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    out.write_all(MAGIC)?;
+    serde_json::to_writer(&mut out, &header)?;
+    out.write_all(b"\n")?;
+
+    let mut input = BufReader::new(File::open(archive)?);
+    for _ in 0..header.blocks() {
+        let mut shards = read_block(&mut input, &header)?;
+        rs.encode(&mut shards)?;
+        for shard in &shards {
+            out.write_all(&Sha256::digest(shard))?;
+        }
+        for shard in &shards[header.data_shards..] {
+            out.write_all(shard)?;
+        }
+    }
+    out.into_inner()?.sync_all()?;
+    fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+/// Checks `archive` against its recovery records and reconstructs damaged blocks.
+///
+/// The repaired archive is written beside the damaged one and only replaces it once
+/// its hash matches the one recorded, so a failed repair never makes things worse.
+/// Recovery records that are damaged themselves are written again once the archive
+/// is intact.
+///
+/// # Errors
+/// Returns an error if there are no readable recovery records or if the repaired
+/// archive does not match the recorded hash.
+pub fn repair(archive: &Path) -> Result<RepairReport> {
+    let path = parity_path(archive);
+    let mut records = BufReader::new(
+        File::open(&path).with_context(|| format!("cannot open {}", path.display()))?,
+    );
+    let header = read_header(&mut records)
+        .with_context(|| format!("invalid recovery records {}", path.display()))?;
+    let rs = ReedSolomon::new(header.data_shards, header.parity_shards)?;
+    let mut report = RepairReport {
+        blocks: header.blocks(),
+        ..Default::default()
+    };
+
+    // The first pass only finds the damage, so an intact archive is never rewritten.
+    let mut damaged = false;
+    let mut input = BufReader::new(File::open(archive)?);
+    for _ in 0..header.blocks() {
+        let block = check_block(&mut records, &mut input, &header, &rs)?;
+        report.records_rewritten |= block.records_damaged;
+        if block.unrecoverable {
+            report.unrecoverable_blocks += 1;
+        }
+        damaged |= block.repaired > 0;
+    }
+    let truncated = fs::metadata(archive)?.len() != header.archive_size;
+    if report.unrecoverable_blocks > 0 {
+        report.records_rewritten = false;
+        return Ok(report);
+    }
+
+    if damaged || truncated {
+        report.repaired_shards = rebuild(archive, &header, &rs)?;
+    }
+    if report.records_rewritten {
+        write_records(archive, header.parity_shards)?;
+    }
+    Ok(report)
+}
+
+/// Writes a repaired copy of `archive` and moves it over the original. Returns the
+/// number of reconstructed data shards.
+fn rebuild(archive: &Path, header: &Header, rs: &ReedSolomon) -> Result<usize> {
+    let mut records = BufReader::new(File::open(parity_path(archive))?);
+    read_header(&mut records)?;
+    let mut input = BufReader::new(File::open(archive)?);
+    let tmp = parity_path(archive).with_extension("repair");
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    let mut hasher = Sha256::new();
+    let mut repaired = 0;
+    let mut remaining = header.archive_size;
+
+    for _ in 0..header.blocks() {
+        let block = check_block(&mut records, &mut input, header, rs)?;
+        if block.unrecoverable {
+            bail!("{} changed during the repair", archive.display());
+        }
+        repaired += block.repaired;
+        for shard in block.data {
+            let len = remaining.min(shard.len() as u64) as usize;
+            out.write_all(&shard[..len])?;
+            hasher.update(&shard[..len]);
+            remaining -= len as u64;
+        }
+    }
+    out.into_inner()?.sync_all()?;
+
+    if format!("{:x}", hasher.finalize()) != header.archive_sha256 {
+        fs::remove_file(&tmp)?;
+        bail!(
+            "the repaired archive does not match the recovery records of {}",
+            archive.display()
+        );
+    }
+    fs::rename(&tmp, archive)?;
+    Ok(repaired)
+}
+
+/// The next block of an archive, compared with its recovery records.
+struct Block {
+    /// Data shards, rebuilt where they were damaged.
+    data: Shards,
+    /// Number of data shards whose content was rebuilt.
+    repaired: usize,
+    /// Whether a parity shard or a hash record was damaged.
+    records_damaged: bool,
+    /// Whether more shards are damaged than the block has parity shards.
+    unrecoverable: bool,
+}
+
+/// Reads the next block of the archive and its recovery records and rebuilds the
+/// shards that do not match their hash.
+///
+/// A data shard that is rebuilt into the bytes already stored had a damaged hash
+/// record rather than damaged content, and counts as damage to the records.
+fn check_block(
+    records: &mut impl Read,
+    input: &mut impl Read,
+    header: &Header,
+    rs: &ReedSolomon,
+) -> Result<Block> {
+    let (hashes, parity) = read_records(records, header)?;
+    let mut shards: Vec<Option<Vec<u8>>> = read_block(input, header)?
+        .into_iter()
+        .take(header.data_shards)
+        .chain(parity)
+        .map(Some)
+        .collect();
+    let bad: Vec<_> = (0..shards.len())
+        .filter(|&i| {
+            shards[i]
+                .as_ref()
+                .is_some_and(|s| Sha256::digest(s).as_slice() != hashes[i])
+        })
+        .collect();
+    let mut block = Block {
+        data: vec![],
+        repaired: 0,
+        records_damaged: bad.iter().any(|&i| i >= header.data_shards),
+        unrecoverable: bad.len() > header.parity_shards,
+    };
+    if block.unrecoverable {
+        return Ok(block);
+    }
+
+    let stored: Vec<_> = bad
+        .iter()
+        .filter(|&&i| i < header.data_shards)
+        .map(|&i| (i, shards[i].take()))
+        .collect();
+    if !stored.is_empty() {
+        rs.reconstruct_data(&mut shards)?;
+    }
+    for (i, content) in stored {
+        if shards[i] == content {
+            block.records_damaged = true;
+        } else {
+            block.repaired += 1;
+        }
+    }
+    block.data = shards
+        .into_iter()
+        .take(header.data_shards)
+        .map(Option::unwrap_or_default)
+        .collect();
+    Ok(block)
+}
+
+fn read_header(records: &mut impl BufRead) -> Result<Header> {
+    let mut magic = [0; MAGIC.len()];
+    records.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        bail!("not a recovery file");
+    }
+    let mut line = String::new();
+    records.read_line(&mut line)?;
+    let header: Header = serde_json::from_str(&line)?;
+    if header.data_shards == 0 || header.shard_size == 0 {
+        bail!("invalid layout");
+    }
+    Ok(header)
+}
+
+/// Reads the shard hashes and parity shards of the next block.
+fn read_records(records: &mut impl Read, header: &Header) -> Result<(Shards, Shards)> {
+    let total = header.data_shards + header.parity_shards;
+    let mut hashes = vec![vec![0; HASH_SIZE]; total];
+    for hash in &mut hashes {
+        records.read_exact(hash)?;
+    }
+    let mut parity = vec![vec![0; header.shard_size]; header.parity_shards];
+    for shard in &mut parity {
+        records.read_exact(shard)?;
+    }
+    Ok((hashes, parity))
+}
+
+/// Reads the next block of the archive as data shards followed by empty parity
+/// shards. A short read at the end is padded with zeros.
+fn read_block(input: &mut impl Read, header: &Header) -> io::Result<Shards> {
+    let mut shards = vec![vec![0; header.shard_size]; header.data_shards + header.parity_shards];
+    for shard in &mut shards[..header.data_shards] {
+        read_full(input, shard)?;
+    }
+    Ok(shards)
+}
+
+/// Fills `buf` as far as `input` allows and returns the number of bytes read.
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(dir: &Path, size: usize) -> Result<(PathBuf, Vec<u8>)> {
+        let data: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+        let path = dir.join("docs.tar.xz");
+        fs::write(&path, &data)?;
+        Ok((path, data))
+    }
+
+    #[test]
+    fn test_repair_damaged_archive() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let (path, data) = archive(temp_dir.path(), 200_000)?;
+        let par = write(&path, 10)?;
+        assert_eq!(par, temp_dir.path().join("docs.tar.xz.par"));
+        assert_eq!(
+            repair(&path)?,
+            RepairReport {
+                blocks: 1,
+                ..Default::default()
+            }
+        );
+
+        // Flip a few bytes in two shards.
+        let mut damaged = data.clone();
+        damaged[10] ^= 0xff;
+        damaged[150_000] ^= 0x01;
+        fs::write(&path, &damaged)?;
+        let report = repair(&path)?;
+        assert_eq!(report.repaired_shards, 2);
+        assert!(report.is_ok());
+        assert_eq!(fs::read(&path)?, data);
+
+        // A truncated archive is restored to its full length.
+        fs::write(&path, &data[..data.len() - 100])?;
+        assert!(repair(&path)?.is_ok());
+        assert_eq!(fs::read(&path)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_unrecoverable_and_damaged_records() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let (path, data) = archive(temp_dir.path(), 64 * 100)?;
+        write(&path, 5)?;
+        assert_eq!(parity_shards(5), 4);
+
+        // Five damaged shards are more than the four parity shards.
+        let mut damaged = data.clone();
+        for shard in 0..5 {
+            damaged[shard * 100] ^= 0xff;
+        }
+        fs::write(&path, &damaged)?;
+        let report = repair(&path)?;
+        assert_eq!(report.unrecoverable_blocks, 1);
+        assert_eq!(fs::read(&path)?, damaged);
+
+        // Damaged records are written again.
+        fs::write(&path, &data)?;
+        let par = parity_path(&path);
+        let mut records = fs::read(&par)?;
+        let last = records.len() - 1;
+        records[last] ^= 0xff;
+        fs::write(&par, &records)?;
+        assert!(repair(&path)?.records_rewritten);
+        assert!(!repair(&path)?.records_rewritten);
+
+        // A damaged hash record of a data shard does not make its content damaged.
+        let mut records = fs::read(&par)?;
+        let first_hash = records.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let first_hash = first_hash
+            + records[first_hash..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .unwrap()
+            + 1;
+        records[first_hash] ^= 0xff;
+        fs::write(&par, &records)?;
+        let report = repair(&path)?;
+        assert_eq!(report.repaired_shards, 0);
+        assert!(report.records_rewritten);
+        assert_eq!(fs::read(&path)?, data);
+        assert_eq!(
+            repair(&path)?,
+            RepairReport {
+                blocks: 1,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+}
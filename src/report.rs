@@ -0,0 +1,166 @@
+//! Summary of a backup run.
+//!
+//! Every job that takes part in a run leaves one entry in the [`RunReport`],
+//! which the CLI prints once all jobs have finished or the run was cancelled.
+use std::fmt;
+use std::path::PathBuf;
+
+/// Final state of a single job in a run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    /// The job completed successfully.
+    Success,
+    /// The job failed with the given error message.
+    Failed(String),
+    /// The job was interrupted by a cancellation request.
+    Cancelled,
+    /// The job was skipped because another run of it holds its target lock.
+    Locked,
+    /// The job was skipped because it is disabled.
+    Disabled,
+}
+
+/// Outcome of one job.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Job id.
+    pub id: u32,
+    /// Final state of the job.
+    pub status: Status,
+    /// Final state of each target of a job with several targets.
+    pub targets: Vec<(PathBuf, Status)>,
+}
+
+/// Outcome of all jobs taking part in a run.
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    entries: Vec<Entry>,
+}
+
+impl RunReport {
+    /// Creates an empty report.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the final state of a job.
+    pub fn push(&mut self, id: u32, status: Status) {
+        self.push_targets(id, status, vec![]);
+    }
+
+    /// Records the final state of a job together with the state of each of its targets.
+    pub fn push_targets(&mut self, id: u32, status: Status, targets: Vec<(PathBuf, Status)>) {
+        self.entries.push(Entry {
+            id,
+            status,
+            targets,
+        });
+    }
+
+    /// Returns all recorded entries in the order they were pushed.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Returns `true` if any job was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.entries.iter().any(|e| e.status == Status::Cancelled)
+    }
+
+    /// Returns `true` if any job was skipped because its target was locked.
+    pub fn is_locked(&self) -> bool {
+        self.entries.iter().any(|e| e.status == Status::Locked)
+    }
+
+    /// Returns `true` if any job failed.
+    pub fn has_failures(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|e| matches!(e.status, Status::Failed(_)))
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Run summary:")?;
+        for entry in &self.entries {
+            match &entry.status {
+                Status::Success => writeln!(f, "    job {}: success", entry.id)?,
+                Status::Failed(e) => writeln!(f, "    job {}: failed ({e})", entry.id)?,
+                Status::Cancelled => writeln!(f, "    job {}: cancelled", entry.id)?,
+                Status::Disabled => writeln!(f, "    job {}: skipped (disabled)", entry.id)?,
+                Status::Locked => writeln!(
+                    f,
+                    "    job {}: skipped (another run is in progress)",
+                    entry.id
+                )?,
+            }
+            for (target, status) in &entry.targets {
+                match status {
+                    Status::Failed(e) => writeln!(f, "        {}: failed ({e})", target.display())?,
+                    _ => writeln!(f, "        {}: success", target.display())?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_status_flags() {
+        let mut report = RunReport::new();
+        report.push(1, Status::Success);
+        assert!(!report.is_cancelled());
+        assert!(!report.has_failures());
+
+        report.push(2, Status::Failed("disk full".to_string()));
+        report.push(3, Status::Cancelled);
+        assert!(report.is_cancelled());
+        assert!(report.has_failures());
+        assert!(!report.is_locked());
+
+        report.push(4, Status::Locked);
+        assert!(report.is_locked());
+        assert_eq!(report.entries().len(), 4);
+    }
+
+    #[test]
+    fn test_report_display() {
+        let mut report = RunReport::new();
+        report.push(1, Status::Success);
+        report.push(2, Status::Cancelled);
+        report.push(3, Status::Disabled);
+        assert!(!report.has_failures());
+
+        let s = report.to_string();
+        assert!(s.contains("job 1: success"));
+        assert!(s.contains("job 2: cancelled"));
+        assert!(s.contains("job 3: skipped (disabled)"));
+    }
+
+    #[test]
+    fn test_report_display_targets() {
+        let mut report = RunReport::new();
+        report.push_targets(
+            1,
+            Status::Failed("1 of 2 targets failed".to_string()),
+            vec![
+                (PathBuf::from("/mnt/local"), Status::Success),
+                (
+                    PathBuf::from("/media/usb"),
+                    Status::Failed("not mounted".to_string()),
+                ),
+            ],
+        );
+        assert!(report.has_failures());
+
+        let s = report.to_string();
+        assert!(s.contains("job 1: failed (1 of 2 targets failed)"));
+        assert!(s.contains("        /mnt/local: success"));
+        assert!(s.contains("        /media/usb: failed (not mounted)"));
+    }
+}
@@ -0,0 +1,383 @@
+//! Job schedules.
+//!
+//! A schedule is either a standard five-field cron expression
+//! (`minute hour day-of-month month day-of-week`), one of the `@hourly`, `@daily`,
+//! `@weekly`, `@monthly` or `@yearly` shortcuts, or a fixed interval such as
+//! `every 6h`. Cron expressions are evaluated in local time.
+use anyhow::{Result, bail};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDateTime, Timelike};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Upper bound for the search of the next matching time of a cron expression.
+const MAX_SEARCH_DAYS: i64 = 366 * 5;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// When a job should run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    /// A cron expression.
+    Cron(CronExpr),
+    /// A fixed interval between runs.
+    Every(Duration),
+}
+
+/// A parsed five-field cron expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronExpr {
+    /// The five fields as written, after expanding shortcuts such as `@daily`.
+    pub fields: [String; 5],
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(interval) = s.strip_prefix("every") {
+            return Ok(Schedule::Every(parse_interval(interval.trim())?));
+        }
+        let expr = match s {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            _ if s.starts_with('@') => bail!("unknown schedule shortcut: {s}"),
+            _ => s,
+        };
+        Ok(Schedule::Cron(expr.parse()?))
+    }
+}
+
+impl Schedule {
+    /// Returns the first time strictly after `after` at which the schedule fires.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Every(interval) => {
+                after.checked_add_signed(ChronoDuration::from_std(*interval).ok()?)
+            }
+            Schedule::Cron(expr) => expr.next_after(after),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Every(interval) => write!(f, "every {}s", interval.as_secs()),
+            Schedule::Cron(expr) => write!(f, "{}", expr.fields.join(" ")),
+        }
+    }
+}
+
+impl FromStr for CronExpr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!(
+                "cron expression must have 5 fields, got {}: {s}",
+                fields.len()
+            );
+        };
+        let mut weekdays = parse_field(weekday, 0, 7, &DAY_NAMES, 0)?;
+        // Both 0 and 7 mean Sunday.
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(CronExpr {
+            fields: [minute, hour, day, month, weekday].map(str::to_string),
+            minutes: parse_field(minute, 0, 59, &[], 0)?,
+            hours: parse_field(hour, 0, 23, &[], 0)?,
+            days: parse_field(day, 1, 31, &[], 0)?,
+            months: parse_field(month, 1, 12, &MONTH_NAMES, 1)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+}
+
+impl CronExpr {
+    /// Returns the first time strictly after `after` that matches the expression.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start =
+            after.naive_local().with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let limit = start + ChronoDuration::days(MAX_SEARCH_DAYS);
+        let mut t = start;
+        while t < limit {
+            if !bit(self.months, t.month()) {
+                t = first_of_next_month(t)?;
+            } else if !self.matches_day(t) {
+                t = (t.date() + ChronoDuration::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if !bit(self.hours, t.hour()) {
+                t = t.with_minute(0)? + ChronoDuration::hours(1);
+            } else if !bit(self.minutes, t.minute()) {
+                t += ChronoDuration::minutes(1);
+            } else if let Some(local) = t.and_local_timezone(Local).earliest() {
+                return Some(local);
+            } else {
+                // The local time does not exist (DST gap).
+                t += ChronoDuration::minutes(1);
+            }
+        }
+        None
+    }
+
+    /// Returns equivalent systemd `OnCalendar=` expressions.
+    ///
+    /// systemd requires both the day of month and the day of week to match, so an
+    /// expression restricting both is split in two to keep the cron semantics.
+    pub fn on_calendar(&self) -> Vec<String> {
+        let time = format!(
+            "*-{}-{} {}:{}:00",
+            calendar_field(self.months, 1, 12),
+            calendar_field(self.days, 1, 31),
+            calendar_field(self.hours, 0, 23),
+            calendar_field(self.minutes, 0, 59),
+        );
+        let weekdays = (0..7)
+            .filter(|&d| bit(self.weekdays, d))
+            .map(|d| {
+                let name = DAY_NAMES[d as usize];
+                name[..1].to_ascii_uppercase() + &name[1..]
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        match (self.any_day, self.any_weekday) {
+            (_, true) => vec![time],
+            (true, false) => vec![format!("{weekdays} {time}")],
+            (false, false) => {
+                let any_day = format!(
+                    "*-{}-* {}:{}:00",
+                    calendar_field(self.months, 1, 12),
+                    calendar_field(self.hours, 0, 23),
+                    calendar_field(self.minutes, 0, 59),
+                );
+                vec![time, format!("{weekdays} {any_day}")]
+            }
+        }
+    }
+
+    fn matches_day(&self, t: NaiveDateTime) -> bool {
+        let day = bit(self.days, t.day());
+        let weekday = bit(self.weekdays, t.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            // Like cron, a restricted day-of-month and day-of-week match either.
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+fn bit(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Formats a field bit set as a systemd calendar component.
+fn calendar_field(set: u64, min: u32, max: u32) -> String {
+    if (min..=max).all(|v| bit(set, v)) {
+        return "*".to_string();
+    }
+    (min..=max)
+        .filter(|&v| bit(set, v))
+        .map(|v| format!("{v:02}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn first_of_next_month(t: NaiveDateTime) -> Option<NaiveDateTime> {
+    let (year, month) = if t.month() == 12 {
+        (t.year() + 1, 1)
+    } else {
+        (t.year(), t.month() + 1)
+    };
+    chrono::NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)
+}
+
+/// Parses one cron field into a bit set of the values it matches.
+///
+/// `names` optionally maps three-letter names to values starting at `name_base`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], name_base: u32) -> Result<u64> {
+    let value = |s: &str| -> Result<u32> {
+        let lower = s.to_ascii_lowercase();
+        if let Some(i) = names.iter().position(|n| *n == lower) {
+            return Ok(i as u32 + name_base);
+        }
+        let v: u32 = s
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid value `{s}` in cron field `{field}`"))?;
+        if v < min || v > max {
+            bail!("value {v} out of range {min}-{max} in cron field `{field}`");
+        }
+        Ok(v)
+    };
+
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| {
+                    anyhow::anyhow!("invalid step `{step}` in cron field `{field}`")
+                })?;
+                if step == 0 {
+                    bail!("step must not be 0 in cron field `{field}`");
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (value(a)?, value(b)?)
+        } else {
+            let v = value(range)?;
+            // `5/15` means every 15 starting at 5.
+            (v, if step > 1 { max } else { v })
+        };
+        if start > end {
+            bail!("invalid range `{range}` in cron field `{field}`");
+        }
+        for v in (start..=end).step_by(step as usize) {
+            set |= 1 << v;
+        }
+    }
+    Ok(set)
+}
+
+/// Parses an interval such as `6h`, `30m`, `1d` or `90s`.
+fn parse_interval(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow::anyhow!("missing unit in interval `{s}`"))?;
+    let (num, unit) = s.split_at(split);
+    let num: u64 = num
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid interval `{s}`"))?;
+    let factor = match unit.trim() {
+        "s" | "sec" | "secs" => 1,
+        "m" | "min" | "mins" => 60,
+        "h" | "hour" | "hours" => 3600,
+        "d" | "day" | "days" => 86400,
+        "w" | "week" | "weeks" => 604800,
+        _ => bail!("unknown unit in interval `{s}`"),
+    };
+    let secs = num
+        .checked_mul(factor)
+        .ok_or_else(|| anyhow::anyhow!("interval `{s}` is too large"))?;
+    if secs == 0 {
+        bail!("interval must be greater than 0");
+    }
+    let interval = Duration::from_secs(secs);
+    if ChronoDuration::from_std(interval).is_err() {
+        bail!("interval `{s}` is too large");
+    }
+    Ok(interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn local(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_shortcuts_and_intervals() {
+        assert!(matches!(
+            "@daily".parse::<Schedule>(),
+            Ok(Schedule::Cron(_))
+        ));
+        assert_eq!(
+            "every 6h".parse::<Schedule>().unwrap(),
+            Schedule::Every(Duration::from_secs(6 * 3600))
+        );
+        assert_eq!(
+            "every 30 min".parse::<Schedule>().unwrap(),
+            Schedule::Every(Duration::from_secs(1800))
+        );
+        assert!("@sometimes".parse::<Schedule>().is_err());
+        assert!("every 0h".parse::<Schedule>().is_err());
+        assert!("* * *".parse::<Schedule>().is_err());
+        assert!("61 * * * *".parse::<Schedule>().is_err());
+        assert!(
+            "every 99999999999999999w"
+                .parse::<Schedule>()
+                .unwrap_err()
+                .to_string()
+                .contains("too large")
+        );
+        assert!("every 9999999999999999s".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn test_next_after_out_of_range() {
+        // Far beyond the last representable date.
+        let schedule = Schedule::Every(Duration::from_secs(300_000 * 365 * 86400));
+        assert_eq!(schedule.next_after(local(2025, 3, 10, 13, 30)), None);
+    }
+
+    #[test]
+    fn test_next_after_daily() {
+        let schedule: Schedule = "@daily".parse().unwrap();
+        let next = schedule.next_after(local(2025, 3, 10, 13, 30)).unwrap();
+        assert_eq!(next, local(2025, 3, 11, 0, 0));
+    }
+
+    #[test]
+    fn test_next_after_steps_and_names() {
+        let schedule: Schedule = "*/15 9-17 * * mon-fri".parse().unwrap();
+        // 2025-03-08 is a Saturday.
+        let next = schedule.next_after(local(2025, 3, 8, 10, 0)).unwrap();
+        assert_eq!(next, local(2025, 3, 10, 9, 0));
+        let next = schedule.next_after(local(2025, 3, 10, 9, 0)).unwrap();
+        assert_eq!(next, local(2025, 3, 10, 9, 15));
+    }
+
+    #[test]
+    fn test_next_after_month_rollover() {
+        let schedule: Schedule = "30 2 31 * *".parse().unwrap();
+        let next = schedule.next_after(local(2025, 4, 1, 0, 0)).unwrap();
+        assert_eq!(next, local(2025, 5, 31, 2, 30));
+    }
+
+    #[test]
+    fn test_on_calendar() {
+        let cron = |s: &str| s.parse::<CronExpr>().unwrap().on_calendar();
+        assert_eq!(cron("0 0 * * *"), ["*-*-* 00:00:00"]);
+        assert_eq!(
+            cron("*/30 9-11 * * mon-fri"),
+            ["Mon,Tue,Wed,Thu,Fri *-*-* 09,10,11:00,30:00"]
+        );
+        assert_eq!(cron("0 3 1 jan *"), ["*-01-01 03:00:00"]);
+        assert_eq!(cron("0 3 1 * 0"), ["*-*-01 03:00:00", "Sun *-*-* 03:00:00"]);
+    }
+
+    #[test]
+    fn test_sunday_as_seven() {
+        let schedule: Schedule = "0 12 * * 7".parse().unwrap();
+        // 2025-03-09 is a Sunday.
+        let next = schedule.next_after(local(2025, 3, 8, 0, 0)).unwrap();
+        assert_eq!(next, local(2025, 3, 9, 12, 0));
+    }
+}
@@ -0,0 +1,297 @@
+//! Scrubbing of existing backups for silent corruption.
+//!
+//! A scrub re-reads every file of a backup and compares its hash with the
+//! [manifest](crate::manifest) written by the last run. Corrupted or missing files
+//! can be copied again from the source, as long as the source still holds the
+//! content recorded in the manifest. The archive of a compressed job is checked as
+//! a whole.
+//!
+//! Progress is journaled in the target directory, so an interrupted scrub can be
+//! resumed, and reading can be throttled to spare slow disks.
+
+use crate::error::HbackupError;
+use crate::integrity::digest;
+use crate::job::Job;
+use crate::journal::Journal;
+use crate::manifest::{FileEntry, Manifest};
+use crate::{cancel, file_util, lock};
+use anyhow::{Result, bail};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Options of a scrub.
+#[derive(Debug, Clone, Default)]
+pub struct ScrubOptions {
+    /// Copy corrupted or missing files again from the source if it is unchanged.
+    pub repair: bool,
+    /// Skip the files checked by a previously interrupted scrub.
+    pub resume: bool,
+    /// Maximum number of bytes read per second.
+    pub max_rate: Option<u64>,
+    /// How long to wait for a run of the job to release the target lock.
+    pub wait: Option<Duration>,
+}
+
+/// The result of scrubbing one target.
+#[derive(Debug, Default, PartialEq)]
+pub struct ScrubReport {
+    /// Number of files read.
+    pub checked: usize,
+    /// Number of files skipped because an interrupted scrub already checked them.
+    pub skipped: usize,
+    /// Files listed in the manifest that are not in the target.
+    pub missing: Vec<PathBuf>,
+    /// Files whose content no longer matches the manifest.
+    pub corrupted: Vec<PathBuf>,
+    /// Missing or corrupted files that were copied again from the source.
+    pub repaired: Vec<PathBuf>,
+}
+
+impl ScrubReport {
+    /// Returns `true` if no damage is left in the target.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+/// Scrubs the backup `job` keeps in `target` against the `manifest` of that target.
+/// Expects expanded paths.
+///
+/// # Errors
+/// Returns an error if the target is locked by a run of the job, if the scrub is
+/// cancelled, or if the progress journal cannot be written.
+pub fn scrub_target(
+    job: &Job,
+    target: &Path,
+    manifest: &Manifest,
+    opts: &ScrubOptions,
+) -> Result<ScrubReport> {
+    let _lock = lock::acquire(&lock::target_lock_path(target), opts.wait)?;
+    let mut journal = Journal::open_at(progress_path(target, job.id), opts.resume)?;
+    let mut throttle = opts.max_rate.map(Throttle::new);
+    let mut report = ScrubReport::default();
+
+    if let Some(archive) = &manifest.archive {
+        // The files of an archive cannot be read on their own.
+        let path = target.join(archive);
+        if let Some(sha256) = &manifest.archive_sha256 {
+            match hash(&path, throttle.as_mut())? {
+                None => report.missing.push(archive.into()),
+                Some(got) if got != *sha256 => report.corrupted.push(archive.into()),
+                Some(_) => {}
+            }
+            report.checked += 1;
+        }
+        journal.finish()?;
+        return Ok(report);
+    }
+
+    let sources = source_paths(job, manifest)?;
+    for (file, source) in manifest.files.iter().zip(sources) {
+        if cancel::is_cancelled() {
+            bail!(HbackupError::Cancelled);
+        }
+        let path = target.join(&file.path);
+        if journal.is_done(&path) {
+            report.skipped += 1;
+            continue;
+        }
+        let damaged = match hash(&path, throttle.as_mut())? {
+            None => Some(&mut report.missing),
+            Some(got) if got != file.sha256 => Some(&mut report.corrupted),
+            Some(_) => None,
+        };
+        report.checked += 1;
+        if let Some(list) = damaged {
+            let repaired = opts.repair
+                && match &source {
+                    Some(src) => repair(src, &path, file)?,
+                    None => false,
+                };
+            if repaired {
+                report.repaired.push(PathBuf::from(&file.path));
+            } else {
+                list.push(PathBuf::from(&file.path));
+            }
+        }
+        journal.record_done(&path)?;
+    }
+    journal.finish()?;
+    Ok(report)
+}
+
+/// Returns the path of the progress journal of a scrub of job `id` in `dir`.
+pub fn progress_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!(".hbackup-scrub-{id}"))
+}
+
+/// Returns the source file of each file of the manifest, if it can be determined.
+fn source_paths(job: &Job, manifest: &Manifest) -> Result<Vec<Option<PathBuf>>> {
+    let names = file_util::top_level_names(&job.source)?;
+    let source = |path: &str| -> Option<PathBuf> {
+        let (top, rest) = path.split_once('/').unwrap_or((path, ""));
+        let (src, _) = job.source.iter().zip(&names).find(|(_, n)| *n == top)?;
+        Some(if rest.is_empty() {
+            src.clone()
+        } else {
+            src.join(rest)
+        })
+    };
+    Ok(manifest.files.iter().map(|f| source(&f.path)).collect())
+}
+
+/// Copies `src` over the damaged backup file `dest` if the source still holds the
+/// content recorded in the manifest, and returns whether it did.
+fn repair(src: &Path, dest: &Path, file: &FileEntry) -> Result<bool> {
+    if hash(src, None)?.as_deref() != Some(file.sha256.as_str()) {
+        return Ok(false);
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(src, dest)?;
+    Ok(hash(dest, None)?.as_deref() == Some(file.sha256.as_str()))
+}
+
+/// Returns the hash of the file at `path`, or `None` if it does not exist.
+fn hash(path: &Path, throttle: Option<&mut Throttle>) -> Result<Option<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let (_, sha256) = match throttle {
+        Some(throttle) => digest(
+            Throttled {
+                inner: file,
+                throttle,
+            },
+            true,
+        )?,
+        None => digest(file, true)?,
+    };
+    Ok(sha256)
+}
+
+/// Limits reading to a number of bytes per second, averaged since the start.
+#[derive(Debug)]
+struct Throttle {
+    rate: u64,
+    start: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Self {
+        Throttle {
+            rate: rate.max(1),
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Accounts for `n` bytes read, sleeping until they are within the rate.
+    fn consume(&mut self, n: usize) {
+        self.bytes += n as u64;
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.rate as f64);
+        if let Some(ahead) = due.checked_sub(self.start.elapsed()) {
+            thread::sleep(ahead);
+        }
+    }
+}
+
+/// A reader whose reads are limited by a [`Throttle`].
+struct Throttled<'a, R> {
+    inner: R,
+    throttle: &'a mut Throttle,
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.throttle.consume(n);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::{RunOptions, run_job};
+
+    #[test]
+    fn test_scrub_target() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let src = temp_dir.path().join("docs");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("a.txt"), "a")?;
+        fs::write(src.join("b.txt"), "b")?;
+        fs::write(src.join("c.txt"), "c")?;
+        let target = temp_dir.path().join("backup");
+        fs::create_dir_all(&target)?;
+        let job = Job::temp_job(
+            vec![src.clone()],
+            vec![target.clone()],
+            None,
+            None,
+            None,
+            None,
+        );
+        run_job(&job, &RunOptions::default())?;
+        let manifest = Manifest::read(&target, job.id)?.unwrap();
+
+        let report = scrub_target(&job, &target, &manifest, &ScrubOptions::default())?;
+        assert_eq!(report.checked, 3);
+        assert!(report.is_ok());
+
+        // a.txt rots in the backup, b.txt also changed in the source, c.txt is lost.
+        fs::write(target.join("docs").join("a.txt"), "x")?;
+        fs::write(target.join("docs").join("b.txt"), "x")?;
+        fs::write(src.join("b.txt"), "new")?;
+        fs::remove_file(target.join("docs").join("c.txt"))?;
+        let report = scrub_target(&job, &target, &manifest, &ScrubOptions::default())?;
+        assert_eq!(report.corrupted.len(), 2);
+        assert_eq!(report.missing, vec![PathBuf::from("docs/c.txt")]);
+
+        let opts = ScrubOptions {
+            repair: true,
+            ..Default::default()
+        };
+        let report = scrub_target(&job, &target, &manifest, &opts)?;
+        assert_eq!(
+            report.repaired,
+            vec![PathBuf::from("docs/a.txt"), PathBuf::from("docs/c.txt")]
+        );
+        assert_eq!(report.corrupted, vec![PathBuf::from("docs/b.txt")]);
+        assert_eq!(fs::read_to_string(target.join("docs").join("a.txt"))?, "a");
+        assert!(!progress_path(&target, job.id).exists());
+
+        // A resumed scrub skips the files an interrupted one already checked.
+        let mut journal = Journal::open_at(progress_path(&target, job.id), false)?;
+        journal.record_done(&target.join("docs").join("a.txt"))?;
+        drop(journal);
+        let opts = ScrubOptions {
+            resume: true,
+            ..Default::default()
+        };
+        let report = scrub_target(&job, &target, &manifest, &opts)?;
+        assert_eq!((report.checked, report.skipped), (2, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_throttle() -> Result<()> {
+        let mut throttle = Throttle::new(1000);
+        let start = Instant::now();
+        let mut reader = Throttled {
+            inner: &[0u8; 100][..],
+            throttle: &mut throttle,
+        };
+        io::copy(&mut reader, &mut io::sink())?;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        Ok(())
+    }
+}
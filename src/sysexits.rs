@@ -11,6 +11,10 @@
 /// Misuse of shell builtins (according to Bash documentation)
 pub(crate) const EX_KEYWORD: i32 = 2;
 
+/// value: 130  
+/// Script terminated by Control-C (according to Bash documentation)
+pub(crate) const EX_SIGINT: i32 = 130;
+
 /// value: 64  
 /// The command was used incorrectly, e.g., with the wrong number of arguments, a bad flag, a bad syntax in a parameter, etc.
 pub(crate) const EX_USAGE: i32 = 64;
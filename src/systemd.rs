@@ -0,0 +1,398 @@
+//! systemd user timers for scheduled jobs.
+//!
+//! On machines where a long-running `bk daemon` is not wanted, every scheduled job can
+//! instead be installed as a `hbackup-<id>.service`/`hbackup-<id>.timer` pair under
+//! `~/.config/systemd/user/`. The units of a configuration other than the default one
+//! are named `hbackup-<hash>-<id>` after a short hash of its path, so the jobs of
+//! several profiles can share an id. The service runs `bk run -i <id> --skip-disabled` (with
+//! `--config` if a configuration other than the default one is used) and the timer
+//! fires it according to the job's schedule. Disabled jobs are not installed, and a
+//! job disabled after its units were installed is skipped when the timer fires.
+
+use crate::Result;
+use crate::application::{Application, custom_config_file};
+use anyhow::{Context, anyhow};
+use hbackup::job::{Job, JobRef};
+use hbackup::schedule::Schedule;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Prefix of the unit names written for jobs.
+const UNIT_PREFIX: &str = "hbackup-";
+
+/// The rendered unit files of a single job.
+#[derive(Debug)]
+pub(crate) struct Units {
+    pub id: u32,
+    pub service: String,
+    pub timer: String,
+}
+
+impl Units {
+    /// Renders the service and timer units of a scheduled job, run with the
+    /// configuration file `config` if given.
+    pub(crate) fn render(job: &Job, exe: &Path, config: Option<&Path>) -> Result<Self> {
+        let expr = job
+            .schedule
+            .as_deref()
+            .ok_or_else(|| anyhow!("job {} has no schedule", job.id))?;
+        let schedule: Schedule = expr.parse()?;
+
+        let service = format!(
+            "[Unit]\n\
+             Description=hbackup job {id}\n\
+             \n\
+             [Service]\n\
+             Type=oneshot\n\
+             ExecStart={exe}{config} run -i {id} --skip-disabled\n",
+            id = job.id,
+            exe = quote(&exe.display().to_string()),
+            config = config
+                .map(|path| format!(" --config {}", quote(&path.display().to_string())))
+                .unwrap_or_default(),
+        );
+
+        let mut triggers = String::new();
+        match &schedule {
+            Schedule::Cron(cron) => {
+                for calendar in cron.on_calendar() {
+                    triggers.push_str(&format!("OnCalendar={calendar}\n"));
+                }
+                // Like the daemon, catch up runs missed while the machine was off.
+                triggers.push_str("Persistent=true\n");
+            }
+            Schedule::Every(interval) => {
+                let secs = interval.as_secs();
+                triggers.push_str(&format!("OnBootSec={secs}s\nOnUnitActiveSec={secs}s\n"));
+            }
+        }
+        let timer = format!(
+            "[Unit]\n\
+             Description=hbackup job {id} ({expr})\n\
+             \n\
+             [Timer]\n\
+             {triggers}\
+             Unit={service_name}\n\
+             \n\
+             [Install]\n\
+             WantedBy=timers.target\n",
+            id = job.id,
+            service_name = unit_name(config, job.id, "service"),
+        );
+
+        Ok(Self {
+            id: job.id,
+            service,
+            timer,
+        })
+    }
+}
+
+/// Returns the directory holding systemd user units.
+pub(crate) fn unit_dir() -> Result<PathBuf> {
+    let dir = dirs::config_dir().ok_or_else(|| anyhow!("Couldn't get the config directory"))?;
+    Ok(dir.join("systemd").join("user"))
+}
+
+/// Returns the file name of the `kind` ("service" or "timer") unit of job `id` in the
+/// configuration file `config`, or in the default one.
+pub(crate) fn unit_name(config: Option<&Path>, id: u32, kind: &str) -> String {
+    format!("{}{id}.{kind}", unit_prefix(config))
+}
+
+/// Returns the prefix of the unit names of the jobs in `config`: [`UNIT_PREFIX`] for
+/// the default configuration, followed by a short hash of the path of another one.
+fn unit_prefix(config: Option<&Path>) -> String {
+    match config {
+        None => UNIT_PREFIX.to_string(),
+        Some(path) => {
+            let hash = Sha256::digest(path.as_os_str().as_encoded_bytes());
+            let short: String = hash[..4].iter().map(|b| format!("{b:02x}")).collect();
+            format!("{UNIT_PREFIX}{short}-")
+        }
+    }
+}
+
+/// Writes (or with `print`, prints) the units of the selected scheduled jobs and
+/// enables their timers.
+pub(crate) fn install(ids: Option<Vec<JobRef>>, print: bool) -> Result<()> {
+    let exe = std::env::current_exe().context("failed to locate the bk executable")?;
+    let config = custom_config_file();
+    let units = select(ids)?
+        .iter()
+        .map(|job| Units::render(job, &exe, config))
+        .collect::<Result<Vec<_>>>()?;
+    if units.is_empty() {
+        println!("No scheduled jobs to install.");
+        return Ok(());
+    }
+
+    if print {
+        for (i, unit) in units.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            println!(
+                "# {}\n{}",
+                unit_name(config, unit.id, "service"),
+                unit.service
+            );
+            print!("# {}\n{}", unit_name(config, unit.id, "timer"), unit.timer);
+        }
+        return Ok(());
+    }
+
+    let dir = unit_dir()?;
+    fs::create_dir_all(&dir)?;
+    for unit in &units {
+        fs::write(
+            dir.join(unit_name(config, unit.id, "service")),
+            &unit.service,
+        )?;
+        fs::write(dir.join(unit_name(config, unit.id, "timer")), &unit.timer)?;
+        println!(
+            "Installed {}",
+            dir.join(unit_name(config, unit.id, "timer")).display()
+        );
+    }
+    if systemctl(&["daemon-reload"]) {
+        for unit in &units {
+            systemctl(&["enable", "--now", &unit_name(config, unit.id, "timer")]);
+        }
+    }
+    Ok(())
+}
+
+/// Disables and removes the units of the given jobs, or of every installed job.
+pub(crate) fn uninstall(ids: Option<Vec<JobRef>>) -> Result<()> {
+    let dir = unit_dir()?;
+    let config = custom_config_file();
+    let ids = match ids {
+        Some(ids) => resolve_ids(ids),
+        None => installed(&dir, config)?,
+    };
+    let mut removed = false;
+    for id in ids {
+        let service = dir.join(unit_name(config, id, "service"));
+        let timer = dir.join(unit_name(config, id, "timer"));
+        if !service.exists() && !timer.exists() {
+            eprintln!("No units installed for job {id}.");
+            continue;
+        }
+        systemctl(&["disable", "--now", &unit_name(config, id, "timer")]);
+        for path in [timer, service] {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        println!("Uninstalled units of job {id}");
+        removed = true;
+    }
+    if removed {
+        systemctl(&["daemon-reload"]);
+    }
+    Ok(())
+}
+
+/// Prints whether the units of the selected scheduled jobs are installed and current.
+pub(crate) fn status(ids: Option<Vec<JobRef>>) -> Result<()> {
+    let exe = std::env::current_exe().context("failed to locate the bk executable")?;
+    let dir = unit_dir()?;
+    let config = custom_config_file();
+    let jobs = select(ids)?;
+    if jobs.is_empty() {
+        println!("No scheduled jobs.");
+    }
+    for job in &jobs {
+        let units = Units::render(job, &exe, config)?;
+        let service = fs::read_to_string(dir.join(unit_name(config, job.id, "service"))).ok();
+        let timer = fs::read_to_string(dir.join(unit_name(config, job.id, "timer"))).ok();
+        let state = match (service, timer) {
+            (None, None) => "not installed".to_string(),
+            (Some(service), Some(timer)) if service == units.service && timer == units.timer => {
+                format!("installed ({})", timer_state(config, job.id))
+            }
+            _ => "outdated, run `bk schedule install` to update".to_string(),
+        };
+        println!("job {}: {state}", job.id);
+    }
+    // Units left behind by jobs that were deleted, disabled or lost their schedule.
+    for id in installed(&dir, config)? {
+        if !jobs.iter().any(|job| job.id == id) {
+            println!(
+                "job {id}: installed but not scheduled or disabled, run `bk schedule uninstall --id {id}`"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns the ids referenced by `ids`. Numeric ids are kept as they are, so the
+/// units of a job that was deleted since can still be removed.
+fn resolve_ids(ids: Vec<JobRef>) -> Vec<u32> {
+    let jobs = Application::get_jobs();
+    ids.into_iter()
+        .filter_map(|id| match id {
+            JobRef::Id(id) => Some(id),
+            JobRef::Name(_) => match jobs.iter().find(|job| id.matches(job)) {
+                Some(job) => Some(job.id),
+                None => {
+                    eprintln!("Job {id} not found.");
+                    None
+                }
+            },
+        })
+        .collect()
+}
+
+/// Returns the enabled scheduled jobs, restricted to those referenced by `ids`
+/// when given.
+fn select(ids: Option<Vec<JobRef>>) -> Result<Vec<Job>> {
+    let jobs = Application::get_jobs();
+    let Some(ids) = ids else {
+        return Ok(jobs
+            .into_iter()
+            .filter(|j| j.schedule.is_some() && j.is_enabled())
+            .collect());
+    };
+    let mut selected = vec![];
+    for id in ids {
+        match jobs.iter().find(|job| id.matches(job)) {
+            Some(job) if job.schedule.is_none() => eprintln!("Job {id} has no schedule."),
+            Some(job) if !job.is_enabled() => eprintln!("Job {id} is disabled."),
+            Some(job) => selected.push(job.clone()),
+            None => eprintln!("Job {id} not found."),
+        }
+    }
+    Ok(selected)
+}
+
+/// Returns the ids of the jobs of `config` whose timers are installed in `dir`.
+fn installed(dir: &Path, config: Option<&Path>) -> Result<Vec<u32>> {
+    let prefix = unit_prefix(config);
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(vec![]);
+    };
+    let mut ids = vec![];
+    for entry in entries {
+        let name = entry?.file_name();
+        let id = name
+            .to_str()
+            .and_then(|n| n.strip_prefix(prefix.as_str()))
+            .and_then(|n| n.strip_suffix(".timer"))
+            .and_then(|n| n.parse().ok());
+        if let Some(id) = id {
+            ids.push(id);
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Returns the activity of the timer of job `id` in `config` as reported by systemd.
+fn timer_state(config: Option<&Path>, id: u32) -> String {
+    Command::new("systemctl")
+        .args(["--user", "is-active", &unit_name(config, id, "timer")])
+        .output()
+        .ok()
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Runs `systemctl --user` with `args`, reporting failures without aborting.
+fn systemctl(args: &[&str]) -> bool {
+    let ok = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .is_ok_and(|status| status.success());
+    if !ok {
+        eprintln!("Warning: `systemctl --user {}` failed.", args.join(" "));
+    }
+    ok
+}
+
+/// Quotes a word for a systemd `ExecStart=` line.
+fn quote(word: &str) -> String {
+    let escaped = word.replace('%', "%%").replace('$', "$$");
+    if escaped.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduled_job(schedule: &str) -> Job {
+        Job {
+            id: 4,
+            source: vec![PathBuf::from("/src")],
+            target: vec![PathBuf::from("/dst")],
+            schedule: Some(schedule.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_cron_units() -> Result<()> {
+        let units = Units::render(&scheduled_job("@daily"), Path::new("/usr/bin/bk"), None)?;
+        assert!(
+            units
+                .service
+                .contains("ExecStart=/usr/bin/bk run -i 4 --skip-disabled\n")
+        );
+        assert!(units.timer.contains("OnCalendar=*-*-* 00:00:00\n"));
+        assert!(units.timer.contains("Persistent=true\n"));
+        assert!(units.timer.contains("Unit=hbackup-4.service\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_interval_units() -> Result<()> {
+        let units = Units::render(
+            &scheduled_job("every 6h"),
+            Path::new("/opt/my tools/bk"),
+            Some(Path::new("/home/me/work.toml")),
+        )?;
+        assert!(units.service.contains(
+            "ExecStart=\"/opt/my tools/bk\" --config /home/me/work.toml run -i 4 --skip-disabled\n"
+        ));
+        assert!(units.timer.contains("OnUnitActiveSec=21600s\n"));
+        assert!(!units.timer.contains("OnCalendar"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_profiles_install_side_by_side() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let dir = temp_dir.path();
+        let work = Path::new("/home/me/.config/hbackup/work.toml");
+        let home = Path::new("/home/me/.config/hbackup/home.toml");
+        let job = scheduled_job("@daily");
+        for config in [None, Some(work), Some(home)] {
+            let units = Units::render(&job, Path::new("/usr/bin/bk"), config)?;
+            let service = unit_name(config, job.id, "service");
+            assert!(units.timer.contains(&format!("Unit={service}\n")));
+            fs::write(dir.join(&service), &units.service)?;
+            fs::write(dir.join(unit_name(config, job.id, "timer")), &units.timer)?;
+        }
+
+        let names: Vec<_> = [None, Some(work), Some(home)]
+            .into_iter()
+            .map(|config| unit_name(config, 4, "timer"))
+            .collect();
+        assert_eq!(names[0], "hbackup-4.timer");
+        assert_ne!(names[1], names[2]);
+        assert_eq!(fs::read_dir(dir)?.count(), 6);
+        for config in [None, Some(work), Some(home)] {
+            assert_eq!(installed(dir, config)?, [4]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Path templates stored in jobs and expanded when a job runs.
+//!
+//! A job path may start with `~` for the home directory and contain environment
+//! variables (`$VAR` or `${VAR}`) and placeholders:
+//!
+//! - `{hostname}`: the name of the machine
+//! - `{date}`: the local date as `YYYY-MM-DD`
+//!
+//! `$$` stands for a literal `$` and `{{` for a literal `{`. A `$` that is not
+//! followed by a variable name and unknown `{...}` placeholders are kept as they are.
+use anyhow::{Context, Result, bail};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Returns `true` if `path` contains anything [`expand`] would replace.
+pub fn is_template(path: &Path) -> bool {
+    let s = path.to_string_lossy();
+    s == "~"
+        || s.starts_with("~/")
+        || s.starts_with("~\\")
+        || s.contains('$')
+        || s.contains("{{")
+        || s.contains("{hostname}")
+        || s.contains("{date}")
+}
+
+/// Expands the home directory, environment variables and placeholders in `path`.
+///
+/// # Errors
+/// Returns an error if a referenced environment variable is not set or the home
+/// directory cannot be determined.
+pub fn expand(path: &Path) -> Result<PathBuf> {
+    if !is_template(path) {
+        return Ok(path.to_path_buf());
+    }
+    let s = path.to_string_lossy();
+    let mut out = String::with_capacity(s.len());
+    let mut rest: &str = &s;
+
+    if rest == "~" || rest.starts_with("~/") || rest.starts_with("~\\") {
+        let home = dirs::home_dir().context("Couldn't get the home directory")?;
+        out.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+
+    while let Some(i) = rest.find(['$', '{']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let Some(end) = after.find('}') else {
+                bail!("unterminated variable in path template {path:?}");
+            };
+            out.push_str(&var(&after[..end])?);
+            rest = &after[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('$') {
+            let len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            if len == 0 {
+                out.push('$');
+            } else {
+                out.push_str(&var(&after[..len])?);
+            }
+            rest = &after[len..];
+        } else if let Some(after) = rest.strip_prefix("{{") {
+            out.push('{');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{hostname}") {
+            out.push_str(&hostname());
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{date}") {
+            out.push_str(&chrono::Local::now().format("%Y-%m-%d").to_string());
+            rest = after;
+        } else {
+            out.push('{');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(PathBuf::from(out))
+}
+
+/// Returns `path` with every `$` and `{` doubled, so that [`expand`] leaves it as
+/// it is.
+pub fn escape(path: &Path) -> PathBuf {
+    let s = path.to_string_lossy();
+    if !s.contains(['$', '{']) {
+        // Keeps paths that are not valid Unicode intact.
+        return path.to_path_buf();
+    }
+    PathBuf::from(s.replace('$', "$$").replace('{', "{{"))
+}
+
+fn var(name: &str) -> Result<String> {
+    env::var(name).with_context(|| format!("environment variable {name} is not set"))
+}
+
+/// Returns the name of this machine, or `localhost` if it cannot be determined.
+pub fn hostname() -> String {
+    let from_env = ["HOSTNAME", "COMPUTERNAME"]
+        .into_iter()
+        .find_map(|name| env::var(name).ok());
+    let from_file = || {
+        std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+            .ok()
+    };
+    let from_command = || {
+        Command::new("hostname")
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+    };
+    from_env
+        .or_else(from_file)
+        .or_else(from_command)
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() -> Result<()> {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand(Path::new("~/Documents"))?, home.join("Documents"));
+        assert_eq!(expand(Path::new("/data/~x"))?, PathBuf::from("/data/~x"));
+
+        let path = env::var("PATH")?;
+        assert_eq!(
+            expand(Path::new("${PATH}/a"))?,
+            PathBuf::from(format!("{path}/a"))
+        );
+        assert_eq!(
+            expand(Path::new("$PATH/a"))?,
+            PathBuf::from(format!("{path}/a"))
+        );
+        assert!(expand(Path::new("/$HBACKUP_TEST_UNSET_VAR/a")).is_err());
+
+        assert_eq!(expand(Path::new("/a/$$/b"))?, PathBuf::from("/a/$/b"));
+        assert_eq!(expand(Path::new("/a/$-/{x}"))?, PathBuf::from("/a/$-/{x}"));
+        let escaped = escape(Path::new("/a/$HOME"));
+        assert_eq!(expand(&escaped.join("$$"))?, PathBuf::from("/a/$HOME/$"));
+        for plain in [
+            "/a/{date}",
+            "/a/{{x}",
+            "/$RECYCLE.BIN/a$$b",
+            "/a/{hostname}$",
+        ] {
+            assert_eq!(expand(&escape(Path::new(plain)))?, PathBuf::from(plain));
+        }
+
+        let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+        let expanded = expand(Path::new("/b/{hostname}/{date}"))?;
+        assert_eq!(expanded, Path::new("/b").join(hostname()).join(date));
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_template() {
+        assert!(is_template(Path::new("~")));
+        assert!(is_template(Path::new("${BACKUP_ROOT}/x")));
+        assert!(is_template(Path::new("/b/{date}")));
+        assert!(is_template(Path::new("/b/{{x}")));
+        assert!(!is_template(Path::new("/home/user/{notes}")));
+        assert!(!is_template(Path::new("/home/user/~backup")));
+    }
+}
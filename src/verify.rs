@@ -0,0 +1,324 @@
+//! Verification and scrubbing of backups.
+//!
+//! `bk verify` decodes every archive a compressed job keeps in its targets and
+//! compares the files it holds with the manifest written next to it, or with the
+//! sources of the job if there is none, so a damaged or incomplete backup is noticed
+//! before it is needed. The copied tree of an uncompressed job is checked against
+//! its manifest.
+//!
+//! `bk scrub` re-reads every file of a backup to find silent corruption on aging
+//! disks, and can copy damaged files again from unchanged sources.
+//!
+//! `bk repair` reconstructs damaged archives from the recovery records written
+//! beside them.
+
+use crate::Result;
+use crate::application::Application;
+use crate::sysexits;
+use hbackup::cancel;
+use hbackup::integrity::{self, Outcome};
+use hbackup::job::{Job, JobRef};
+use hbackup::lock;
+use hbackup::manifest::Manifest;
+use hbackup::parity::{self, RepairReport};
+use hbackup::scrub::{self, ScrubOptions, ScrubReport};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+/// Verifies the backups of the jobs referenced by `ids`, or of all jobs, and prints
+/// the result of each target.
+///
+/// Exits with `EX_DATAERR` if a backup is missing, cannot be decoded or differs from
+/// what it should contain.
+pub(crate) fn run(ids: Option<Vec<JobRef>>, deep: bool) -> Result<()> {
+    let selected = select(ids);
+    if selected.is_empty() {
+        println!("No jobs to verify.");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for job in &selected {
+        print_job(job);
+        failed += verify_job(job, deep);
+    }
+    if failed > 0 {
+        println!("{failed} backup(s) failed verification");
+        process::exit(sysexits::EX_DATAERR);
+    }
+    Ok(())
+}
+
+/// Scrubs the backups of the jobs referenced by `ids`, or of all jobs, and prints
+/// the result of each target.
+///
+/// Exits with `EX_DATAERR` if damage is left in a backup.
+pub(crate) fn scrub(ids: Option<Vec<JobRef>>, opts: &ScrubOptions) -> Result<()> {
+    let selected = select(ids);
+    if selected.is_empty() {
+        println!("No jobs to scrub.");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for job in &selected {
+        print_job(job);
+        let job = match job.expanded() {
+            Ok(job) => job,
+            Err(e) => {
+                println!("    error: cannot expand the paths: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+        for target in &job.target {
+            let manifest = match Manifest::read(target, job.id) {
+                Ok(Some(manifest)) => manifest,
+                Ok(None) => {
+                    println!("    {}: skipped, no manifest", target.display());
+                    continue;
+                }
+                Err(e) => {
+                    println!("    {}: error: {e:#}", target.display());
+                    failed += 1;
+                    continue;
+                }
+            };
+            match scrub::scrub_target(&job, target, &manifest, opts) {
+                Ok(report) => {
+                    if !report.is_ok() {
+                        failed += 1;
+                    }
+                    print_scrub(target, &report);
+                }
+                Err(e) if cancel::is_cancellation(&e) => {
+                    println!(
+                        "    {}: interrupted, resume with --resume",
+                        target.display()
+                    );
+                    process::exit(sysexits::EX_TEMPFAIL);
+                }
+                Err(e) => {
+                    println!("    {}: error: {e:#}", target.display());
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        println!("{failed} backup(s) are damaged");
+        process::exit(sysexits::EX_DATAERR);
+    }
+    Ok(())
+}
+
+/// Repairs the archives of the jobs referenced by `ids`, or of all jobs, from their
+/// recovery records and prints the result of each target.
+///
+/// Exits with `EX_DATAERR` if an archive is missing or cannot be recovered.
+pub(crate) fn repair(ids: Option<Vec<JobRef>>, wait: Option<Duration>) -> Result<()> {
+    let selected = select(ids);
+    if selected.is_empty() {
+        println!("No jobs to repair.");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for job in &selected {
+        print_job(job);
+        let job = match job.expanded() {
+            Ok(job) => job,
+            Err(e) => {
+                println!("    error: cannot expand the paths: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+        if job.compression.is_none() {
+            println!("    skipped, not compressed");
+            continue;
+        }
+        for target in &job.target {
+            let Some(archive) = job.archive_path(target) else {
+                continue;
+            };
+            if !parity::parity_path(&archive).exists() {
+                println!("    {}: skipped, no recovery records", archive.display());
+                continue;
+            }
+            if !archive.exists() {
+                println!("    {}: missing", archive.display());
+                failed += 1;
+                continue;
+            }
+            let result = lock::acquire(&lock::target_lock_path(target), wait)
+                .and_then(|_lock| parity::repair(&archive));
+            match result {
+                Ok(report) => {
+                    if !report.is_ok() {
+                        failed += 1;
+                    }
+                    print_repair(&archive, &report);
+                }
+                Err(e) => {
+                    println!("    {}: error: {e:#}", archive.display());
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        println!("{failed} archive(s) could not be repaired");
+        process::exit(sysexits::EX_DATAERR);
+    }
+    Ok(())
+}
+
+/// Returns the jobs referenced by `ids`, or all jobs, reporting unknown references.
+fn select(ids: Option<Vec<JobRef>>) -> Vec<Job> {
+    let jobs = Application::get_jobs();
+    let Some(ids) = ids else {
+        return jobs;
+    };
+    for id in ids
+        .iter()
+        .filter(|id| !jobs.iter().any(|job| id.matches(job)))
+    {
+        eprintln!("Job {id} not found.");
+    }
+    jobs.into_iter()
+        .filter(|job| ids.iter().any(|id| id.matches(job)))
+        .collect()
+}
+
+/// Prints the line introducing the results of `job`.
+fn print_job(job: &Job) {
+    match &job.name {
+        Some(name) => println!("job {} ({name}):", job.id),
+        None => println!("job {}:", job.id),
+    }
+}
+
+/// Prints the result of scrubbing `target`.
+fn print_scrub(target: &Path, report: &ScrubReport) {
+    let mut summary = format!("{} files checked", report.checked);
+    if report.skipped > 0 {
+        summary += &format!(", {} skipped", report.skipped);
+    }
+    for (n, what) in [
+        (report.missing.len(), "missing"),
+        (report.corrupted.len(), "corrupted"),
+        (report.repaired.len(), "repaired"),
+    ] {
+        if n > 0 {
+            summary += &format!(", {n} {what}");
+        }
+    }
+    println!("    {}: {summary}", target.display());
+    let details = [
+        ("missing", &report.missing),
+        ("corrupted", &report.corrupted),
+        ("repaired", &report.repaired),
+    ];
+    for (what, paths) in details {
+        for path in paths {
+            println!("        {what}: {}", path.display());
+        }
+    }
+}
+
+/// Prints the result of repairing `archive`.
+fn print_repair(archive: &Path, report: &RepairReport) {
+    let mut summary = if !report.is_ok() {
+        format!(
+            "{} of {} blocks cannot be recovered",
+            report.unrecoverable_blocks, report.blocks
+        )
+    } else if report.repaired_shards > 0 {
+        format!(
+            "repaired, {} damaged shard(s) rebuilt",
+            report.repaired_shards
+        )
+    } else {
+        format!("ok, {} blocks intact", report.blocks)
+    };
+    if report.records_rewritten {
+        summary += ", recovery records rewritten";
+    }
+    println!("    {}: {summary}", archive.display());
+}
+
+/// Verifies the backup in each target of `job` and returns the number of failures.
+fn verify_job(job: &Job, deep: bool) -> usize {
+    let job = match job.expanded() {
+        Ok(job) => job,
+        Err(e) => {
+            println!("    error: cannot expand the paths: {e}");
+            return 1;
+        }
+    };
+    let mut failed = 0;
+    for target in &job.target {
+        let (path, result) = match job.archive_path(target) {
+            Some(archive) if !archive.exists() => {
+                println!("    {}: missing", archive.display());
+                failed += 1;
+                continue;
+            }
+            Some(archive) => {
+                let result = integrity::verify_archive(&job, &archive, deep);
+                (archive, result)
+            }
+            None => match Manifest::read(target, job.id) {
+                Ok(Some(manifest)) => {
+                    let result = integrity::verify_tree(target, &manifest, deep);
+                    (target.clone(), result)
+                }
+                Ok(None) => {
+                    println!("    {}: skipped, no manifest", target.display());
+                    continue;
+                }
+                Err(e) => (target.clone(), Err(e)),
+            },
+        };
+        if !report(&path, result) {
+            failed += 1;
+            if parity::parity_path(&path).exists() {
+                println!(
+                    "        recovery records found, try `bk repair -i {}`",
+                    job.id
+                );
+            }
+        }
+    }
+    failed
+}
+
+/// Prints the result of verifying the backup at `path` and returns whether it passed.
+fn report(path: &Path, result: Result<Outcome>) -> bool {
+    match result {
+        Ok(outcome) if outcome.is_ok() => {
+            println!("    {}: ok, {} files", path.display(), outcome.files);
+            true
+        }
+        Ok(outcome) => {
+            println!("    {}: {}", path.display(), outcome.summary());
+            let details = [
+                ("missing", &outcome.missing),
+                ("unexpected", &outcome.unexpected),
+                ("changed", &outcome.changed),
+            ];
+            for (what, paths) in details {
+                for path in paths {
+                    println!("        {what}: {}", path.display());
+                }
+            }
+            false
+        }
+        Err(e) => {
+            println!("    {}: error: {e:#}", path.display());
+            false
+        }
+    }
+}
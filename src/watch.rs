@@ -0,0 +1,152 @@
+//! Continuous backup on filesystem changes.
+//!
+//! `bk watch` runs every selected job once, then watches the job sources and, after
+//! changes have settled for the debounce period, only syncs the paths that changed.
+
+use crate::Result;
+use crate::application::Application;
+use crate::daemon::log;
+use hbackup::job::{Job, JobRef, RunOptions, run_job, sync_changes};
+use hbackup::{cancel, lock};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Interval at which cancellation is checked while waiting for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Watches the sources of the selected jobs until cancellation is requested.
+pub(crate) fn run(ids: Option<Vec<JobRef>>, debounce: Duration) -> Result<()> {
+    let jobs = select(ids);
+    if jobs.is_empty() {
+        println!("No jobs to watch.");
+        return Ok(());
+    }
+
+    // Paths are watched as expanded at start; each sync expands the templates again.
+    let expanded = jobs.iter().map(Job::expanded).collect::<Result<Vec<_>>>()?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for src in expanded.iter().flat_map(|job| &job.source) {
+        // A single file is watched through its directory, so editors that replace
+        // the file on save are still noticed.
+        let (path, mode) = match src.parent() {
+            Some(parent) if src.is_file() => (parent, RecursiveMode::NonRecursive),
+            _ => (src.as_path(), RecursiveMode::Recursive),
+        };
+        watcher.watch(path, mode)?;
+    }
+
+    // Start from an up-to-date target, so later runs only have to sync changes.
+    for job in &jobs {
+        report(job, run_job(job, &RunOptions::default()));
+    }
+    log(&format!("watching {} job(s) for changes", jobs.len()));
+
+    let mut pending: Vec<BTreeSet<PathBuf>> = vec![BTreeSet::new(); jobs.len()];
+    let mut last_event = Instant::now();
+    while !cancel::is_cancelled() {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+                for path in event.paths {
+                    for (i, job) in expanded.iter().enumerate() {
+                        if is_relevant(job, &path) {
+                            pending[i].insert(path.clone());
+                            last_event = Instant::now();
+                        }
+                    }
+                }
+            }
+            Ok(Err(e)) => eprintln!("Watch error: {e}"),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_event.elapsed() < debounce {
+            continue;
+        }
+        for (job, changed) in jobs.iter().zip(pending.iter_mut()) {
+            if changed.is_empty() || cancel::is_cancelled() {
+                continue;
+            }
+            let paths: Vec<_> = changed.iter().cloned().collect();
+            log(&format!("job {}: {} path(s) changed", job.id, paths.len()));
+            match sync_changes(job, &paths) {
+                // Keep the changes for the next attempt while another run holds the lock.
+                Err(e) if lock::is_contention(&e) => {
+                    log(&format!(
+                        "job {}: waiting for another run to finish",
+                        job.id
+                    ));
+                    last_event = Instant::now();
+                }
+                result => {
+                    changed.clear();
+                    report(job, result);
+                }
+            }
+        }
+    }
+    log("watch stopped");
+    Ok(())
+}
+
+/// Returns the jobs to watch: the jobs referenced by `ids`, or all enabled jobs.
+fn select(ids: Option<Vec<JobRef>>) -> Vec<Job> {
+    let jobs = Application::get_jobs();
+    let Some(ids) = ids else {
+        return jobs.into_iter().filter(Job::is_enabled).collect();
+    };
+    let mut selected = vec![];
+    for id in ids {
+        match jobs.iter().find(|job| id.matches(job)) {
+            Some(job) => selected.push(job.clone()),
+            None => eprintln!("Job {id} not found."),
+        }
+    }
+    selected
+}
+
+/// Returns `true` if a change of `path` affects the backup of `job`.
+fn is_relevant(job: &Job, path: &Path) -> bool {
+    // Writes to a target inside the source must not trigger another sync.
+    job.source.iter().any(|src| path.starts_with(src))
+        && !job.target.iter().any(|target| path.starts_with(target))
+}
+
+fn report(job: &Job, result: Result<()>) {
+    match result {
+        Ok(()) => log(&format!("job {}: success", job.id)),
+        Err(e) if cancel::is_cancellation(&e) => log(&format!("job {}: cancelled", job.id)),
+        Err(e) => log(&format!("job {}: failed ({e})", job.id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_relevant() {
+        let job = Job {
+            source: vec![
+                PathBuf::from("/data/docs"),
+                PathBuf::from("/data/notes.txt"),
+            ],
+            target: vec![PathBuf::from("/data/docs/.backup")],
+            ..Default::default()
+        };
+        assert!(is_relevant(&job, Path::new("/data/docs/a.txt")));
+        assert!(!is_relevant(
+            &job,
+            Path::new("/data/docs/.backup/docs/a.txt")
+        ));
+        assert!(is_relevant(&job, Path::new("/data/notes.txt")));
+        assert!(!is_relevant(&job, Path::new("/data/other.txt")));
+    }
+}
@@ -0,0 +1,36 @@
+//! Cancellation sets a process-wide flag, so these tests live in their own binary.
+#![cfg(unix)]
+
+use hbackup::cancel;
+use hbackup::job::{Job, RunOptions, run_job};
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+#[test]
+fn test_cancelled_run_stops_running_hook() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let src = temp.path().join("docs");
+    fs::create_dir_all(&src)?;
+    let dest = temp.path().join("back");
+    let marker = temp.path().join("marker");
+    let job = Job {
+        pre_hook: Some(format!("sleep 5; touch '{}'", marker.display())),
+        ..Job::temp_job(vec![src], vec![dest.clone()], None, None, None, None)
+    };
+
+    let canceller = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(300));
+        cancel::cancel();
+    });
+    let start = Instant::now();
+    let err = run_job(&job, &RunOptions::default()).unwrap_err();
+    canceller.join().unwrap();
+
+    assert!(cancel::is_cancellation(&err), "{err:#}");
+    assert!(start.elapsed() < Duration::from_secs(4));
+    assert!(!marker.exists());
+    assert!(!dest.exists());
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Cancellation sets a process-wide flag, so these tests live in their own binary.
+
+use hbackup::cancel;
+use hbackup::file_util;
+use hbackup::job::{BackupModel, CompressFormat, Job, Level, RunOptions, run_job};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn test_cancelled_run_leaves_no_partial_backup() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let src = temp.path().join("docs");
+    fs::create_dir_all(&src)?;
+    for i in 0..10 {
+        fs::write(src.join(format!("{i}.txt")), "content")?;
+    }
+    let dest = temp.path().join("back");
+    fs::create_dir_all(&dest)?;
+    let entries = file_util::scan(&src, None)?;
+    let archive = file_util::archive_path(&src, &dest, &CompressFormat::Gzip);
+    fs::write(&archive, "previous")?;
+
+    cancel::cancel();
+
+    // The archive started before the cancellation is removed again, and the
+    // previous archive is kept.
+    let err = file_util::compress_entries(
+        &src,
+        &entries,
+        &dest,
+        &CompressFormat::Gzip,
+        &Level::Fastest,
+    )
+    .unwrap_err();
+    assert!(cancel::is_cancellation(&err));
+    assert!(!file_util::partial_path(&archive).exists());
+    assert_eq!(fs::read_to_string(&archive)?, "previous");
+
+    // No item of a job is started once cancellation was requested.
+    let job = Job::temp_job(
+        vec![src.clone()],
+        vec![dest.clone()],
+        None,
+        None,
+        None,
+        Some(BackupModel::Mirror),
+    );
+    let err = run_job(&job, &RunOptions::default()).unwrap_err();
+    assert!(cancel::is_cancellation(&err));
+    assert!(!dest.join("docs").exists());
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_check_reports_problems() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .args(["add", "--name", "docs"])
+        .arg(&src)
+        .arg(&tgt)
+        .args(["--ignore", "node_modules"]);
+    add.assert().success();
+
+    let mut check = Command::new(bin);
+    check.env("XDG_CONFIG_HOME", temp.path()).arg("check");
+    check
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("job 1 (docs):"))
+        .stdout(predicate::str::contains(
+            "warning: ignore entry \"node_modules\" matches nothing",
+        ))
+        .stdout(predicate::str::contains("0 error(s), 1 warning(s)"));
+
+    // A source that went away is fatal.
+    std::fs::remove_dir(&src)?;
+    let mut check = Command::new(bin);
+    check
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["check", "--id", "docs"]);
+    check
+        .assert()
+        .code(78)
+        .stdout(predicate::str::contains("error: source"))
+        .stdout(predicate::str::contains("does not exist"));
+    Ok(())
+}
+
+#[test]
+fn cli_add_target_inside_source() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let home = temp.path().join("home");
+    let backups = home.join("backups");
+    std::fs::create_dir_all(&backups)?;
+
+    let mut same = Command::new(bin);
+    same.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&home)
+        .arg(&home);
+    same.assert()
+        .failure()
+        .stderr(predicate::str::contains("is also a source"));
+
+    let mut nested = Command::new(bin);
+    nested
+        .env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&home)
+        .arg(&backups);
+    nested
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("left out of the backup"));
+
+    let mut check = Command::new(bin);
+    check.env("XDG_CONFIG_HOME", temp.path()).arg("check");
+    check
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("warning: target"));
+    Ok(())
+}
+
+#[test]
+fn cli_add_overlapping_sources() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let docs = temp.path().join("docs");
+    let inner = docs.join("inner");
+    std::fs::create_dir_all(&inner)?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&docs)
+        .arg(&inner)
+        .arg(&tgt)
+        .args(["-c", "tar"]);
+    add.assert()
+        .failure()
+        .stderr(predicate::str::contains("is inside source"));
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&inner)
+        .arg(&tgt);
+    add.assert().success();
+    let mut edit = Command::new(bin);
+    edit.env("XDG_CONFIG_HOME", temp.path())
+        .args(["edit", "1", "--add-source"])
+        .arg(&docs);
+    edit.assert()
+        .failure()
+        .stderr(predicate::str::contains("is inside source"));
+    Ok(())
+}
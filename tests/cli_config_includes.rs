@@ -0,0 +1,91 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_config_show_resolved() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let config_dir = temp.path().join("hbackup");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(
+        config_dir.join("config.toml"),
+        r#"version = "1.3"
+include = ["team.toml"]
+
+[[jobs]]
+id = 1
+source = ["/home"]
+target = ["/backup"]
+
+[host."laptop-01".jobs.photos]
+target = ["/media/usb"]
+"#,
+    )?;
+    std::fs::write(
+        config_dir.join("team.toml"),
+        "[[jobs]]\nid = 2\nname = \"photos\"\nsource = [\"/photos\"]\ntarget = [\"/nas\"]\n",
+    )?;
+
+    let mut show = Command::new(bin);
+    show.env("XDG_CONFIG_HOME", temp.path())
+        .env("HOSTNAME", "laptop-01")
+        .args(["config", "show", "--resolved"]);
+    show.assert()
+        .success()
+        .stdout(predicate::str::contains("\"/photos\""))
+        .stdout(predicate::str::contains("\"/media/usb\""))
+        .stdout(predicate::str::contains("include").not());
+
+    // Included jobs run like any other, with the overrides of another host ignored.
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path())
+        .env("HOSTNAME", "desktop")
+        .arg("list");
+    list.assert()
+        .success()
+        .stdout(predicate::str::contains("/nas"));
+
+    // Adding a job keeps the includes and skips the ids of included jobs.
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&src)
+        .arg(&tgt);
+    add.assert().success();
+    let config = std::fs::read_to_string(config_dir.join("config.toml"))?;
+    assert!(config.contains("include = [\"team.toml\"]"));
+    assert!(config.contains("id = 3"));
+    assert!(!config.contains("\"/photos\""));
+
+    // Included jobs are changed in their own file, and their names stay unique.
+    let mut edit = Command::new(bin);
+    edit.env("XDG_CONFIG_HOME", temp.path())
+        .args(["edit", "photos", "--name", "pictures"]);
+    edit.assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "defined in {}",
+            config_dir.join("team.toml").display()
+        )));
+    let mut disable = Command::new(bin);
+    disable
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["disable", "2"]);
+    disable
+        .assert()
+        .stderr(predicate::str::contains("must be changed there"));
+    let mut rename = Command::new(bin);
+    rename
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["edit", "3", "--name", "photos"]);
+    rename.assert().failure().stderr(predicate::str::contains(
+        "a job named \"photos\" already exists",
+    ));
+    Ok(())
+}
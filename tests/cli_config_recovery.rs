@@ -0,0 +1,56 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_recovers_damaged_config_from_backup() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let config_dir = temp.path().join("hbackup");
+
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+    // Two writes leave a backup holding the first job.
+    for _ in 0..2 {
+        let mut add = Command::new(bin);
+        add.env("XDG_CONFIG_HOME", temp.path())
+            .arg("add")
+            .arg(src.as_os_str())
+            .arg(tgt.as_os_str());
+        add.assert().success();
+    }
+    assert!(config_dir.join("config.toml.bak").exists());
+
+    // Simulate a crash that left a half-written configuration.
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "version = \"1.0\"\n[[jobs]\n",
+    )?;
+
+    let mut declined = assert_cmd::Command::new(bin);
+    declined
+        .env("XDG_CONFIG_HOME", temp.path())
+        .arg("list")
+        .write_stdin("n\n");
+    declined
+        .assert()
+        .code(78)
+        .stderr(predicate::str::contains("Error reading config file"));
+
+    let mut restored = assert_cmd::Command::new(bin);
+    restored
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["list", "--format", "json"])
+        .write_stdin("y\n");
+    restored
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Configuration restored"))
+        .stdout(predicate::str::contains("\"id\": 1"));
+    assert!(config_dir.join("config.toml.corrupt").exists());
+
+    Ok(())
+}
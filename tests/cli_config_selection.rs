@@ -0,0 +1,72 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_select_config_file() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+    let work = temp.path().join("work").join("jobs.toml");
+
+    // A job added with --config ends up in that file only.
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .env_remove("HBACKUP_CONFIG")
+        .arg("--config")
+        .arg(&work)
+        .arg("add")
+        .arg(&src)
+        .arg(&tgt);
+    add.assert().success();
+    assert!(std::fs::read_to_string(&work)?.contains("id = 1"));
+
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path())
+        .env_remove("HBACKUP_CONFIG")
+        .arg("list");
+    list.assert()
+        .success()
+        .stdout(predicate::str::contains("docs").not());
+
+    // HBACKUP_CONFIG selects the same file.
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path())
+        .env("HBACKUP_CONFIG", &work)
+        .arg("list");
+    list.assert()
+        .success()
+        .stdout(predicate::str::contains("docs"));
+
+    // A profile lives next to the default configuration file.
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .env("HBACKUP_CONFIG", &work)
+        .args(["--profile", "personal", "add"])
+        .arg(&src)
+        .arg(&tgt);
+    add.assert().success();
+    let profile = temp.path().join("hbackup").join("personal.toml");
+    assert!(std::fs::read_to_string(&profile)?.contains("id = 1"));
+
+    let mut config = Command::new(bin);
+    config
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["config", "--profile", "personal"]);
+    config
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(profile.to_string_lossy()));
+
+    let mut both = Command::new(bin);
+    both.env("XDG_CONFIG_HOME", temp.path())
+        .args(["--profile", "personal", "--config"])
+        .arg(&work)
+        .arg("list");
+    both.assert().failure();
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use assert_fs::TempDir;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn cli_daemon_does_not_prompt_for_a_damaged_config() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+
+    let config_dir = temp.path().join("hbackup");
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::write(config_dir.join("config.toml"), "jobs = [")?;
+    // A usable backup would make an interactive load ask to restore it.
+    std::fs::write(
+        config_dir.join("config.toml.bak"),
+        "version = \"1.4\"\njobs = []\n",
+    )?;
+
+    let mut daemon = Command::new(bin)
+        .env("XDG_CONFIG_HOME", temp.path())
+        .arg("daemon")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    thread::sleep(Duration::from_millis(1500));
+    let running = daemon.try_wait()?.is_none();
+    daemon.kill()?;
+    daemon.wait()?;
+
+    let mut stdout = String::new();
+    daemon.stdout.take().unwrap().read_to_string(&mut stdout)?;
+    let mut stderr = String::new();
+    daemon.stderr.take().unwrap().read_to_string(&mut stderr)?;
+    assert!(running, "{stdout}{stderr}");
+    assert!(stdout.contains("cannot read"), "{stdout}");
+    assert!(!stderr.contains("Restore the configuration"), "{stderr}");
+    Ok(())
+}
@@ -37,7 +37,7 @@ fn cli_delete_all_yes_end_to_end() -> anyhow::Result<()> {
     list.env("XDG_CONFIG_HOME", temp.path()).arg("list");
     list.assert()
         .success()
-        .stdout(predicate::str::contains("id:"));
+        .stdout(predicate::str::contains("SOURCE"));
 
     // Run the bk binary with delete --all -y
     let mut cmd = Command::new(bin);
@@ -55,7 +55,7 @@ fn cli_delete_all_yes_end_to_end() -> anyhow::Result<()> {
     list2
         .assert()
         .success()
-        .stdout(predicate::str::contains("id:").not());
+        .stdout(predicate::str::contains("SOURCE").not());
 
     Ok(())
 }
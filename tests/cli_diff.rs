@@ -0,0 +1,62 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_diff_lists_changes_since_backup() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join("a.txt"), "a")?;
+    std::fs::write(src.join("b.txt"), "b")?;
+    let target = temp.path().join("backup");
+    std::fs::create_dir_all(&target)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&src)
+        .arg(&target)
+        .args(["--model", "mirror", "--name", "docs"]);
+    add.assert().success();
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path()).arg("run");
+    run.assert().success();
+
+    let mut diff = Command::new(bin);
+    diff.env("XDG_CONFIG_HOME", temp.path())
+        .args(["diff", "docs"]);
+    diff.assert()
+        .success()
+        .stdout(predicate::str::contains("no differences"));
+
+    std::fs::write(src.join("a.txt"), "changed")?;
+    std::fs::remove_file(src.join("b.txt"))?;
+    std::fs::write(src.join("c.txt"), "c")?;
+    let mut diff = Command::new(bin);
+    diff.env("XDG_CONFIG_HOME", temp.path()).args(["diff", "1"]);
+    diff.assert().success().stdout(
+        predicate::str::contains("1 added, 1 removed, 1 modified")
+            .and(predicate::str::contains("added: docs/c.txt"))
+            .and(predicate::str::contains("removed: docs/b.txt"))
+            .and(predicate::str::contains("modified: docs/a.txt")),
+    );
+
+    let mut diff = Command::new(bin);
+    diff.env("XDG_CONFIG_HOME", temp.path())
+        .args(["diff", "docs", "--checksum", "--json"]);
+    let output = diff.assert().success().get_output().stdout.clone();
+    let json: serde_json::Value = serde_json::from_slice(&output)?;
+    assert_eq!(json["job"], 1);
+    assert_eq!(json["targets"][0]["added"][0], "docs/c.txt");
+    assert_eq!(json["targets"][0]["modified"][0], "docs/a.txt");
+
+    let mut diff = Command::new(bin);
+    diff.env("XDG_CONFIG_HOME", temp.path()).args(["diff", "9"]);
+    diff.assert()
+        .code(64)
+        .stderr(predicate::str::contains("Job with id 9 not found."));
+    Ok(())
+}
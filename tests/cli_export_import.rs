@@ -0,0 +1,103 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_export_and_import_jobs() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+    let other = temp.path().join("other.toml");
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .args(["add", "--name", "docs"])
+        .arg(&src)
+        .arg(&tgt);
+    add.assert().success();
+
+    let mut export = Command::new(bin);
+    export
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["export", "--format", "json"]);
+    let output = export.assert().success().get_output().stdout.clone();
+    let exported = temp.path().join("jobs.json");
+    std::fs::write(&exported, &output)?;
+    assert!(String::from_utf8(output)?.contains("\"name\": \"docs\""));
+
+    // Into an empty configuration the job keeps its id ...
+    let mut import = Command::new(bin);
+    import
+        .arg("--config")
+        .arg(&other)
+        .arg("import")
+        .arg(&exported);
+    import
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Imported job 1."));
+
+    // ... and a clashing job is renamed or remapped.
+    let mut import = Command::new(bin);
+    import
+        .arg("--config")
+        .arg(&other)
+        .arg("import")
+        .arg(&exported);
+    import.assert().failure().stderr(predicate::str::contains(
+        "a job named \"docs\" already exists",
+    ));
+
+    let json = std::fs::read_to_string(&exported)?.replace("\"docs\"", "\"docs-2\"");
+    std::fs::write(&exported, json)?;
+    let mut import = Command::new(bin);
+    import
+        .arg("--config")
+        .arg(&other)
+        .arg("import")
+        .arg(&exported);
+    import
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Imported job 1 as job 2."));
+
+    let mut replace = Command::new(bin);
+    replace
+        .arg("--config")
+        .arg(&other)
+        .args(["import", "--replace"])
+        .arg(&exported);
+    replace.assert().success();
+    let config = std::fs::read_to_string(&other)?;
+    assert!(config.contains("id = 1") && !config.contains("id = 2"));
+
+    // Relative paths cannot be imported.
+    let yaml = temp.path().join("jobs.yaml");
+    std::fs::write(
+        &yaml,
+        "jobs:\n- id: 1\n  source: [docs]\n  target: [/backup]\n",
+    )?;
+    let mut import = Command::new(bin);
+    import.arg("--config").arg(&other).arg("import").arg(&yaml);
+    import
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not absolute"));
+
+    // Neither can a job whose target contains one of its sources.
+    std::fs::write(
+        &yaml,
+        "jobs:\n- id: 1\n  source: [/backup/docs]\n  target: [/backup]\n",
+    )?;
+    let mut import = Command::new(bin);
+    import.arg("--config").arg(&other).arg("import").arg(&yaml);
+    import
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("contains source"));
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_select_jobs_by_name_and_tag() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+    for (name, tags) in [
+        ("docs", "nightly"),
+        ("photos", "nightly,usb"),
+        ("music", "usb"),
+    ] {
+        let src = temp.path().join(name);
+        std::fs::create_dir_all(&src)?;
+        std::fs::write(src.join("a.txt"), name)?;
+        let mut add = Command::new(bin);
+        add.env("XDG_CONFIG_HOME", temp.path())
+            .arg("add")
+            .arg(src.as_os_str())
+            .arg(tgt.as_os_str())
+            .args(["--name", name, "--tags", tags]);
+        add.assert().success();
+    }
+
+    // Names are unique.
+    let mut dup = Command::new(bin);
+    dup.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(temp.path().join("docs").as_os_str())
+        .arg(tgt.as_os_str())
+        .args(["--name", "docs"]);
+    dup.assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path())
+        .args(["list", "--tag", "usb", "--format", "json"]);
+    list.assert()
+        .success()
+        .stdout(predicate::str::contains("\"name\": \"photos\""))
+        .stdout(predicate::str::contains("\"name\": \"music\""))
+        .stdout(predicate::str::contains("\"name\": \"docs\"").not());
+
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path())
+        .args(["run", "--tag", "nightly"]);
+    run.assert().success();
+    assert!(tgt.join("docs").join("a.txt").exists());
+    assert!(tgt.join("photos").join("a.txt").exists());
+    assert!(!tgt.join("music").exists());
+
+    let mut edit = Command::new(bin);
+    edit.env("XDG_CONFIG_HOME", temp.path())
+        .args(["edit", "music", "--name", "songs"]);
+    edit.assert().success();
+
+    let mut delete = Command::new(bin);
+    delete
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["delete", "songs,1"]);
+    delete
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Job named \"songs\" deleted successfully.",
+        ))
+        .stdout(predicate::str::contains(
+            "Job with id 1 deleted successfully.",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn cli_list_filters_and_formats() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let docs = temp.path().join("docs");
+    let photos = temp.path().join("photos");
+    std::fs::create_dir_all(&docs)?;
+    std::fs::create_dir_all(&photos)?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&docs)
+        .arg(&tgt)
+        .args(["-c", "gzip"]);
+    add.assert().success();
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&photos)
+        .arg(&tgt)
+        .args(["-m", "mirror"]);
+    add.assert().success();
+
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path())
+        .args(["list", "--source", "photos", "--format", "csv"]);
+    list.assert()
+        .success()
+        .stdout(predicate::str::starts_with("id,name,"))
+        .stdout(predicate::str::contains("photos"))
+        .stdout(predicate::str::contains("docs").not());
+
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path()).args([
+        "list",
+        "--model",
+        "full",
+        "--compression",
+        "gzip",
+    ]);
+    list.assert()
+        .success()
+        .stdout(predicate::str::contains("docs"))
+        .stdout(predicate::str::contains("photos").not());
+
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path()).args([
+        "list",
+        "--target",
+        "no-such-target",
+        "--format",
+        "json",
+    ]);
+    list.assert().success().stdout("[]\n");
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_expand_path_templates_at_run_time() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join("a.txt"), "a")?;
+    let root = temp.path().join("backups");
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .env("HB_SRC", &src)
+        .env("HB_ROOT", &root)
+        .args(["add", "$HB_SRC", "${HB_ROOT}/{date}"]);
+    add.assert().success();
+
+    // A variable that is not set is reported when the job is added.
+    let mut unset = Command::new(bin);
+    unset
+        .env("XDG_CONFIG_HOME", temp.path())
+        .env_remove("HB_UNSET")
+        .args(["add", "$HB_UNSET/docs", "/tmp"]);
+    unset
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("HB_UNSET is not set"));
+
+    // The job keeps the templates ...
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path()).arg("list");
+    list.assert()
+        .success()
+        .stdout(predicate::str::contains("$HB_SRC"))
+        .stdout(predicate::str::contains("${HB_ROOT}/{date}"));
+
+    // ... and expands them with the environment of each run.
+    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let target = root.join(&date);
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path())
+        .env("HB_SRC", &src)
+        .env("HB_ROOT", &root)
+        .args(["list", "--expanded"]);
+    list.assert()
+        .success()
+        .stdout(predicate::str::contains(target.to_string_lossy()));
+
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path())
+        .env("HB_SRC", &src)
+        .env("HB_ROOT", &root)
+        .arg("run");
+    run.assert().success();
+    assert!(target.join("docs").join("a.txt").exists());
+
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path())
+        .env("HB_SRC", &src)
+        .env_remove("HB_ROOT")
+        .arg("run");
+    run.assert()
+        .failure()
+        .stderr(predicate::str::contains("HB_ROOT is not set"));
+    // A relative template is anchored at the current directory.
+    let mut relative = Command::new(bin);
+    relative
+        .env("XDG_CONFIG_HOME", temp.path())
+        .env("HB_ROOT", &root)
+        .current_dir(temp.path())
+        .args(["add", "docs-{date}", "$HB_ROOT", "--name", "relative"]);
+
+    relative.assert().success();
+    let mut list = Command::new(bin);
+    list.env("XDG_CONFIG_HOME", temp.path())
+        .args(["list", "--id", "relative"]);
+    list.assert().success().stdout(predicate::str::contains(
+        temp.path().join("docs-{date}").to_string_lossy(),
+    ));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn cli_escape_resolved_paths() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+
+    // A link that resolves to a path that reads as a template.
+    let src = temp.path().join("$work-{date}");
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join("a.txt"), "a")?;
+    let link = temp.path().join("work");
+    std::os::unix::fs::symlink(&src, &link)?;
+    let target = temp.path().join("back");
+    std::fs::create_dir_all(&target)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .env_remove("work")
+        .arg("add")
+        .arg(&link)
+        .arg(&target);
+    add.assert().success();
+
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path())
+        .env_remove("work")
+        .arg("run");
+    run.assert().success();
+    assert!(target.join("$work-{date}").join("a.txt").exists());
+    Ok(())
+}
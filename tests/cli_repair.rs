@@ -0,0 +1,72 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_repair_reconstructs_damaged_archive() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    let text: String = (0..2000).map(|i| format!("line {i}\n")).collect();
+    std::fs::write(src.join("a.txt"), &text)?;
+    let target = temp.path().join("backup");
+    std::fs::create_dir_all(&target)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&src)
+        .arg(&target)
+        .args(["--compression", "gzip", "--parity", "10"]);
+    add.assert().success();
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path()).arg("run");
+    run.assert().success();
+
+    let archive = target.join("docs.tar.gz");
+    let par = target.join("docs.tar.gz.par");
+    assert!(par.exists());
+
+    let mut repair = Command::new(bin);
+    repair.env("XDG_CONFIG_HOME", temp.path()).arg("repair");
+    repair
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ok, 1 blocks intact"));
+
+    // A damaged sector in the middle of the archive.
+    let mut bytes = std::fs::read(&archive)?;
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 0xff;
+    std::fs::write(&archive, &bytes)?;
+    let mut verify = Command::new(bin);
+    verify.env("XDG_CONFIG_HOME", temp.path()).arg("verify");
+    verify
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("try `bk repair -i 1`"));
+
+    let mut repair = Command::new(bin);
+    repair
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["repair", "--id", "1"]);
+    repair.assert().success().stdout(predicate::str::contains(
+        "repaired, 1 damaged shard(s) rebuilt",
+    ));
+    let mut verify = Command::new(bin);
+    verify.env("XDG_CONFIG_HOME", temp.path()).arg("verify");
+    verify.assert().success();
+
+    // Without parity, the next run removes the stale records.
+    let mut edit = Command::new(bin);
+    edit.env("XDG_CONFIG_HOME", temp.path())
+        .args(["edit", "1", "--clear", "parity"]);
+    edit.assert().success();
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path()).arg("run");
+    run.assert().success();
+    assert!(!par.exists());
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_run_one_time_directory_backup() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(src.join("nested"))?;
+    std::fs::write(src.join("a.txt"), b"hello")?;
+    std::fs::write(src.join("nested").join("b.txt"), b"world")?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path())
+        .arg("run")
+        .arg(src.as_os_str())
+        .arg(tgt.as_os_str());
+    run.assert().success();
+
+    assert_eq!(std::fs::read(tgt.join("docs").join("a.txt"))?, b"hello");
+    assert_eq!(
+        std::fs::read(tgt.join("docs").join("nested").join("b.txt"))?,
+        b"world"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn cli_run_rejects_target_over_source() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let config_dir = temp.path().join("hbackup");
+    std::fs::create_dir_all(&config_dir)?;
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join("a.txt"), b"hello")?;
+
+    // A hand-edited configuration bypasses the checks of `bk add`.
+    std::fs::write(
+        config_dir.join("config.toml"),
+        format!(
+            "version = \"1.3\"\n\n[[jobs]]\nid = 1\nsource = [{src:?}]\ntarget = [{src:?}]\n\n\
+             [[jobs]]\nid = 2\nsource = [{src:?}]\ntarget = [{root:?}]\n",
+            src = src.to_string_lossy(),
+            root = temp.path().to_string_lossy(),
+        ),
+    )?;
+
+    for (id, error) in [("1", "is also a source"), ("2", "contains source")] {
+        let mut run = Command::new(bin);
+        run.env("XDG_CONFIG_HOME", temp.path())
+            .args(["run", "-i", id]);
+        run.assert()
+            .failure()
+            .stderr(predicate::str::contains(error));
+    }
+    assert_eq!(std::fs::read(src.join("a.txt"))?, b"hello");
+    Ok(())
+}
+
+#[test]
+fn cli_run_reports_each_target() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join("a.txt"), b"hello")?;
+    let local = temp.path().join("local");
+    std::fs::create_dir_all(&local)?;
+    let usb = temp.path().join("usb");
+    std::fs::create_dir_all(&usb)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&src)
+        .arg(&local)
+        .arg("--add-target")
+        .arg(&usb);
+    add.assert().success();
+
+    // The second target turns into a file, where a directory is expected.
+    std::fs::remove_dir(&usb)?;
+    std::fs::write(&usb, "not a directory")?;
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path())
+        .args(["run", "-i", "1"]);
+    run.assert()
+        .code(74)
+        .stdout(predicate::str::contains(format!(
+            "{}: success",
+            local.display()
+        )))
+        .stdout(predicate::str::contains(format!(
+            "{}: failed",
+            usb.display()
+        )));
+    assert_eq!(std::fs::read(local.join("docs").join("a.txt"))?, b"hello");
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_schedule_install_print() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(src.as_os_str())
+        .arg(tgt.as_os_str())
+        .args(["--schedule", "30 2 * * *", "--name", "docs"]);
+    add.assert().success();
+
+    let mut install = Command::new(bin);
+    install
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["schedule", "install", "--print"]);
+    install
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("# hbackup-1.service"))
+        .stdout(predicate::str::contains("run -i 1"))
+        .stdout(predicate::str::contains("OnCalendar=*-*-* 02:30:00"));
+
+    // Printing must not write any unit files.
+    assert!(!temp.path().join("systemd").exists());
+
+    let mut status = Command::new(bin);
+    status
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["schedule", "status"]);
+    status
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("job 1: not installed"));
+
+    // Jobs can be selected by name, too.
+    let mut install = Command::new(bin);
+    install.env("XDG_CONFIG_HOME", temp.path()).args([
+        "schedule",
+        "install",
+        "--print",
+        "--id",
+        "docs,photos",
+    ]);
+    install
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("# hbackup-1.service"))
+        .stderr(predicate::str::contains("Job named \"photos\" not found."));
+
+    Ok(())
+}
+
+#[test]
+fn cli_schedule_skips_disabled_jobs() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join("a.txt"), "a")?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(src.as_os_str())
+        .arg(tgt.as_os_str())
+        .args(["--schedule", "@daily"]);
+    add.assert().success();
+    let mut disable = Command::new(bin);
+    disable
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["disable", "1"]);
+    disable.assert().success();
+
+    let mut install = Command::new(bin);
+    install
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["schedule", "install", "--print"]);
+    install
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No scheduled jobs to install."));
+
+    let mut install = Command::new(bin);
+    install
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["schedule", "install", "--print", "--id", "1"]);
+    install
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Job with id 1 is disabled."));
+
+    // Units installed before the job was disabled no longer run it.
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path())
+        .args(["run", "-i", "1", "--skip-disabled"]);
+    run.assert().success();
+    assert!(!tgt.join("docs").exists());
+
+    Ok(())
+}
+
+#[test]
+fn cli_schedule_profiles_do_not_collide() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    let tgt = temp.path().join("back");
+    std::fs::create_dir_all(&tgt)?;
+
+    let mut services = vec![];
+    for profile in ["work", "home"] {
+        let mut add = Command::new(bin);
+        add.env("XDG_CONFIG_HOME", temp.path())
+            .args(["--profile", profile, "add"])
+            .arg(&src)
+            .arg(&tgt)
+            .args(["--schedule", "@daily"]);
+        add.assert().success();
+
+        let mut install = Command::new(bin);
+        install.env("XDG_CONFIG_HOME", temp.path()).args([
+            "--profile",
+            profile,
+            "schedule",
+            "install",
+            "--print",
+        ]);
+        let output = install.output()?;
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout)?;
+        let service = stdout
+            .lines()
+            .find_map(|line| line.strip_prefix("# "))
+            .filter(|name| name.ends_with("-1.service"))
+            .map(str::to_string);
+        assert!(
+            stdout.contains(&format!("{profile}.toml run -i 1")),
+            "{stdout}"
+        );
+        services.push(service.expect("no service unit printed"));
+    }
+    assert_ne!(services[0], services[1]);
+    assert!(!services.contains(&"hbackup-1.service".to_string()));
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_scrub_finds_and_repairs_corruption() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join("a.txt"), "a")?;
+    std::fs::write(src.join("b.txt"), "b")?;
+    let target = temp.path().join("backup");
+    std::fs::create_dir_all(&target)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&src)
+        .arg(&target)
+        .args(["--model", "mirror"]);
+    add.assert().success();
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path()).arg("run");
+    run.assert().success();
+
+    let mut scrub = Command::new(bin);
+    scrub
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["scrub", "--max-rate", "1M"]);
+    scrub
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 files checked"));
+
+    // A flipped byte keeps size and modification time, so a mirror run would not notice.
+    std::fs::write(target.join("docs").join("a.txt"), "x")?;
+    let mut scrub = Command::new(bin);
+    scrub.env("XDG_CONFIG_HOME", temp.path()).arg("scrub");
+    scrub
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("corrupted: docs/a.txt"));
+
+    let mut scrub = Command::new(bin);
+    scrub
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["scrub", "--id", "1", "--repair"]);
+    scrub
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("repaired: docs/a.txt"));
+    assert_eq!(
+        std::fs::read_to_string(target.join("docs").join("a.txt"))?,
+        "a"
+    );
+
+    let mut scrub = Command::new(bin);
+    scrub
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["scrub", "--max-rate", "fast"]);
+    scrub
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid rate"));
+    Ok(())
+}
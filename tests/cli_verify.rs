@@ -0,0 +1,118 @@
+use assert_cmd::prelude::*;
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::process::Command;
+
+#[test]
+fn cli_verify_archives() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join("a.txt"), "a")?;
+    let target = temp.path().join("backup");
+    std::fs::create_dir_all(&target)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&src)
+        .arg(&target)
+        .args(["--compression", "zstd", "--verify"]);
+    add.assert().success();
+
+    // Nothing has been written yet.
+    let mut verify = Command::new(bin);
+    verify.env("XDG_CONFIG_HOME", temp.path()).arg("verify");
+    verify
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("docs.tar.zst: missing"));
+
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path()).arg("run");
+    run.assert().success();
+
+    let mut verify = Command::new(bin);
+    verify
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["verify", "--deep"]);
+    verify
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("docs.tar.zst: ok, 1 files"));
+
+    // The archive is compared with its manifest, not with the current sources ...
+    std::fs::write(src.join("b.txt"), "b")?;
+    let mut verify = Command::new(bin);
+    verify.env("XDG_CONFIG_HOME", temp.path()).arg("verify");
+    verify.assert().success();
+
+    // ... unless there is no manifest.
+    std::fs::remove_file(target.join(".hbackup-manifest-1.json"))?;
+    let mut verify = Command::new(bin);
+    verify
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["verify", "--id", "1"]);
+    verify
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("1 missing"))
+        .stdout(predicate::str::contains("missing: docs/b.txt"));
+
+    // A damaged archive cannot be decoded.
+    let archive = target.join("docs.tar.zst");
+    let data = std::fs::read(&archive)?;
+    std::fs::write(&archive, &data[..data.len() - 8])?;
+    let mut verify = Command::new(bin);
+    verify.env("XDG_CONFIG_HOME", temp.path()).arg("verify");
+    verify
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("error: cannot decode"));
+    Ok(())
+}
+
+#[test]
+fn cli_verify_copied_tree_against_manifest() -> anyhow::Result<()> {
+    let temp = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin!("bk");
+    let src = temp.path().join("docs");
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join("a.txt"), "a")?;
+    let target = temp.path().join("backup");
+    std::fs::create_dir_all(&target)?;
+
+    let mut add = Command::new(bin);
+    add.env("XDG_CONFIG_HOME", temp.path())
+        .arg("add")
+        .arg(&src)
+        .arg(&target);
+    add.assert().success();
+    let mut run = Command::new(bin);
+    run.env("XDG_CONFIG_HOME", temp.path()).arg("run");
+    run.assert().success();
+
+    let manifest = std::fs::read_to_string(target.join(".hbackup-manifest-1.json"))?;
+    assert!(manifest.contains("\"path\": \"docs/a.txt\""), "{manifest}");
+    let sums = std::fs::read_to_string(target.join(".hbackup-manifest-1.sha256"))?;
+    assert_eq!(
+        sums,
+        "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb  docs/a.txt\n"
+    );
+
+    // Same size, different content: only a deep verification notices.
+    std::fs::write(target.join("docs").join("a.txt"), "b")?;
+    let mut verify = Command::new(bin);
+    verify.env("XDG_CONFIG_HOME", temp.path()).arg("verify");
+    verify.assert().success();
+    let mut verify = Command::new(bin);
+    verify
+        .env("XDG_CONFIG_HOME", temp.path())
+        .args(["verify", "--deep"]);
+    verify
+        .assert()
+        .code(65)
+        .stdout(predicate::str::contains("changed: docs/a.txt"));
+    Ok(())
+}
@@ -39,7 +39,7 @@ fn delete_yes_end_to_end() -> anyhow::Result<()> {
     list.env("XDG_CONFIG_HOME", temp.path()).arg("list");
     list.assert()
         .success()
-        .stdout(predicate::str::contains("id:"));
+        .stdout(predicate::str::contains("SOURCE"));
 
     // Run the bk binary with delete --all -y
     let mut cmd = Command::new(bin);
@@ -57,7 +57,7 @@ fn delete_yes_end_to_end() -> anyhow::Result<()> {
     list2
         .assert()
         .success()
-        .stdout(predicate::str::contains("id:").not());
+        .stdout(predicate::str::contains("SOURCE").not());
 
     Ok(())
 }
@@ -531,3 +531,114 @@ fn test_compression_creates_destination_directory() {
     let compressed_file = dest.join(format!("{}.gz", filename));
     assert!(compressed_file.exists());
 }
+
+#[test]
+fn test_archive_path_matches_written_archive() {
+    let src = tempdir().unwrap();
+    let file1_path = src.path().join("file1.txt");
+    fs::write(&file1_path, "content of file 1").unwrap();
+
+    let dest = tempdir().unwrap();
+    let formats = [
+        CompressFormat::Gzip,
+        CompressFormat::Zip,
+        CompressFormat::Sevenz,
+        CompressFormat::Zstd,
+        CompressFormat::Bzip2,
+        CompressFormat::Xz,
+        CompressFormat::Lz4,
+        CompressFormat::Tar,
+    ];
+    for format in &formats {
+        file_util::compression(src.path(), dest.path(), format, &Level::Fastest, None).unwrap();
+        assert!(file_util::archive_path(src.path(), dest.path(), format).exists());
+
+        file_util::compression(&file1_path, dest.path(), format, &Level::Fastest, None).unwrap();
+        assert!(file_util::archive_path(&file1_path, dest.path(), format).exists());
+    }
+}
+
+#[test]
+fn test_top_level_names_are_unique() {
+    let sources = [
+        Path::new("/home/a/docs").to_path_buf(),
+        Path::new("/home/b/docs").to_path_buf(),
+        Path::new("/home/a/notes.txt").to_path_buf(),
+        Path::new("/home/b/notes.txt").to_path_buf(),
+        Path::new("/home/a/.bashrc").to_path_buf(),
+        Path::new("/home/b/.bashrc").to_path_buf(),
+    ];
+    let names = file_util::top_level_names(&sources).unwrap();
+    assert_eq!(
+        names,
+        [
+            "docs",
+            "docs-2",
+            "notes.txt",
+            "notes-2.txt",
+            ".bashrc",
+            ".bashrc-2"
+        ]
+    );
+    assert!(file_util::top_level_names(&[Path::new("/").to_path_buf()]).is_err());
+}
+
+#[test]
+fn test_compress_sources_into_one_archive() {
+    let root = tempdir().unwrap();
+    let docs = root.path().join("docs");
+    fs::create_dir_all(docs.join("sub")).unwrap();
+    fs::write(docs.join("sub/a.txt"), "a").unwrap();
+    let other = root.path().join("other");
+    fs::create_dir_all(other.join("docs")).unwrap();
+    fs::write(other.join("docs/b.txt"), "b").unwrap();
+    let rc = root.path().join(".bashrc");
+    fs::write(&rc, "rc").unwrap();
+
+    let sources = vec![docs.clone(), other.join("docs"), rc.clone()];
+    let mut entries = vec![];
+    for src in &sources {
+        entries.extend(file_util::scan(src, None).unwrap());
+    }
+    let dest = tempdir().unwrap();
+
+    for format in [CompressFormat::Tar, CompressFormat::Zip] {
+        file_util::compress_sources(
+            &sources,
+            &entries,
+            dest.path(),
+            "home",
+            &format,
+            &Level::Fastest,
+        )
+        .unwrap();
+        let archive = file_util::sources_archive_path(&sources, "home", dest.path(), &format);
+        let names: Vec<String> = match format {
+            CompressFormat::Tar => {
+                let mut tar = tar::Archive::new(fs::File::open(&archive).unwrap());
+                tar.entries()
+                    .unwrap()
+                    .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+                    .collect()
+            }
+            _ => {
+                let zip = zip::ZipArchive::new(fs::File::open(&archive).unwrap()).unwrap();
+                zip.file_names().map(str::to_string).collect()
+            }
+        };
+        let mut names: Vec<_> = names.iter().map(|n| n.trim_end_matches('/')).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                ".bashrc",
+                "docs",
+                "docs-2",
+                "docs-2/b.txt",
+                "docs/sub",
+                "docs/sub/a.txt"
+            ],
+            "{format:?}"
+        );
+    }
+}